@@ -7,6 +7,14 @@ import os
 directory = "./filesystem/"
 disk_img = "./disk/disk2.img"
 
+# Swap space of the kernel, after the sectors of the filesystem (see src/memory/swap.rs)
+SWAP_START_SECTOR = 1 << 15
+SWAP_PAGES = 4096
+
+# Directories the kernel writes into, created empty if the filesystem directory has none
+# (git does not keep empty directories). `cores` holds the core files, see src/scheduler/process/coredump.rs
+EMPTY_DIRECTORIES = ["cores"]
+
 def construct_filesystem_tree(path):
     files = []
     name = "root" # give a name
@@ -29,11 +37,17 @@ def write_ustar(data, disk_img_path):
     file = open(disk_img_path, "wb")
     binary_data = bytearray(data)
     file.write(binary_data)
+    # Leaves room for the swap, the kernel sizes it from the size of the disk
+    file.truncate(max(len(binary_data), SWAP_START_SECTOR * 512 + SWAP_PAGES * 4096))
     file.close() 
 
 # Main function
 def build_filesystem(fs_path, disk_img_path):
     tree = construct_filesystem_tree(fs_path)
+    names = [child.header.name for child in tree.files]
+    for name in EMPTY_DIRECTORIES:
+        if name not in names:
+            tree.add_file(Dir([], name))
     build_ustar(tree)
     data = USTAR.get_data()
     print(sum(data), 512 * 32)
@@ -61,7 +61,7 @@ class Header:
         data[32:36] = [(self.nb_bloc >> (i * 8)) & 255 for i in range(0, 4)] # nb blocs
         data[36:36+SHORT_MODE_LIMIT*4] = perm(self.block_addresses)
         assert(SHORT_MODE_LIMIT == 100)
-        data[436:438] = [0, 0] # flags
+        data[436:438] = [0b1010_1110, 0b1010_0000] # flags : rwxr-xr-x, see HeaderFlags
         data[438:439] = [int(self.size > BLOCK_SIZE * SHORT_MODE_LIMIT)] # mode
         data[439:471] = [ord(i) for i in self.name] + [0] * (32 - len(self.name)) # name
         data[471:472] = [self.type] # file type
@@ -3,9 +3,32 @@
 use super::{align_up, Locked};
 
 use crate::errorln;
+use crate::memory;
 use core::alloc::{GlobalAlloc, Layout};
 use core::mem;
 use core::ptr;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Least number of pages added when the heap grows
+const GROWTH_PAGES: usize = 16;
+
+/// Bytes mapped by a level 4 entry, the heap can not grow out of its own
+const LEVEL_4_SIZE: usize = 1 << 39;
+
+/// Frames of the page tables of the heap, taken like its pages, see `add_page`
+struct HeapFrames;
+
+unsafe impl FrameAllocator<Size4KiB> for HeapFrames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        memory::allocate_frame().map(PhysFrame::containing_address)
+    }
+}
 
 /// Implements the structure of a linked list.
 ///
@@ -72,16 +95,28 @@ impl ListNode {
 
 /// Implements the structure of a memory allocator based on a linked list
 ///
-/// It holds a single value `head` which is the first `ListNode` of the associated linked list.
+/// It holds `head`, the first `ListNode` of the associated linked list, and the bounds of the heap.
+/// When no region is large enough, the heap grows by mapping new pages at its end, see `add_page`.
 #[derive(Debug)]
 pub struct LinkedListAllocator {
     head: ListNode,
+    heap_start: usize,
+    /// End of the pages mapped
+    heap_end: usize,
+    /// The heap does not grow past this address
+    max_end: usize,
+    /// Bytes handed out
+    used: usize,
 }
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
         Self {
             head: ListNode::new(0, true),
+            heap_start: 0,
+            heap_end: 0,
+            max_end: 0,
+            used: 0,
         }
     }
     /// Adds a free region to the allocator. It works by placing a new `ListNode` at the front of the allocator with the given size.
@@ -123,13 +158,83 @@ impl LinkedListAllocator {
         // If we arrive here, we simply need to append the new_region
         (*node_ptr).next = None;
         current.next = Some(&mut *node_ptr);
+        // The pages added at the end of the heap extend the last region
+        current.merge_partial(1);
     }
     /// # Safety
     /// TODO
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize, max_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.set_max_size(max_size);
         self.add_free_region(heap_start, heap_size)
     }
 
+    /// Sets the size the heap can not grow past. It never shrinks below the pages already mapped,
+    /// and never grows past the level 4 entry of the heap: the next one is shared by every address space.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        let level_4_end = (self.heap_start | (LEVEL_4_SIZE - 1)) + 1;
+        self.max_end = self
+            .heap_start
+            .saturating_add(max_size)
+            .min(level_4_end)
+            .max(self.heap_end);
+    }
+
+    /// Returns the size of the heap and the number of bytes handed out
+    pub fn usage(&self) -> (usize, usize) {
+        (self.heap_end - self.heap_start, self.used)
+    }
+
+    /// Returns the size the heap can not grow past
+    pub fn max_size(&self) -> usize {
+        self.max_end - self.heap_start
+    }
+
+    /// Maps a new page at the end of the heap, with a frame of `memory::allocate_frame`, and adds it to the free regions.
+    /// Returns false if the heap reached its maximal size or if there is no frame left.
+    /// The heap has its own level 4 entry, shared by every address space, so the page is mapped everywhere.
+    /// The frames are not taken through `FRAME_ALLOCATOR`, which the code that allocates may be borrowing.
+    /// # Safety
+    /// The heap must be initialised
+    pub unsafe fn add_page(&mut self) -> bool {
+        if self.heap_end + PAGE_SIZE > self.max_end {
+            return false;
+        }
+        let frame = match memory::allocate_frame() {
+            Some(address) => PhysFrame::<Size4KiB>::containing_address(address),
+            None => return false,
+        };
+        let (level_4, _) = Cr3::read();
+        let table =
+            &mut *((level_4.start_address().as_u64() + memory::PHYSICAL_OFFSET) as *mut PageTable);
+        let mut mapper = OffsetPageTable::new(table, VirtAddr::new(memory::PHYSICAL_OFFSET));
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(self.heap_end as u64));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        match mapper.map_to(page, frame, flags, &mut HeapFrames) {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                memory::deallocate_frame(frame.start_address());
+                return false;
+            }
+        }
+        self.add_free_region(self.heap_end, PAGE_SIZE);
+        self.heap_end += PAGE_SIZE;
+        true
+    }
+
+    /// Adds enough pages for a region of `size` bytes aligned on `align`, at least `GROWTH_PAGES`.
+    /// Returns false if the heap could not grow that much.
+    unsafe fn grow(&mut self, size: usize, align: usize) -> bool {
+        let pages = ((size + align + PAGE_SIZE - 1) / PAGE_SIZE).max(GROWTH_PAGES);
+        for _ in 0..pages {
+            if !self.add_page() {
+                return false;
+            }
+        }
+        true
+    }
+
     /// Find the first free region in the allocator that has a size at least equal to the requested one.
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
         let mut current = &mut self.head;
@@ -174,18 +279,27 @@ impl LinkedListAllocator {
     }
 }
 
-unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+impl LinkedListAllocator {
+    /// Returns a region for `layout`, or a null pointer if there is none large enough
+    /// even after growing the heap.
+    /// # Safety
+    /// The allocator must have been initialised
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
         // perform layout adjustments
         let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
+        let found = match self.find_region(size, align) {
+            Some(found) => Some(found),
+            None if self.grow(size, align) => self.find_region(size, align),
+            None => None,
+        };
+        if let Some((region, alloc_start)) = found {
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
             let excess_size = region.end_addr() - alloc_end;
             if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
+                self.add_free_region(alloc_end, excess_size);
             }
+            self.used += size;
             alloc_start as *mut u8
         } else {
             errorln!("Could not find memory region");
@@ -193,15 +307,24 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    /// Gives back a region returned by `allocate` with the same `layout`
+    /// # Safety
+    /// `ptr` must come from `allocate`
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
         // perform layout adjustments
         let (size, _) = LinkedListAllocator::size_align(layout);
 
-        self.lock().add_free_region(ptr as usize, size)
+        self.used -= size;
+        self.add_free_region(ptr as usize, size)
     }
 }
 
-impl Locked<LinkedListAllocator> {
-    #[allow(dead_code)]
-    fn add_page(&self) {}
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
 }
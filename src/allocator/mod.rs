@@ -1,7 +1,7 @@
 //! Heap allocator. This is a clone of the one inside the `librust`
 
 //use alloc::alloc::{GlobalAlloc, Layout};
-use linked_list::LinkedListAllocator;
+use slab::SlabAllocator;
 use x86_64::{
     addr::VirtAddr,
     structures::paging::{
@@ -11,22 +11,52 @@ use x86_64::{
 //use core::ptr::null_mut;
 //Will be removed in favor of a custom allocator in the future
 pub mod linked_list;
+pub mod slab;
 
 /// The start adress of the kernel heap.
 pub const HEAP_START: usize = 0x4444_4444_0000;
 
-/// The size of the kernel heap. It is for now pretty small.
-pub const HEAP_SIZE: usize = 100000 * 1024;
+/// The size of the kernel heap when it is mapped, it then grows when it is full.
+pub const HEAP_SIZE: usize = 16 * 1024 * 1024;
 
-/// Handles any allocation error.
+/// The default size the kernel heap can not grow past, see `set_max_size`.
+pub const HEAP_MAX_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Size and usage of the kernel heap, in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// Bytes mapped
+    pub size: usize,
+    /// Bytes handed out, the slabs of the slab caches included
+    pub used: usize,
+    /// Size the heap can not grow past
+    pub max_size: usize,
+}
+
+/// Handles any allocation error, the heap could not grow enough.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("Allocation error : {:?}", layout)
+    panic!("Allocation error : {:?}, heap : {:?}", layout, heap_stats())
 }
 
-/// Main allocator
+/// Main allocator, the slab caches falling back to a linked list
 #[global_allocator]
-static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+static ALLOCATOR: Locked<SlabAllocator> = Locked::new(SlabAllocator::new());
+
+/// Returns the statistics of the slab caches
+pub fn slab_stats() -> [slab::CacheStats; slab::CACHES] {
+    ALLOCATOR.lock().stats()
+}
+
+/// Returns the size and usage of the kernel heap
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().heap_stats()
+}
+
+/// Sets the size the kernel heap can not grow past, root does it with the syscall `set_heap_max`
+pub fn set_max_size(max_size: usize) {
+    ALLOCATOR.lock().set_max_size(max_size)
+}
 
 /// Initializes the Allocator
 pub fn init(
@@ -51,7 +81,7 @@ pub fn init(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE, HEAP_MAX_SIZE);
     }
 
     Ok(())
@@ -0,0 +1,214 @@
+//! Slab caches for the small objects of the kernel heap.
+//!
+//! Each cache hands out objects of a single size, cut from slabs of `SLAB_SIZE` bytes taken from the
+//! linked-list allocator. The free objects of a cache form a linked list threaded through them,
+//! so allocating and freeing one does not walk anything.
+//! The sizes that do not fit a cache go to the linked-list allocator directly.
+
+use super::linked_list::LinkedListAllocator;
+use super::{HeapStats, Locked};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Size of the slabs taken from the linked-list allocator
+const SLAB_SIZE: usize = 4096;
+
+/// Alignment of the slabs, objects needing more go to the linked-list allocator
+const SLAB_ALIGN: usize = 16;
+
+/// Size of the objects of each cache
+const CACHE_SIZES: [usize; CACHES] = [16, 32, 64, 128, 256, 512, 1024];
+
+/// Number of caches
+pub const CACHES: usize = 7;
+
+/// Statistics of a cache, in objects
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub object_size: usize,
+    pub in_use: usize,
+    pub free: usize,
+    pub total: usize,
+    pub slabs: usize,
+}
+
+struct SlabCache {
+    object_size: usize,
+    /// First free object, null if there is none
+    free_list: *mut usize,
+    stats: CacheStats,
+}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            free_list: ptr::null_mut(),
+            stats: CacheStats {
+                object_size,
+                in_use: 0,
+                free: 0,
+                total: 0,
+                slabs: 0,
+            },
+        }
+    }
+
+    /// Cuts a new slab into free objects. Returns false if the fallback allocator has no room for it.
+    unsafe fn grow(&mut self, fallback: &mut LinkedListAllocator) -> bool {
+        let slab = fallback.allocate(Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_ALIGN));
+        if slab.is_null() {
+            return false;
+        }
+        let objects = SLAB_SIZE / self.object_size;
+        for i in (0..objects).rev() {
+            let object = slab.add(i * self.object_size) as *mut usize;
+            *object = self.free_list as usize;
+            self.free_list = object;
+        }
+        self.stats.slabs += 1;
+        self.stats.total += objects;
+        self.stats.free += objects;
+        true
+    }
+
+    unsafe fn allocate(&mut self, fallback: &mut LinkedListAllocator) -> *mut u8 {
+        if self.free_list.is_null() && !self.grow(fallback) {
+            return ptr::null_mut();
+        }
+        let object = self.free_list;
+        self.free_list = *object as *mut usize;
+        self.stats.free -= 1;
+        self.stats.in_use += 1;
+        object as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, object: *mut u8) {
+        let object = object as *mut usize;
+        *object = self.free_list as usize;
+        self.free_list = object;
+        self.stats.free += 1;
+        self.stats.in_use -= 1;
+    }
+}
+
+/// Allocator of the kernel heap: the slab caches, backed by the linked-list allocator
+pub struct SlabAllocator {
+    caches: [SlabCache; CACHES],
+    fallback: LinkedListAllocator,
+}
+
+// The free lists only point into the heap, which is only used under the lock
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        Self {
+            caches: [
+                SlabCache::new(CACHE_SIZES[0]),
+                SlabCache::new(CACHE_SIZES[1]),
+                SlabCache::new(CACHE_SIZES[2]),
+                SlabCache::new(CACHE_SIZES[3]),
+                SlabCache::new(CACHE_SIZES[4]),
+                SlabCache::new(CACHE_SIZES[5]),
+                SlabCache::new(CACHE_SIZES[6]),
+            ],
+            fallback: LinkedListAllocator::new(),
+        }
+    }
+
+    /// # Safety
+    /// The heap must be mapped and unused
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize, max_size: usize) {
+        self.fallback.init(heap_start, heap_size, max_size)
+    }
+
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.fallback.set_max_size(max_size)
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        let (size, used) = self.fallback.usage();
+        HeapStats {
+            size,
+            used,
+            max_size: self.fallback.max_size(),
+        }
+    }
+
+    /// Returns the index of the cache serving `layout`, None if it goes to the linked-list allocator
+    fn cache_index(layout: &Layout) -> Option<usize> {
+        if layout.align() > SLAB_ALIGN {
+            return None;
+        }
+        CACHE_SIZES.iter().position(|size| layout.size() <= *size)
+    }
+
+    /// Returns the statistics of every cache
+    pub fn stats(&self) -> [CacheStats; CACHES] {
+        let mut stats = [CacheStats::default(); CACHES];
+        for (stat, cache) in stats.iter_mut().zip(self.caches.iter()) {
+            *stat = cache.stats;
+        }
+        stats
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let allocator = &mut *allocator;
+        match SlabAllocator::cache_index(&layout) {
+            Some(index) => allocator.caches[index].allocate(&mut allocator.fallback),
+            None => allocator.fallback.allocate(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        let allocator = &mut *allocator;
+        match SlabAllocator::cache_index(&layout) {
+            Some(index) => allocator.caches[index].deallocate(ptr),
+            None => allocator.fallback.deallocate(ptr, layout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::slab_stats;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn small_objects_come_from_their_cache() {
+        let before = slab_stats()[0];
+        let object = Box::new(0_u64);
+        assert_eq!(slab_stats()[0].in_use, before.in_use + 1);
+        drop(object);
+        assert_eq!(slab_stats()[0].in_use, before.in_use);
+    }
+
+    #[test_case]
+    fn freed_objects_are_reused() {
+        let first = Box::new([0_u8; 100]);
+        let address = &*first as *const [u8; 100] as usize;
+        drop(first);
+        let second = Box::new([1_u8; 100]);
+        assert_eq!(&*second as *const [u8; 100] as usize, address);
+    }
+
+    #[test_case]
+    fn a_cache_grows_by_whole_slabs() {
+        let index = SlabAllocator::cache_index(&Layout::new::<[u8; 512]>()).unwrap();
+        let before = slab_stats()[index];
+        let objects: Vec<Box<[u8; 512]>> =
+            (0..before.free + 1).map(|_| Box::new([0; 512])).collect();
+        let after = slab_stats()[index];
+        assert_eq!(after.slabs, before.slabs + 1);
+        assert_eq!(after.total, before.total + SLAB_SIZE / 512);
+        drop(objects);
+        assert_eq!(slab_stats()[index].in_use, before.in_use);
+    }
+}
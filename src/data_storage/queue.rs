@@ -1,7 +1,11 @@
-//! Here we define a basic bounded queue (fixed size) supporting creation, push and pop
+//! Here we define a basic queue supporting creation, push and pop.
+//! Its capacity grows when needed, up to an optional maximum size.
 
-/// Maximam size of a queue
-const MAX_SIZE: usize = 32;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+
+/// Capacity of a queue the first time it grows
+const MIN_CAPACITY: usize = 8;
 
 /// Queue error.
 ///
@@ -16,26 +20,33 @@ pub enum Error {
     Underflow,
 }
 
-/// Bounded queue abstraction
+/// Growable queue abstraction
 ///
-/// It has O(1) `push` and `pop` operations
+/// It has O(1) `pop` and amortized O(1) `push` operations
 pub struct Queue<T> {
-    data: [Option<T>; MAX_SIZE],
+    data: Vec<Option<T>>,
     pushing: usize, // the next element will be put in data[min]
     poping: usize,  // the next element to pop is in data[max]
     empty: bool,    // to distinguish empty from full
+    max_size: usize,
 }
 impl<T> Queue<T>
 where
     Option<T>: Copy,
 {
-    /// Returns a new bounded queue, freshly initialized
+    /// Returns a new unbounded queue, freshly initialized
     pub const fn new() -> Self {
+        Self::bounded(usize::MAX)
+    }
+
+    /// Returns a new queue that can hold at most `max_size` elements
+    pub const fn bounded(max_size: usize) -> Self {
         Queue {
-            data: [None; MAX_SIZE],
+            data: Vec::new(),
             pushing: 0,
             poping: 0,
             empty: true,
+            max_size,
         }
     }
 
@@ -44,21 +55,49 @@ where
         self.empty
     }
 
+    pub fn len(&self) -> usize {
+        //! Returns the number of elements in the queue
+        if self.empty {
+            0
+        } else if self.pushing > self.poping {
+            self.pushing - self.poping
+        } else {
+            self.pushing + self.data.len() - self.poping
+        }
+    }
+
     pub fn is_full(&self) -> bool {
-        //! Returns true iff the queue is full
-        !self.is_empty() && self.poping == self.pushing
+        //! Returns true iff the queue reached its maximum size
+        self.len() >= self.max_size
+    }
+
+    /// Moves the elements into a bigger buffer
+    fn grow(&mut self) {
+        let new_capacity = min(max(MIN_CAPACITY, 2 * self.data.len()), self.max_size);
+        let mut data = Vec::with_capacity(new_capacity);
+        while let Ok(elt) = self.pop() {
+            data.push(Some(elt));
+        }
+        let length = data.len();
+        data.resize(new_capacity, None);
+        self.data = data;
+        self.poping = 0;
+        self.pushing = length % new_capacity;
+        self.empty = length == 0;
     }
 
     pub fn push(&mut self, elt: T) -> Result<(), Error> {
         //! Adds `elt` to the queue. Will return `Err(Overflow)` if the queue is full
         if self.is_full() {
-            Err(Error::Overflow)
-        } else {
-            self.data[self.pushing] = Some(elt);
-            self.pushing = (self.pushing + 1) % MAX_SIZE;
-            self.empty = false;
-            Ok(())
+            return Err(Error::Overflow);
+        }
+        if self.data.is_empty() || (!self.empty && self.poping == self.pushing) {
+            self.grow();
         }
+        self.data[self.pushing] = Some(elt);
+        self.pushing = (self.pushing + 1) % self.data.len();
+        self.empty = false;
+        Ok(())
     }
 
     pub fn pop(&mut self) -> Result<T, Error> {
@@ -68,7 +107,7 @@ where
         } else {
             let res = self.data[self.poping].unwrap();
             self.data[self.poping] = None;
-            self.poping = (self.poping + 1) % MAX_SIZE;
+            self.poping = (self.poping + 1) % self.data.len();
             if self.poping == self.pushing {
                 self.empty = true;
             }
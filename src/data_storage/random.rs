@@ -1,8 +1,13 @@
-//! A very basic pseudo-RNG
+//! A very basic pseudo-RNG, and a random source good enough for the address space layout randomisation
+
+use x86_64::instructions::random::RdRand;
 
 /// Initital seed
 static mut RAND_SEED: u8 = 42_u8;
 
+/// State of the generator used when the processor has no `rdrand`, 0 until it is seeded
+static mut XORSHIFT_STATE: u64 = 0;
+
 /// Returns a pseudo-random `u8`, using a simple and naive algorihm.
 /// We could use the CPU's built-in pRNG instructions.
 ///
@@ -21,3 +26,21 @@ pub fn random_u8() -> u8 {
         RAND_SEED
     }
 }
+
+/// Returns a random `u64` from the `rdrand` instruction of the processor.
+/// Without it, a xorshift generator seeded with the time stamp counter is used:
+/// it is not as good, but it still changes from one boot to another.
+pub fn random_u64() -> u64 {
+    if let Some(value) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        return value;
+    }
+    unsafe {
+        if XORSHIFT_STATE == 0 {
+            XORSHIFT_STATE = core::arch::x86_64::_rdtsc() | 1;
+        }
+        XORSHIFT_STATE ^= XORSHIFT_STATE << 13;
+        XORSHIFT_STATE ^= XORSHIFT_STATE >> 7;
+        XORSHIFT_STATE ^= XORSHIFT_STATE << 17;
+        XORSHIFT_STATE
+    }
+}
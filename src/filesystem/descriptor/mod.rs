@@ -2,25 +2,27 @@
 
 use super::fsflags::OpenFlags;
 use crate::data_storage::path::Path;
-use crate::scheduler::process;
+use crate::scheduler::process::{self, limits::Rlimit};
+use crate::scheduler::wait_queue::{self, Resource};
 
+use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::vec::Vec;
 
 pub struct FileDesciptorError();
 
 /// Max number of total opened files
-const MAX_TOTAL_OPEN_FILES: usize = 256;
-
-/// Max number of openable files by a process
-const MAX_TOTAL_OPEN_FILES_BY_PROCESS: usize = 16;
+const MAX_TOTAL_OPEN_FILES: usize = 4096;
 
 pub static mut GLOBAL_FILE_TABLE: GeneralFileTable = GeneralFileTable::new();
 
 /// Contains all the open_file_tables
 #[derive(Clone, Debug)]
 pub struct GeneralFileTable {
-    /// Array that maps a fdindex to a OpenFileTable (ie all the relevant metadata on the given file)
-    tables: [Option<OpenFileTable>; MAX_TOTAL_OPEN_FILES as usize],
+    /// Maps a fdindex to a OpenFileTable (ie all the relevant metadata on the given file).
+    /// It grows up to `MAX_TOTAL_OPEN_FILES` entries. They are boxed so that they do not move when it grows,
+    /// the descriptors hand out references to them.
+    tables: Vec<Option<Box<OpenFileTable>>>,
     /// Index of the first unoccupied space in the table
     index: usize,
 }
@@ -28,45 +30,32 @@ pub struct GeneralFileTable {
 impl GeneralFileTable {
     pub const fn new() -> Self {
         Self {
-            tables: [
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None, None, None,
-            ],
+            tables: Vec::new(),
             index: 0,
         }
     }
 
     /// Inserts an entry in the file table for the given file.
-    pub fn insert(&mut self, openfile: OpenFileTable) -> usize {
-        for _i in 0..MAX_TOTAL_OPEN_FILES {
+    /// Gives it back if the table is full.
+    pub fn insert(&mut self, openfile: OpenFileTable) -> Result<usize, OpenFileTable> {
+        for _i in 0..self.tables.len() {
+            if self.index >= self.tables.len() {
+                self.index = 0;
+            }
             if self.tables[self.index].is_none() {
-                self.tables[self.index] = Some(openfile);
-                return self.index;
+                self.tables[self.index] = Some(Box::new(openfile));
+                return Ok(self.index);
             }
             self.index += 1;
-            if self.index == MAX_TOTAL_OPEN_FILES {
-                self.index = 0;
-            }
         }
-        // need to be improved
-        panic!("file system is full");
+        if self.tables.len() < MAX_TOTAL_OPEN_FILES {
+            self.index = self.tables.len();
+            self.tables.push(Some(Box::new(openfile)));
+            Ok(self.index)
+        } else {
+            crate::warningln!("file system is full");
+            Err(openfile)
+        }
     }
 
     /// Deletes an entry in the table files.
@@ -80,6 +69,10 @@ impl GeneralFileTable {
                     self.tables[index] = None;
                 } else {
                     crate::debug!("fd wasn't closed");
+                    // A reader sleeping on a fifo must notice that the other end went away
+                    if file.get_path().to().starts_with("/dev/fifo") {
+                        wait_queue::wake_up(Resource::Fifo(file.get_id()));
+                    }
                 }
             }
             None => panic!("Unexisting file was closed"),
@@ -96,7 +89,7 @@ impl GeneralFileTable {
 
     /// Returns mutable copy of a given entry
     pub fn get_file_table_ref_mut(&'static mut self, index: usize) -> &'static mut OpenFileTable {
-        self.tables[index].as_mut().unwrap()
+        self.tables[index].as_deref_mut().unwrap()
     }
 }
 
@@ -185,20 +178,35 @@ impl FileDescriptor {
 }
 
 /// Held by the [`crate::scheduler::process::Process`] struct.
-#[derive(Debug, Copy, Clone)]
+/// It grows as files get opened, up to the limit of open files of the process.
+#[derive(Debug, Clone)]
 pub struct ProcessDescriptorTable {
     /// Associates a file descriptor to the index of the open file table
     /// in the [`GLOBAL_FILE_TABLE`]
-    files: [Option<usize>; MAX_TOTAL_OPEN_FILES_BY_PROCESS],
-    index: usize,
+    files: Vec<Option<usize>>,
 }
 
 impl ProcessDescriptorTable {
     pub const fn init() -> Self {
-        Self {
-            files: [None; MAX_TOTAL_OPEN_FILES_BY_PROCESS],
-            index: 0,
+        Self { files: Vec::new() }
+    }
+
+    /// Returns the index in the [`GLOBAL_FILE_TABLE`] behind `fd`
+    fn get(&self, fd: usize) -> Option<usize> {
+        self.files.get(fd).copied().flatten()
+    }
+
+    /// Makes `fd` point to `index`, growing the table if needed
+    fn set(&mut self, fd: usize, index: Option<usize>) {
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
         }
+        self.files[fd] = index;
+    }
+
+    /// Returns the lowest free file descriptor below `max`, if any
+    fn free_descriptor(&self, max: usize) -> Option<usize> {
+        (0..max).find(|fd| self.get(*fd).is_none())
     }
 
     /// Returns reference to filetable from a filedescriptor.
@@ -206,10 +214,7 @@ impl ProcessDescriptorTable {
         &self,
         fd: FileDescriptor,
     ) -> Result<&'static mut OpenFileTable, FileDesciptorError> {
-        if fd.into_usize() >= MAX_TOTAL_OPEN_FILES_BY_PROCESS {
-            return Err(FileDesciptorError());
-        }
-        if let Some(id) = self.files[fd.into_usize()] {
+        if let Some(id) = self.get(fd.into_usize()) {
             Ok(unsafe { GLOBAL_FILE_TABLE.get_file_table_ref_mut(id) })
         } else {
             Err(FileDesciptorError())
@@ -217,30 +222,50 @@ impl ProcessDescriptorTable {
     }
 
     pub fn is_none(&self, i: usize) -> bool {
-        self.files[i].is_none()
+        self.get(i).is_none()
+    }
+
+    /// Number of open file descriptors
+    pub fn count(&self) -> usize {
+        self.files.iter().filter(|file| file.is_some()).count()
     }
 
-    pub fn add_file_table(&mut self, open_file_table: OpenFileTable) -> FileDescriptor {
-        let mut i = 0;
-        while i < MAX_TOTAL_OPEN_FILES_BY_PROCESS {
-            if self.files[i].is_none() {
-                // File descriptor to be returned
-                break;
+    /// Gives a file descriptor below `max` to an already opened file.
+    /// The file is closed if there is no room left.
+    pub fn add_file_table(
+        &mut self,
+        open_file_table: OpenFileTable,
+        max: usize,
+    ) -> Result<FileDescriptor, FileDesciptorError> {
+        let fd = match self.free_descriptor(max) {
+            Some(fd) => fd,
+            None => {
+                crate::warningln!("Too many opened files by process.");
+                super::close_file(&open_file_table);
+                return Err(FileDesciptorError());
+            }
+        };
+        match unsafe { GLOBAL_FILE_TABLE.insert(open_file_table) } {
+            Ok(index) => {
+                self.set(fd, Some(index));
+                Ok(FileDescriptor::new(fd))
+            }
+            Err(open_file_table) => {
+                super::close_file(&open_file_table);
+                Err(FileDesciptorError())
             }
-            i += 1;
-        }
-        if i == MAX_TOTAL_OPEN_FILES_BY_PROCESS {
-            panic!("Too many opened files by process.");
-        } else {
-            let fd = i;
-            let index = unsafe { GLOBAL_FILE_TABLE.insert(open_file_table) };
-            self.files[i] = Some(index);
-            FileDescriptor::new(fd)
         }
     }
 
+    /// Opens a file and gives it a file descriptor below `max`.
+    /// Returns usize::MAX as file descriptor if it fails.
     /// TODO : add fields like flags, etc.
-    pub fn create_file_table(&mut self, path: Path, flags: OpenFlags) -> FileDescriptor {
+    pub fn create_file_table(
+        &mut self,
+        path: Path,
+        flags: OpenFlags,
+        max: usize,
+    ) -> FileDescriptor {
         // Here we create a new OpenFileTable.
         // We fill it with all the passed values,
         // inserts it into the GLOBAL_FILE_TABLE
@@ -249,40 +274,44 @@ impl ProcessDescriptorTable {
         // unoccupied FileDescriptor field.
         // We then return the associated FileDescriptor
         crate::debug!("{:?} {:?}", path, [path.to()]);
+        if self.free_descriptor(max).is_none() {
+            crate::warningln!("Too many opened files by process.");
+            return FileDescriptor::new(usize::MAX);
+        }
         let id = match super::open_file(&path, flags) {
             Some(i) => i,
             None => return FileDescriptor::new(usize::MAX),
         };
         let open_file_table = OpenFileTable::new(path, flags, id);
-        self.add_file_table(open_file_table)
+        self.add_file_table(open_file_table, max)
+            .unwrap_or_else(|_| FileDescriptor::new(usize::MAX))
     }
 
-    /// self.dup(1, 4) redirects fd 1 to the OpenFileTable
-    /// fd 4 points to.
+    /// self.dup(1, 4, max) redirects fd 1 to the OpenFileTable
+    /// fd 4 points to. `target` must be below `max`.
     pub fn dup(
         &mut self,
         target: FileDescriptor,
         operand: FileDescriptor,
+        max: usize,
     ) -> Result<usize, FileDesciptorError> {
-        // TO DO check the bounds and validity of the given data!
-        if target.into_usize() >= MAX_TOTAL_OPEN_FILES_BY_PROCESS
-            || operand.into_usize() >= MAX_TOTAL_OPEN_FILES_BY_PROCESS
-        {
+        if target.into_usize() >= max {
             return Err(FileDesciptorError());
         }
         crate::debug!(
             "Dup from descriptor {} -> {}",
             target.into_usize(),
-            self.files[target.into_usize()].is_none()
+            self.is_none(target.into_usize())
         );
-        match self.files[target.into_usize()] {
+        match self.get(target.into_usize()) {
             None => (),
             Some(fd) => unsafe {
                 GLOBAL_FILE_TABLE.delete(fd);
             },
         }
-        self.files[target.into_usize()] = self.files[operand.into_usize()];
-        match self.files[operand.into_usize()] {
+        let index = self.get(operand.into_usize());
+        self.set(target.into_usize(), index);
+        match index {
             None => (),
             Some(fd) => unsafe {
                 GLOBAL_FILE_TABLE.duplicate(fd);
@@ -291,22 +320,20 @@ impl ProcessDescriptorTable {
         Ok(0)
     }
 
-    pub fn copy(&mut self, father: ProcessDescriptorTable) {
-        for i in 0..MAX_TOTAL_OPEN_FILES_BY_PROCESS {
-            match father.files[i] {
-                None => self.files[i] = None,
-                Some(fd) => {
-                    unsafe {
-                        GLOBAL_FILE_TABLE.duplicate(fd);
-                    }
-                    self.files[i] = Some(fd);
-                }
+    pub fn copy(&mut self, father: &ProcessDescriptorTable) {
+        for fd in father.files.iter().flatten() {
+            unsafe {
+                GLOBAL_FILE_TABLE.duplicate(*fd);
             }
         }
+        self.files = father.files.clone();
     }
 
+    /// Closes the descriptor `fd`, its entry of `GLOBAL_FILE_TABLE` goes with its last descriptor.
+    /// Returns 2 if `fd` was not open.
     /// # Safety
-    /// TODO
+    /// No `&'static mut OpenFileTable` into `GLOBAL_FILE_TABLE`, see `get_file_table_ref_mut`,
+    /// may be alive: the entry may be removed. Needs to be called with the kernel lock held.
     pub unsafe fn close_fd(&mut self, fd: usize) -> Result<usize, FileDesciptorError> {
         if fd >= self.files.len() {
             return Err(FileDesciptorError());
@@ -321,65 +348,38 @@ impl ProcessDescriptorTable {
         }
     }
 
+    /// Closes all the descriptors, when the process dies or its table is replaced.
     /// # Safety
-    /// TODO
+    /// No `&'static mut OpenFileTable` into `GLOBAL_FILE_TABLE`, see `get_file_table_ref_mut`,
+    /// may be alive: the entries may be removed. Needs to be called with the kernel lock held.
     pub unsafe fn close(&mut self) {
-        for i in 0..MAX_TOTAL_OPEN_FILES_BY_PROCESS {
-            if let Some(fd) = self.files[i] {
-                GLOBAL_FILE_TABLE.delete(fd);
-            }
+        for fd in self.files.drain(..).flatten() {
+            GLOBAL_FILE_TABLE.delete(fd);
         }
     }
 }
 
 pub fn open(filename: String, mode: OpenFlags) -> FileDescriptor {
     let current_process = unsafe { process::get_current_as_mut() };
-    // look for a place to put the next file in the process file descriptor table
-    let mut new_pfdt = current_process.open_files;
-    for i in 0..(MAX_TOTAL_OPEN_FILES_BY_PROCESS - 1) {
-        if new_pfdt.index == 0 {
-            new_pfdt.index = 1;
-        }
-        match new_pfdt.files[new_pfdt.index] {
-            Some(_) => {
-                new_pfdt.index = (new_pfdt.index + 1) % MAX_TOTAL_OPEN_FILES_BY_PROCESS;
-                if i == MAX_TOTAL_OPEN_FILES_BY_PROCESS - 1 {
-                    panic!("Cannot open any new file for current process");
-                }
-            }
-            None => {
-                new_pfdt.index = i;
-                let id = match super::open_file(&Path::from(&filename), mode) {
-                    Some(i) => i,
-                    None => return FileDescriptor::new(usize::MAX),
-                };
-                unsafe {
-                    new_pfdt.files[i] = Some(GLOBAL_FILE_TABLE.insert(OpenFileTable::new(
-                        Path::from(filename.as_str()),
-                        mode,
-                        id,
-                    )));
-                }
-                current_process.open_files = new_pfdt;
-                return FileDescriptor::new(current_process.open_files.index);
-            }
-        };
-    }
-    current_process.open_files = new_pfdt;
-    FileDescriptor::new(current_process.open_files.index)
+    let max = current_process.limits.soft(Rlimit::OpenFiles) as usize;
+    current_process
+        .open_files
+        .create_file_table(Path::from(&filename), mode, max)
 }
 
 pub fn close(descriptor: u64) -> Result<(), FileDesciptorError> {
     let current_proccess = unsafe { process::get_current_as_mut() };
     // we try to close the file, and if at any point we fail, raise an error
-    match current_proccess.open_files.files[descriptor as usize] {
-        None => Err(FileDesciptorError()),
-        Some(idx) => {
-            current_proccess.open_files.files[descriptor as usize] = None;
-            unsafe {
-                GLOBAL_FILE_TABLE.delete(idx);
-                Ok(())
-            }
-        }
+    unsafe {
+        current_proccess
+            .open_files
+            .close_fd(descriptor as usize)
+            .and_then(|code| {
+                if code == 0 {
+                    Ok(())
+                } else {
+                    Err(FileDesciptorError())
+                }
+            })
     }
 }
@@ -18,57 +18,67 @@ pub fn test_read(port: u16) {
 
 /// Initialise the disk by reading it's informations (should improve it by giving an output)
 pub fn init(port: u16) {
-    unsafe {
-        // disable();
-        let mut data_register = Port::<u16>::new(port); // used to read write PIO data
-        let mut sectorcount_register = Port::new(port + 2);
-        let mut lba_low = Port::new(port + 3);
-        let mut lba_mid = Port::new(port + 4);
-        let mut lba_high = Port::new(port + 5);
-        let mut drive_head_register = Port::new(port + 6);
-        let mut command_register = Port::new(port + 7);
-        drive_head_register.write(0b10100000_u8);
-        sectorcount_register.write(0_u8);
-        lba_low.write(0_u8);
-        lba_mid.write(0_u8);
-        lba_high.write(0_u8);
-        //        println!("command send");
-
-        command_register.write(0xEC_u8);
-
-        let mut i = command_register.read();
-        let mut _compte = 1; // unused variable?
-        while (i & 0x8) == 0 {
-            i = command_register.read();
-            _compte += 1;
-        }
-        lba_low.read();
-        lba_mid.read();
-        lba_high.read();
-        let mut data_table: [u16; 256] = [0; 256];
-        for elt in &mut data_table {
-            *elt = data_register.read();
-        }
-
-        println!("uint16_t 0 : {}", data_table[0]);
-        println!("uint16_t 83 : {} {}", data_table[83], data_table[83] & 1024);
-        println!("uint16_t 88 : {}", data_table[88]);
-        println!("uint16_t 93 : {}", data_table[93]);
-        println!(
-            "uint32_t 61-61 : {}",
-            (data_table[60] as u32) | ((data_table[61] as u32) << 16)
-        );
-        println!(
-            "uint32_t 100-103 : {}",
-            (data_table[100] as u64)
-                | ((data_table[101] as u64) << 16)
-                | ((data_table[102] as u64) << 32)
-                | ((data_table[103] as u64) << 48)
-        );
-
-        wait_bsy(port);
-        //  enable(); // /!\ Should not to this if it was disabled before !
+    let data_table = unsafe { identify(port) };
+
+    println!("uint16_t 0 : {}", data_table[0]);
+    println!("uint16_t 83 : {} {}", data_table[83], data_table[83] & 1024);
+    println!("uint16_t 88 : {}", data_table[88]);
+    println!("uint16_t 93 : {}", data_table[93]);
+    println!(
+        "uint32_t 61-61 : {}",
+        (data_table[60] as u32) | ((data_table[61] as u32) << 16)
+    );
+    println!(
+        "uint32_t 100-103 : {}",
+        (data_table[100] as u64)
+            | ((data_table[101] as u64) << 16)
+            | ((data_table[102] as u64) << 32)
+            | ((data_table[103] as u64) << 48)
+    );
+}
+
+/// Returns the number of sectors addressable in 28-bit LBA mode on the disk at `port`
+pub fn sector_count(port: u16) -> u32 {
+    let data_table = unsafe { identify(port) };
+    (data_table[60] as u32) | ((data_table[61] as u32) << 16)
+}
+
+/// Sends the IDENTIFY command and returns the 256 words describing the disk
+unsafe fn identify(port: u16) -> [u16; 256] {
+    // disable();
+    let mut data_register = Port::<u16>::new(port); // used to read write PIO data
+    let mut sectorcount_register = Port::new(port + 2);
+    let mut lba_low = Port::new(port + 3);
+    let mut lba_mid = Port::new(port + 4);
+    let mut lba_high = Port::new(port + 5);
+    let mut drive_head_register = Port::new(port + 6);
+    let mut command_register = Port::new(port + 7);
+    drive_head_register.write(0b10100000_u8);
+    sectorcount_register.write(0_u8);
+    lba_low.write(0_u8);
+    lba_mid.write(0_u8);
+    lba_high.write(0_u8);
+    //        println!("command send");
+
+    command_register.write(0xEC_u8);
+
+    let mut i = command_register.read();
+    let mut _compte = 1; // unused variable?
+    while (i & 0x8) == 0 {
+        i = command_register.read();
+        _compte += 1;
     }
+    lba_low.read();
+    lba_mid.read();
+    lba_high.read();
+    let mut data_table: [u16; 256] = [0; 256];
+    for elt in &mut data_table {
+        *elt = data_register.read();
+    }
+
+    wait_bsy(port);
+    //  enable(); // /!\ Should not to this if it was disabled before !
+    data_table
 }
 
 /// function that from la sector of the disk outputs the data stored at the corresponding place (lba's count starts at 1!)
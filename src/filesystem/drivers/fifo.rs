@@ -4,6 +4,7 @@ use super::super::partition::{IoError, Partition};
 use crate::data_storage::path::Path;
 use crate::filesystem::descriptor::OpenFileTable;
 use crate::filesystem::fsflags::OpenFlags;
+use crate::scheduler::wait_queue::{self, Resource};
 use alloc::vec::Vec;
 use crossbeam_queue::{ArrayQueue, PopError, PushError};
 
@@ -102,7 +103,22 @@ impl Partition for FiFoPartition {
         }
         match &mut self.data[oft.get_id()] {
             None => Err(IoError::Kill),
-            Some(fifo) => fifo.read(size, oft.get_amount() == 1),
+            Some(fifo) => {
+                let res = fifo.read(size, oft.get_amount() == 1);
+                match res {
+                    Ok(data)
+                        if data.is_empty()
+                            && size != 0
+                            && oft.get_flags().contains(OpenFlags::OBLOCK) =>
+                    {
+                        unsafe {
+                            wait_queue::sleep_on(Resource::Fifo(oft.get_id()), true);
+                        }
+                        Err(IoError::Sleep)
+                    }
+                    res => res,
+                }
+            }
         }
     }
 
@@ -112,7 +128,13 @@ impl Partition for FiFoPartition {
         }
         match &mut self.data[oft.get_id()] {
             None => 0,
-            Some(fifo) => fifo.write(buffer),
+            Some(fifo) => {
+                let amount = fifo.write(buffer);
+                if amount > 0 {
+                    wait_queue::wake_up(Resource::Fifo(oft.get_id()));
+                }
+                amount
+            }
         }
     }
 
@@ -142,6 +164,7 @@ impl Partition for FiFoPartition {
             Some(v) => crate::warningln!("Fifo of length {}", v.len()),
         }
         self.data[oft.get_id()] = None;
+        wait_queue::wake_up(Resource::Fifo(oft.get_id()));
         false
     }
 
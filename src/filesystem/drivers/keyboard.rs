@@ -5,6 +5,8 @@ use crate::{data_storage::path::Path, warningln};
 use crate::filesystem::descriptor::OpenFileTable;
 use crate::filesystem::fsflags::OpenFlags;
 use crate::keyboard::get_top_key_event;
+use crate::scheduler::wait_queue::{self, Resource};
+use crate::scheduler::{job, process};
 use alloc::vec::Vec;
 
 pub struct KeyBoard;
@@ -30,15 +32,27 @@ impl Partition for KeyBoard {
         }
     }
 
-    fn read(&mut self, _oft: &OpenFileTable, size: usize) -> Result<Vec<u8>, IoError> {
+    fn read(&mut self, oft: &OpenFileTable, size: usize) -> Result<Vec<u8>, IoError> {
+        // Background jobs are stopped until they are put in the foreground
+        let current = process::get_current();
+        if !job::is_foreground(current) {
+            job::stop_group(current);
+            return Err(IoError::Sleep);
+        }
         // The number of packets to be written into the buffer
         let mut res = Vec::new();
         for _ in 0..size {
             if let Ok(k) = get_top_key_event() {
                 res.push(k);
             } else {
-                return Ok(res);
+                break;
+            }
+        }
+        if res.is_empty() && size != 0 && oft.get_flags().contains(OpenFlags::OBLOCK) {
+            unsafe {
+                wait_queue::sleep_on(Resource::Keyboard, true);
             }
+            return Err(IoError::Sleep);
         }
         Ok(res)
     }
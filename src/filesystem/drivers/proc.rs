@@ -3,14 +3,28 @@ use crate::filesystem::descriptor::OpenFileTable;
 use crate::filesystem::fsflags::OpenFlags;
 
 use crate::debug;
+use crate::memory;
 use crate::scheduler;
 use crate::scheduler::process;
+use crate::scheduler::process::{elf, regions::MappingKind};
 use crate::{data_storage::path::Path, warningln};
 
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+
+/// Files at the root of `proc` that are not about a process
+const SYSTEM_FILES: [(&str, fn() -> Vec<u8>); 7] = [
+    ("stat", system_stat),
+    ("meminfo", meminfo),
+    ("buddyinfo", buddyinfo),
+    ("slabinfo", slabinfo),
+    ("heapinfo", heapinfo),
+    ("swapinfo", swapinfo),
+    ("shm", shm),
+];
 
 /// Drives the `proc` repertory
 pub struct ProcDriver {
@@ -40,6 +54,50 @@ impl ProcDriver {
             String::from("name"),
             ProcInfoDriver::new(String::from("name"), name),
         );
+        res.infos.insert(
+            String::from("pgid"),
+            ProcInfoDriver::new(String::from("pgid"), pgid),
+        );
+        res.infos.insert(
+            String::from("sid"),
+            ProcInfoDriver::new(String::from("sid"), sid),
+        );
+        res.infos.insert(
+            String::from("uid"),
+            ProcInfoDriver::new(String::from("uid"), uid),
+        );
+        res.infos.insert(
+            String::from("utime"),
+            ProcInfoDriver::new(String::from("utime"), utime),
+        );
+        res.infos.insert(
+            String::from("stime"),
+            ProcInfoDriver::new(String::from("stime"), stime),
+        );
+        res.infos.insert(
+            String::from("switches"),
+            ProcInfoDriver::new(String::from("switches"), switches),
+        );
+        res.infos.insert(
+            String::from("faults"),
+            ProcInfoDriver::new(String::from("faults"), faults),
+        );
+        res.infos.insert(
+            String::from("start"),
+            ProcInfoDriver::new(String::from("start"), start),
+        );
+        res.infos.insert(
+            String::from("sched"),
+            ProcInfoDriver::new(String::from("sched"), sched),
+        );
+        res.infos.insert(
+            String::from("maps"),
+            ProcInfoDriver::new(String::from("maps"), maps),
+        );
+        res.infos.insert(
+            String::from("rss"),
+            ProcInfoDriver::new(String::from("rss"), rss),
+        );
         res
     }
     pub fn get_info(&self, id: &str) -> Result<&ProcInfoDriver, ErrProc> {
@@ -80,6 +138,17 @@ impl Partition for ProcDriver {
                     return Ok(Vec::new());
                 }
             }
+        } else if let Some((_, file)) = SYSTEM_FILES
+            .iter()
+            .find(|(name, _)| sliced.len() == 1 && sliced[0] == *name)
+        {
+            // System-wide statistics
+            let mut res = file();
+            res.truncate(oft.get_offset() + size);
+            res.reverse();
+            res.truncate(core::cmp::max(res.len() - oft.get_offset(), 0));
+            res.reverse();
+            return Ok(res);
         } else if sliced.len() == 1 {
             // Means we access the directory of a process
             let keys = self.infos.keys();
@@ -100,17 +169,18 @@ impl Partition for ProcDriver {
             // Means we access the main proc directory
             // We want to build the array of all alive processes
             let mut res_array = Vec::new();
-            for (id, proc) in unsafe { scheduler::process::ID_TABLE.as_ref().iter().enumerate() } {
-                match proc.state {
-                    process::State::SlotAvailable => (),
-                    _ => {
-                        let id = format!("{}", id);
-                        for b in id.bytes() {
-                            res_array.push(b)
-                        }
-                        res_array.push(b' ');
-                    }
+            for (name, _) in SYSTEM_FILES.iter() {
+                res_array.extend_from_slice(name.as_bytes());
+                res_array.push(b' ');
+            }
+            for id in
+                unsafe { scheduler::process::ID_TABLE.keys() }.filter(|id| !process::is_idle(**id))
+            {
+                let id = format!("{}", id.0);
+                for b in id.bytes() {
+                    res_array.push(b)
                 }
+                res_array.push(b' ');
             }
             res_array.truncate(oft.get_offset() + size);
             res_array.reverse();
@@ -171,10 +241,10 @@ impl ProcInfoDriver {
 }
 
 fn heap_proc(proc: usize) -> Vec<u8> {
-    if proc as u64 >= scheduler::PROCESS_MAX_NUMBER {
-        return Vec::new();
-    }
-    let process = unsafe { process::get_process(proc) };
+    let process = match unsafe { process::get_process(proc) } {
+        Some(process) => process,
+        None => return Vec::new(),
+    };
     let str = format!("{} {}", process.heap_address, process.heap_size);
     str.as_bytes().to_vec()
 }
@@ -184,22 +254,280 @@ fn screen(_proc: usize) -> Vec<u8> {
 }
 
 fn state(proc: usize) -> Vec<u8> {
-    if proc as u64 >= scheduler::PROCESS_MAX_NUMBER {
-        return Vec::new();
-    }
-    let process = unsafe { process::get_process(proc) };
+    let process = match unsafe { process::get_process(proc) } {
+        Some(process) => process,
+        None => return Vec::new(),
+    };
     let str = format!("{:?}", process.state);
     str.as_bytes().to_vec()
 }
 
 fn ppid(proc: usize) -> Vec<u8> {
-    if proc as u64 >= scheduler::PROCESS_MAX_NUMBER {
-        return Vec::new();
+    match unsafe { process::get_process(proc) } {
+        Some(process) => format!("{:?}", process.get_ppid()).as_bytes().to_vec(),
+        None => Vec::new(),
     }
-    let str = format!("{:?}", unsafe { process::get_process(proc) }.get_ppid());
-    str.as_bytes().to_vec()
 }
 
 fn name(proc: usize) -> Vec<u8> {
-    unsafe { process::get_process(proc).get_name() }
+    match unsafe { process::get_process(proc) } {
+        Some(process) => process.get_name(),
+        None => Vec::new(),
+    }
+}
+
+fn pgid(proc: usize) -> Vec<u8> {
+    match unsafe { process::get_process(proc) } {
+        Some(process) => format!("{}", process.get_pgid().0).as_bytes().to_vec(),
+        None => Vec::new(),
+    }
+}
+
+fn sid(proc: usize) -> Vec<u8> {
+    match unsafe { process::get_process(proc) } {
+        Some(process) => format!("{}", process.get_sid().0).as_bytes().to_vec(),
+        None => Vec::new(),
+    }
+}
+
+fn uid(proc: usize) -> Vec<u8> {
+    match unsafe { process::get_process(proc) } {
+        Some(process) => format!("{}", process.credentials.euid).as_bytes().to_vec(),
+        None => Vec::new(),
+    }
+}
+
+/// Formats a statistic of the process, or nothing if it does not exist
+fn proc_stat(proc: usize, stat: fn(&process::stats::CpuStats) -> u64) -> Vec<u8> {
+    match unsafe { process::get_process(proc) } {
+        Some(process) => format!("{}", stat(&process.stats)).as_bytes().to_vec(),
+        None => Vec::new(),
+    }
+}
+
+fn utime(proc: usize) -> Vec<u8> {
+    proc_stat(proc, |stats| stats.user_ticks)
+}
+
+fn stime(proc: usize) -> Vec<u8> {
+    proc_stat(proc, |stats| stats.kernel_ticks)
+}
+
+fn switches(proc: usize) -> Vec<u8> {
+    proc_stat(proc, |stats| stats.context_switches)
+}
+
+fn faults(proc: usize) -> Vec<u8> {
+    proc_stat(proc, |stats| stats.page_faults)
+}
+
+fn start(proc: usize) -> Vec<u8> {
+    proc_stat(proc, |stats| stats.start_time)
+}
+
+/// Scheduling class of the process : `realtime <period> <budget> <remaining>` or `normal <priority>`
+fn sched(proc: usize) -> Vec<u8> {
+    let process = match unsafe { process::get_process(proc) } {
+        Some(process) => process,
+        None => return Vec::new(),
+    };
+    let str = match scheduler::realtime::get(process.get_pid()) {
+        Some(reservation) => format!(
+            "realtime {} {} {}",
+            reservation.period, reservation.budget, reservation.remaining
+        ),
+        None => format!("normal {}", process.get_priority()),
+    };
+    str.as_bytes().to_vec()
+}
+
+/// Ranges of the address space of the process, one per line :
+/// `<start>-<end> <permissions> <resident pages> <tags> <kind>`.
+/// The tags are the custom flags of the pages, `-` if there is none.
+fn maps(proc: usize) -> Vec<u8> {
+    let process = match unsafe { process::get_process(proc) } {
+        Some(process) => process,
+        None => return Vec::new(),
+    };
+    let mut res = String::new();
+    for mapping in process.mappings().iter() {
+        let flags = mapping.flags;
+        let permissions = [
+            'r',
+            if flags.contains(PageTableFlags::WRITABLE) {
+                'w'
+            } else {
+                '-'
+            },
+            if flags.contains(PageTableFlags::NO_EXECUTE) {
+                '-'
+            } else {
+                'x'
+            },
+            if flags.contains(memory::cow::COPY_ON_WRITE) {
+                'c'
+            } else {
+                'p'
+            },
+        ];
+        let tags: Vec<&str> = [
+            (elf::MODIFY_WITH_EXEC, "modify_with_exec"),
+            (elf::STACK, "stack"),
+            (elf::HEAP, "heap"),
+        ]
+        .iter()
+        .filter(|(tag, _)| flags.contains(*tag))
+        .map(|(_, name)| *name)
+        .collect();
+        res.push_str(&format!(
+            "{:016x}-{:016x} {} {} {} [{}]\n",
+            mapping.start,
+            mapping.end,
+            permissions.iter().collect::<String>(),
+            mapping.resident,
+            if tags.is_empty() {
+                String::from("-")
+            } else {
+                tags.join(",")
+            },
+            match mapping.kind {
+                MappingKind::Elf => "elf",
+                MappingKind::Heap => "heap",
+                MappingKind::Stack => "stack",
+                MappingKind::Args => "args",
+                MappingKind::Shared => "shm",
+                MappingKind::File => "file",
+            }
+        ));
+    }
+    res.into_bytes()
+}
+
+/// Resident set size of the process, in pages
+fn rss(proc: usize) -> Vec<u8> {
+    match unsafe { process::get_process(proc) } {
+        Some(process) => format!("{}", process.resident_pages()).as_bytes().to_vec(),
+        None => Vec::new(),
+    }
+}
+
+/// Content of `/proc/stat`, all values are in ticks. The idle task is not counted as a process.
+fn system_stat() -> Vec<u8> {
+    let stats = process::stats::get_system_stats();
+    format!(
+        "ticks {}\nuser {}\nkernel {}\nidle {}\nprocesses {}\ncpus {}\n",
+        scheduler::wait_queue::get_ticks(),
+        stats.user_ticks,
+        stats.kernel_ticks,
+        stats.idle_ticks,
+        unsafe { process::ID_TABLE.keys() }
+            .filter(|id| !process::is_idle(**id))
+            .count(),
+        crate::smp::cpu_count(),
+    )
+    .as_bytes()
+    .to_vec()
+}
+
+/// Content of `/proc/buddyinfo`, one line per order of the frame allocator :
+/// `<block size in KiB> <free> <allocations> <failures> <splits> <merges>`
+fn buddyinfo() -> Vec<u8> {
+    let frame_allocator = match unsafe { &memory::FRAME_ALLOCATOR } {
+        Some(frame_allocator) => frame_allocator,
+        None => return Vec::new(),
+    };
+    let mut res = String::new();
+    for (order, stats) in frame_allocator.order_stats().iter().enumerate() {
+        res.push_str(&format!(
+            "{} {} {} {} {} {}\n",
+            memory::buddy::block_size(order) / 1024,
+            stats.free,
+            stats.allocations,
+            stats.failures,
+            stats.splits,
+            stats.merges
+        ));
+    }
+    res.into_bytes()
+}
+
+/// Content of `/proc/slabinfo`, one line per slab cache of the kernel heap :
+/// `<name> <objects in use> <free objects> <total objects> <object size> <slabs>`
+fn slabinfo() -> Vec<u8> {
+    let mut res = String::new();
+    for stats in crate::allocator::slab_stats().iter() {
+        res.push_str(&format!(
+            "kmalloc-{} {} {} {} {} {}\n",
+            stats.object_size,
+            stats.in_use,
+            stats.free,
+            stats.total,
+            stats.object_size,
+            stats.slabs
+        ));
+    }
+    res.into_bytes()
+}
+
+/// Content of `/proc/heapinfo`, the size and usage of the kernel heap in bytes
+fn heapinfo() -> Vec<u8> {
+    let stats = crate::allocator::heap_stats();
+    format!(
+        "size {}\nused {}\nmax {}\n",
+        stats.size, stats.used, stats.max_size
+    )
+    .as_bytes()
+    .to_vec()
+}
+
+/// Content of `/proc/shm`, one line per named segment: id, name, pages and number of mappings
+fn shm() -> Vec<u8> {
+    let mut res = String::new();
+    for segment in memory::shm::list() {
+        res.push_str(&format!(
+            "{} {} {} {}\n",
+            segment.id, segment.name, segment.pages, segment.mappings
+        ));
+    }
+    res.into_bytes()
+}
+
+/// Content of `/proc/swapinfo`, in pages
+fn swapinfo() -> Vec<u8> {
+    let stats = memory::swap::stats();
+    format!(
+        "slots {}\nused {}\nswapped_out {}\nswapped_in {}\nsecond_chances {}\n",
+        stats.slots, stats.used, stats.swapped_out, stats.swapped_in, stats.second_chances
+    )
+    .as_bytes()
+    .to_vec()
+}
+
+/// Content of `/proc/meminfo`. The frames and the swap are in 4 KiB pages, the heap is in bytes.
+/// The page tables are the ones of the user half of the address spaces of the processes.
+fn meminfo() -> Vec<u8> {
+    let (free, used) = match unsafe { &memory::FRAME_ALLOCATOR } {
+        Some(frame_allocator) => (frame_allocator.state(), frame_allocator.used_frames()),
+        None => (0, 0),
+    };
+    let heap = crate::allocator::heap_stats();
+    let swap = memory::swap::stats();
+    let page_tables: u64 = unsafe { process::ID_TABLE.values() }
+        .filter(|process| !process::is_idle(process.get_pid()) && !process.is_kernel_thread())
+        .map(|process| unsafe { memory::table_frames(PhysFrame::containing_address(process.cr3)) })
+        .sum();
+    format!(
+        "total_frames {}\nfree_frames {}\nused_frames {}\nheap_size {}\nheap_used {}\nheap_max {}\npage_table_frames {}\nswap_total {}\nswap_free {}\n",
+        free + used,
+        free,
+        used,
+        heap.size,
+        heap.used,
+        heap.max_size,
+        page_tables,
+        swap.slots,
+        swap.slots - swap.used
+    )
+    .as_bytes()
+    .to_vec()
 }
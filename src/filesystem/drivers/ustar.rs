@@ -1,10 +1,11 @@
 #![allow(clippy::upper_case_acronyms)]
 
 use super::super::fsflags::OpenFlags;
-use super::super::partition::{IoError, Partition};
+use super::super::partition::{FileSectors, IoError, Partition};
 use super::disk_operations;
 use crate::filesystem::descriptor::OpenFileTable;
 use crate::println;
+use crate::scheduler::process::{self, credentials::Credentials};
 use crate::{data_storage::path::Path, debug, errorln};
 use alloc::collections::BTreeMap;
 use alloc::string::String;
@@ -44,12 +45,18 @@ static mut FILE_BUFFER: FileBuffer = FileBuffer(BTreeMap::new());
 /// Using the informations given by the drive at initialization.
 const LBA_TABLES_COUNT: u32 = 4;
 
+/// Number of sectors the LBA tables can address, the swap lies after them (see `memory::swap`)
+pub const PARTITION_SECTORS: u32 = LBA_TABLES_COUNT * 512;
+
 /// Max number of blocks usable in short mode
 const SHORT_MODE_LIMIT: u32 = 100;
 
 /// Base port for the disk index 2 for QEMU
 pub const DISK_PORT: u16 = 0x170;
 
+/// Permissions of a newly created file
+const DEFAULT_FILE_MODE: u16 = 0o644;
+
 pub static mut LBA_TABLE_GLOBAL: LBATableGlobal = LBATableGlobal {
     index: 0,
     data: [LBATable {
@@ -95,6 +102,50 @@ pub struct HeaderFlags {
     pub group_misc: u8,
 }
 
+/// Read permission in a group of `rwxs` bits
+pub const PERM_READ: u8 = 0b1000;
+/// Write permission in a group of `rwxs` bits
+pub const PERM_WRITE: u8 = 0b0100;
+/// Execute permission in a group of `rwxs` bits
+pub const PERM_EXECUTE: u8 = 0b0010;
+
+impl HeaderFlags {
+    /// Flags of a file with the unix-like `rwx` permissions `mode` (e.g. `0o644`)
+    pub fn from_mode(mode: u16) -> Self {
+        let mut flags = Self {
+            user_owner: 0,
+            group_misc: 0,
+        };
+        flags.set_mode(mode);
+        flags
+    }
+
+    /// Replaces the permissions by the unix-like `rwx` ones, the other flags are kept.
+    /// The "user" permissions are the ones of everyone but the owner and the group.
+    pub fn set_mode(&mut self, mode: u16) {
+        let owner = (((mode >> 6) & 0b111) << 1) as u8;
+        let group = (((mode >> 3) & 0b111) << 1) as u8;
+        let user = ((mode & 0b111) << 1) as u8;
+        self.user_owner = (user << 4) | owner;
+        self.group_misc = (group << 4) | (self.group_misc & 0xF);
+    }
+
+    /// Returns the permissions needed to open a file with `flags`
+    pub fn wanted(flags: OpenFlags) -> u8 {
+        let mut wanted = 0;
+        if flags.contains(OpenFlags::ORD) {
+            wanted |= PERM_READ;
+        }
+        if flags.intersects(OpenFlags::OWR | OpenFlags::OAPPEND) {
+            wanted |= PERM_WRITE;
+        }
+        if flags.contains(OpenFlags::OXCUTE) {
+            wanted |= PERM_EXECUTE;
+        }
+        wanted
+    }
+}
+
 /// Type of a chunk of data.
 ///
 /// Currently only `directory` and `file` but some other things like `Pipe` might be added.
@@ -183,6 +234,30 @@ impl Header {
     fn is_dir(&self) -> bool {
         matches!(self.file_type, Type::Dir)
     }
+
+    /// Returns true iff `credentials` are granted all the `wanted` permissions on this chunk.
+    /// Root is granted everything.
+    pub fn allows(&self, credentials: &Credentials, wanted: u8) -> bool {
+        if credentials.is_root() {
+            return true;
+        }
+        let permissions = if credentials.euid == self.owner.0 {
+            self.flags.user_owner & 0xF
+        } else if credentials.egid == self.group.0 {
+            self.flags.group_misc >> 4
+        } else {
+            self.flags.user_owner >> 4
+        };
+        permissions & wanted == wanted
+    }
+
+    /// Copies the ids and the permissions of `old`, used when a file is rewritten.
+    fn keep_permissions(&mut self, old: &Header) {
+        self.user = old.user;
+        self.owner = old.owner;
+        self.group = old.group;
+        self.flags = old.flags;
+    }
 }
 fn strip_end<T: Eq>(a: &[T], c: T) -> &[T] {
     let mut idx = a.len() - 1;
@@ -542,6 +617,28 @@ impl UsTar {
         Ok(())
     }
 
+    /// Applies `update` to the header of the chunk at `path` (relative to the partition)
+    /// and writes it back to the disk if it returns true.
+    fn update_header(&mut self, path: &Path, update: impl FnOnce(&mut Header) -> bool) -> bool {
+        let mut path_name = String::from("root");
+        if !path.is_empty() {
+            path_name.push('/');
+        }
+        path_name.push_str(&path.to());
+        let path_name = Path::from(&path_name);
+        let address = match self.find_address(&path_name) {
+            Ok(address) => address,
+            Err(_) => return false,
+        };
+        let lba = (address.lba * 512 + address.block + 1) as u32;
+        let mut header: Header = self.read_from_disk(lba);
+        if !update(&mut header) {
+            return false;
+        }
+        self.write_to_disk(header, lba);
+        true
+    }
+
     pub fn find_address(&self, path: &Path) -> Result<Address, UsTarError> {
         if let Some(addr) = unsafe { FILE_ADRESS_CACHE.0.get(&path) } {
             Ok(*addr)
@@ -968,14 +1065,32 @@ impl Default for UsTar {
     }
 }
 impl Partition for UsTar {
-    fn open(&mut self, path: &Path, _flags: OpenFlags) -> Option<usize> {
+    fn open(&mut self, path: &Path, flags: OpenFlags) -> Option<usize> {
         let mut path_name = String::from("root");
         if !path.is_empty() {
             path_name.push('/');
         }
         path_name.push_str(&path.to());
         let path_name = Path::from(&path_name);
-        let _memfile = self.find_memfile(&path_name);
+        let credentials = process::current_credentials();
+        match self.find_memfile(&path_name) {
+            Ok(file) => {
+                if !file.header.allows(&credentials, HeaderFlags::wanted(flags)) {
+                    errorln!("Tried to open {:?}, but no right!", path_name);
+                    return None;
+                }
+            }
+            // The file will be created in its parent directory
+            Err(_) if flags.contains(OpenFlags::OCREAT) => {
+                if let Ok(parent) = self.find_memfile(&path_name.get_parent()) {
+                    if !parent.header.allows(&credentials, PERM_WRITE) {
+                        errorln!("Tried to create {:?}, but no right!", path_name);
+                        return None;
+                    }
+                }
+            }
+            Err(_) => (),
+        }
         /*match memfile {
             Some(f) => {
                 if flags.contains(OpenFlags::ORD | OpenFlags::OWR) | flags.contains(OpenFlags::OXCUTE | OpenFlags::OWR) {
@@ -1012,7 +1127,16 @@ impl Partition for UsTar {
         }
         path_name.push_str(&oft.get_path().to());
         let path = Path::from(&path_name);
+        let wanted = if oft.get_flags().contains(OpenFlags::OXCUTE) {
+            PERM_EXECUTE
+        } else {
+            PERM_READ
+        };
         let file = match self.find_memfile(&path) {
+            Ok(f) if !f.header.allows(&process::current_credentials(), wanted) => {
+                errorln!("Tried to read {:?}, but no right!", path);
+                return Err(IoError::Continue);
+            }
             Ok(f) => {
                 match f.header.file_type {
                     Type::File => f,
@@ -1073,6 +1197,14 @@ impl Partition for UsTar {
                 } else {
                     // look for the parent folder in which we will create the file
                     let parent_path = path_name.get_parent();
+                    let credentials = process::current_credentials();
+                    match self.find_memfile(&parent_path) {
+                        Ok(parent) if !parent.header.allows(&credentials, PERM_WRITE) => {
+                            errorln!("Tried to create {:?}, but no right!", path_name);
+                            return -1;
+                        }
+                        _ => (),
+                    }
                     let parent_dir = if let Ok(a) = self.find_memdir(&parent_path) {
                         debug!("Parent folder is : {:?}", a);
                         a
@@ -1100,17 +1232,14 @@ impl Partition for UsTar {
                         FileMode::Short
                     };
                     let header = Header {
-                        user: UGOID(412),
-                        owner: UGOID(666),
-                        group: UGOID(777),
+                        user: UGOID(credentials.uid),
+                        owner: UGOID(credentials.euid),
+                        group: UGOID(credentials.egid),
                         parent_address: parent_dir.address,
                         length: length as u32,
                         blocks_number,
                         blocks: [Address { lba: 0, block: 0 }; SHORT_MODE_LIMIT as usize],
-                        flags: HeaderFlags {
-                            user_owner: 0b1111_1111_u8,
-                            group_misc: 0b1111_1111_u8,
-                        },
+                        flags: HeaderFlags::from_mode(DEFAULT_FILE_MODE),
                         mode,
                         name: name_arr,
                         file_type: Type::File,
@@ -1138,6 +1267,13 @@ impl Partition for UsTar {
                 if file.header.file_type == Type::Dir {
                     return 0;
                 }
+                if !file
+                    .header
+                    .allows(&process::current_credentials(), PERM_WRITE)
+                {
+                    errorln!("Tried to write in {:?}, but no right!", path_name);
+                    return -1;
+                }
                 // compute the new size of the file, to see if we need to allocate/deallocate disk memory
                 debug!("File exists and is : {:?}", file);
                 let header_address = self.find_address(&path_name).unwrap();
@@ -1265,9 +1401,10 @@ impl Partition for UsTar {
                                 Err(_) => return -1,
                                 Ok(x) => x,
                             };
-                            let new_header: Header = self.read_from_disk(
+                            let mut new_header: Header = self.read_from_disk(
                                 (new_header_addr.lba * 512 + new_header_addr.block + 1) as u32,
                             );
+                            new_header.keep_permissions(&file.header);
                             self.write_to_disk(
                                 new_header,
                                 (old_header_addr.lba * 512 + old_header_addr.block + 1) as u32,
@@ -1297,9 +1434,10 @@ impl Partition for UsTar {
                             Err(_) => return -1,
                             Ok(x) => x,
                         };
-                        let new_header: Header = self.read_from_disk(
+                        let mut new_header: Header = self.read_from_disk(
                             (new_header_addr.lba * 512 + new_header_addr.block + 1) as u32,
                         );
+                        new_header.keep_permissions(&file.header);
                         self.write_to_disk(
                             new_header,
                             (old_header_addr.lba * 512 + old_header_addr.block + 1) as u32,
@@ -1338,4 +1476,73 @@ impl Partition for UsTar {
     fn give_param(&mut self, _oft: &OpenFileTable, _param: usize) -> usize {
         usize::MAX
     }
+
+    /// Only the owner of the file and root can change its permissions
+    fn chmod(&mut self, path: &Path, mode: u16) -> bool {
+        let credentials = process::current_credentials();
+        self.update_header(path, |header| {
+            if credentials.is_root() || credentials.euid == header.owner.0 {
+                header.flags.set_mode(mode);
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    /// The sectors of the data blocks, read from the address blocks in long mode
+    fn file_sectors(&mut self, path: &Path) -> Option<FileSectors> {
+        let mut path_name = String::from("root");
+        if !path.is_empty() {
+            path_name.push('/');
+        }
+        path_name.push_str(&path.to());
+        let address = self.find_address(&Path::from(&path_name)).ok()?;
+        let header: Header = self.read_from_disk((address.lba * 512 + address.block + 1) as u32);
+        if header.file_type != Type::File {
+            return None;
+        }
+        let blocks = match header.mode {
+            FileMode::Short => header.blocks[..header.blocks_number as usize].to_vec(),
+            FileMode::Long => {
+                let nb_bloc = div_ceil(header.length, 512) as usize;
+                let mut data_addresses = Vec::new();
+                for address in header.blocks[..header.blocks_number as usize].iter() {
+                    let sector: LongFile =
+                        self.read_from_disk((address.lba * 512 + address.block + 1) as u32);
+                    data_addresses.extend_from_slice(&sector.addresses);
+                }
+                data_addresses.truncate(nb_bloc);
+                data_addresses
+            }
+        };
+        Some(FileSectors {
+            port: self.port,
+            length: header.length as u64,
+            sectors: blocks
+                .iter()
+                .map(|address| (address.lba * 512 + address.block + 1) as u32)
+                .collect(),
+        })
+    }
+
+    /// Only root can give a file away, the owner can only change its group to one of its own
+    fn chown(&mut self, path: &Path, owner: u64, group: u64) -> bool {
+        let credentials = process::current_credentials();
+        self.update_header(path, |header| {
+            if credentials.is_root() {
+                header.owner = UGOID(owner);
+                header.group = UGOID(group);
+                true
+            } else if credentials.euid == header.owner.0
+                && owner == header.owner.0
+                && (group == credentials.gid || group == credentials.egid)
+            {
+                header.group = UGOID(group);
+                true
+            } else {
+                false
+            }
+        })
+    }
 }
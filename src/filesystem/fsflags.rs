@@ -12,6 +12,8 @@ bitflags! {
         const OCREAT = 1 << 2;
         const OAPPEND = 1 << 3;
         const OXCUTE = 1 << 4;
+        /// Reads sleep until data is available instead of returning nothing
+        const OBLOCK = 1 << 5;
     }
 }
 
@@ -135,10 +135,20 @@ pub fn read_file(oft: &mut OpenFileTable, length: usize) -> Result<Vec<u8>, IoEr
     }
 }
 
+/// Reads the whole file at `path`, the current process needs the permission to read it
 pub fn read_file_from_path(path: Path) -> Result<Vec<u8>, IoError> {
+    read_whole_file(path, fsflags::OpenFlags::ORD)
+}
+
+/// Reads the whole program at `path` to execute it, the current process needs the permission to execute it
+pub fn read_program_from_path(path: Path) -> Result<Vec<u8>, IoError> {
+    read_whole_file(path, fsflags::OpenFlags::OXCUTE)
+}
+
+fn read_whole_file(path: Path, flags: fsflags::OpenFlags) -> Result<Vec<u8>, IoError> {
     unsafe {
         if let Some(ref mut vfs) = VFS {
-            let oft = OpenFileTable::new(path, fsflags::OpenFlags::OXCUTE, usize::MAX);
+            let oft = OpenFileTable::new(path, flags, usize::MAX);
             match vfs.read(&oft, usize::MAX) {
                 Ok(res) => Ok(res),
                 Err(err) => Err(err),
@@ -181,6 +191,39 @@ fn test() {
     );
 }
 
+/// Changes the permissions of the file at `path`, returns true iff it succeeded
+pub fn chmod_file(path: &Path, mode: u16) -> bool {
+    unsafe {
+        if let Some(ref mut vfs) = VFS {
+            vfs.chmod(path, mode)
+        } else {
+            panic!("VFS not initialized in chmod_file.");
+        }
+    }
+}
+
+/// Changes the owner and the group of the file at `path`, returns true iff it succeeded
+pub fn chown_file(path: &Path, owner: u64, group: u64) -> bool {
+    unsafe {
+        if let Some(ref mut vfs) = VFS {
+            vfs.chown(path, owner, group)
+        } else {
+            panic!("VFS not initialized in chown_file.");
+        }
+    }
+}
+
+/// Returns the sectors of the file at `path`, to map it in memory
+pub fn file_sectors(path: &Path) -> Option<partition::FileSectors> {
+    unsafe {
+        if let Some(ref mut vfs) = VFS {
+            vfs.file_sectors(path)
+        } else {
+            panic!("VFS not initialized in file_sectors.");
+        }
+    }
+}
+
 pub fn close_file(oft: &OpenFileTable) {
     unsafe {
         if let Some(ref mut vfs) = VFS {
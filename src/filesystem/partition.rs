@@ -12,6 +12,17 @@ pub enum IoError {
     Sleep,
 }
 
+/// Where the bytes of a file are on a disk, for the partitions whose files can be mapped in memory
+#[derive(Debug, Clone)]
+pub struct FileSectors {
+    /// Base port of the disk
+    pub port: u16,
+    /// Length of the file in bytes
+    pub length: u64,
+    /// Sectors holding the file in order, two bytes per word, the first one in the high byte
+    pub sectors: Vec<u32>,
+}
+
 /// Each storage element (be it an ATA disk or  a virtual system)
 /// needs to implement this trait in order to get integrated into the
 /// VFS.
@@ -43,4 +54,22 @@ pub trait Partition {
 
     /// Param
     fn give_param(&mut self, oft: &OpenFileTable, param: usize) -> usize;
+
+    /// Changes the unix-like `rwx` permissions of a file.
+    /// Returns false if it failed or if the partition has no permissions.
+    fn chmod(&mut self, _path: &Path, _mode: u16) -> bool {
+        false
+    }
+
+    /// Changes the owner and the group of a file.
+    /// Returns false if it failed or if the partition has no owners.
+    fn chown(&mut self, _path: &Path, _owner: u64, _group: u64) -> bool {
+        false
+    }
+
+    /// Returns the sectors of a file, to map it in memory.
+    /// Returns None if it is not a regular file or if the partition is not on a disk.
+    fn file_sectors(&mut self, _path: &Path) -> Option<FileSectors> {
+        None
+    }
 }
@@ -9,7 +9,7 @@ use alloc::vec::Vec;
 
 use super::descriptor::OpenFileTable;
 use super::fsflags::OpenFlags;
-use super::partition::{IoError, Partition};
+use super::partition::{FileSectors, IoError, Partition};
 
 use crate::data_storage::path::Path;
 
@@ -170,6 +170,51 @@ impl Partition for VFS {
             }
         }
     }
+
+    fn chmod(&mut self, path: &Path, mode: u16) -> bool {
+        let sliced = path.slice();
+        match &mut self.subfiles {
+            PartitionNode::Leaf(part) => {
+                part.chmod(&Path::from_sliced(&sliced[self.depth..]), mode)
+            }
+            PartitionNode::Node(map) => {
+                match sliced.get(self.depth).and_then(|name| map.get_mut(name)) {
+                    None => false,
+                    Some(next) => next.chmod(path, mode),
+                }
+            }
+        }
+    }
+
+    fn chown(&mut self, path: &Path, owner: u64, group: u64) -> bool {
+        let sliced = path.slice();
+        match &mut self.subfiles {
+            PartitionNode::Leaf(part) => {
+                part.chown(&Path::from_sliced(&sliced[self.depth..]), owner, group)
+            }
+            PartitionNode::Node(map) => {
+                match sliced.get(self.depth).and_then(|name| map.get_mut(name)) {
+                    None => false,
+                    Some(next) => next.chown(path, owner, group),
+                }
+            }
+        }
+    }
+
+    fn file_sectors(&mut self, path: &Path) -> Option<FileSectors> {
+        let sliced = path.slice();
+        match &mut self.subfiles {
+            PartitionNode::Leaf(part) => {
+                part.file_sectors(&Path::from_sliced(&sliced[self.depth..]))
+            }
+            PartitionNode::Node(map) => {
+                match sliced.get(self.depth).and_then(|name| map.get_mut(name)) {
+                    None => None,
+                    Some(next) => next.file_sectors(path),
+                }
+            }
+        }
+    }
 }
 
 impl VFS {
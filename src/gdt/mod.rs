@@ -2,7 +2,10 @@
 
 //! Everything needed to setup a GDT that does nothing, so we can use paging instead.
 
+use crate::smp::{self, MAX_CPUS};
 use crate::warningln;
+use alloc::boxed::Box;
+use alloc::vec;
 use lazy_static::lazy_static;
 use x86_64::instructions::segmentation::set_cs;
 use x86_64::instructions::tables::load_tss;
@@ -14,6 +17,10 @@ mod gdt_entry;
 /// Index of the stack for double fault handling
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// Index of the stack for page fault handling. The faulting stack may be the one that needs a new page.
+/// The handler may fault again, so it lowers this stack with a `NestedPageFault` while it runs.
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+
 /// Structure to store every selector/segment to modify them at will afterward.
 pub struct Selectors {
     code_selector: SegmentSelector, // kernel selector
@@ -22,58 +29,159 @@ pub struct Selectors {
     data_segment: SegmentSelector,  // user data segment selector
 }
 
-lazy_static! {
-    /// Defines the InterruptDescriptorTable and all the interruption handlers.
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        // create a new Global Descriptor Table
-        let mut gdt = GlobalDescriptorTable::new();
-
-        // Add the kernel code space and data space in the gdt
-        let code_selector = gdt.add_entry(Descriptor::SystemSegment(
-            gdt_entry::kernel_cs(),
-            gdt_entry::kernel_ds(),
-        ));
-        warningln!("kernel_cs : {}", gdt_entry::kernel_cs());
-
-        // Add the segments for user space in the table.
-        let code_segment = gdt.add_entry(Descriptor::UserSegment(gdt_entry::new_cs()));
-        let data_segment = gdt.add_entry(Descriptor::UserSegment(gdt_entry::new_ds()));
-
-        // Add the TSS in the Global descriptor table
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-                code_segment,
-                data_segment,
-            },
-        )
-    };
+/// Size of the stack used to handle double faults
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// Size of the stack used to handle a page fault, it may write a core file
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 8;
+
+/// Number of page faults the page fault stack can hold, each one below the one it interrupted
+const PAGE_FAULT_NESTING: usize = 4;
+
+/// TSS of each processor, by index in `smp`
+static mut CPU_TSS: [*mut TaskStateSegment; MAX_CPUS] = [core::ptr::null_mut(); MAX_CPUS];
+
+/// Top of the page fault stack of each processor
+static mut PAGE_FAULT_STACK_TOP: [u64; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Builds a GDT using the given TSS
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    // create a new Global Descriptor Table
+    let mut gdt = GlobalDescriptorTable::new();
+
+    // Add the kernel code space and data space in the gdt
+    let code_selector = gdt.add_entry(Descriptor::SystemSegment(
+        gdt_entry::kernel_cs(),
+        gdt_entry::kernel_ds(),
+    ));
+    warningln!("kernel_cs : {}", gdt_entry::kernel_cs());
+
+    // Add the segments for user space in the table.
+    let code_segment = gdt.add_entry(Descriptor::UserSegment(gdt_entry::new_cs()));
+    let data_segment = gdt.add_entry(Descriptor::UserSegment(gdt_entry::new_ds()));
+
+    // Add the TSS in the Global descriptor table
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+
+    (
+        gdt,
+        Selectors {
+            code_selector,
+            tss_selector,
+            code_segment,
+            data_segment,
+        },
+    )
 }
 
 lazy_static! {
-    /// Defines the InterruptDescriptorTable and all the interruption handlers
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            // Should be improved by using the frame allocator to have a minimal protection against memory corruption and stack overflow
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            stack_start + STACK_SIZE
-        };
-        tss
-    };
+    /// Defines the InterruptDescriptorTable and all the interruption handlers.
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(unsafe { &TSS });
 }
 
+/// TSS of the bootstrap processor, its stacks are set by `init`
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
 /// Initializes the GDT to be useless, and start the kernel.
 pub fn init() {
+    unsafe {
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+            // Should be improved by using the frame allocator to have a minimal protection against memory corruption and stack overflow
+            static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+            let stack_start = VirtAddr::from_ptr(&STACK);
+            stack_start + DOUBLE_FAULT_STACK_SIZE
+        };
+        TSS.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            static mut STACK: [u8; PAGE_FAULT_STACK_SIZE * PAGE_FAULT_NESTING] =
+                [0; PAGE_FAULT_STACK_SIZE * PAGE_FAULT_NESTING];
+            let stack_start = VirtAddr::from_ptr(&STACK);
+            stack_start + PAGE_FAULT_STACK_SIZE * PAGE_FAULT_NESTING
+        };
+        register(0, &mut TSS);
+    }
     GDT.0.load();
     unsafe {
         set_cs(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// Records the TSS of the processor `cpu`, to move its page fault stack
+unsafe fn register(cpu: usize, tss: *mut TaskStateSegment) {
+    CPU_TSS[cpu] = tss;
+    PAGE_FAULT_STACK_TOP[cpu] =
+        (*tss).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize].as_u64();
+}
+
+/// Allocates a stack that lives as long as the processor, and returns its top
+fn leak_stack(size: usize) -> VirtAddr {
+    let stack: &'static mut [u8] = Box::leak(vec![0; size].into_boxed_slice());
+    VirtAddr::from_ptr(stack.as_ptr()) + size
+}
+
+/// Gives its own GDT and TSS to an application processor, with its own stacks for double and page faults.
+/// They live as long as the processor, so they are leaked.
+pub fn init_ap(cpu: usize) {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+        leak_stack(DOUBLE_FAULT_STACK_SIZE);
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
+        leak_stack(PAGE_FAULT_STACK_SIZE * PAGE_FAULT_NESTING);
+    let tss: &'static mut TaskStateSegment = Box::leak(Box::new(tss));
+    unsafe {
+        register(cpu, tss);
+    }
+    let gdt: &'static (GlobalDescriptorTable, Selectors) = Box::leak(Box::new(new_gdt(tss)));
+    gdt.0.load();
+    unsafe {
+        set_cs(gdt.1.code_selector);
+        load_tss(gdt.1.tss_selector);
+    }
+}
+
+/// Page fault being handled by the running processor. While it lives, the page fault stack starts below it,
+/// so that a page fault raised by its handler, writing a core file, swapping or growing the heap,
+/// does not overwrite its frame.
+pub struct NestedPageFault {
+    tss: *mut TaskStateSegment,
+}
+
+impl NestedPageFault {
+    /// Lowers the page fault stack of the running processor below the page fault being handled.
+    /// Panics if the page faults are nested too deeply for the stack.
+    pub fn enter() -> Self {
+        let cpu = smp::cpu_id();
+        unsafe {
+            let tss = CPU_TSS[cpu];
+            let top = &mut (*tss).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize];
+            let bottom =
+                PAGE_FAULT_STACK_TOP[cpu] - (PAGE_FAULT_STACK_SIZE * PAGE_FAULT_NESTING) as u64;
+            if top.as_u64() - (PAGE_FAULT_STACK_SIZE as u64) <= bottom {
+                panic!("Page faults nested too deeply");
+            }
+            *top -= PAGE_FAULT_STACK_SIZE as u64;
+            Self { tss }
+        }
+    }
+}
+
+impl Drop for NestedPageFault {
+    fn drop(&mut self) {
+        unsafe {
+            (*self.tss).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] +=
+                PAGE_FAULT_STACK_SIZE as u64;
+        }
+    }
+}
+
+/// Gives back its whole page fault stack to the running processor.
+/// Called when it leaves the kernel for another context, the page faults it was handling are abandoned.
+pub fn reset_page_fault_stack() {
+    let cpu = smp::cpu_id();
+    unsafe {
+        if let Some(tss) = CPU_TSS[cpu].as_mut() {
+            tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] =
+                VirtAddr::new(PAGE_FAULT_STACK_TOP[cpu]);
+        }
+    }
+}
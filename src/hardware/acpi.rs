@@ -0,0 +1,136 @@
+//! Minimal reader of the ACPI tables, only used to list the processors in the MADT.
+
+use crate::memory::PHYSICAL_OFFSET;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_unaligned;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+/// Where the BIOS writes the segment of the extended BIOS data area
+const EBDA_POINTER: u64 = 0x40E;
+/// The RSDP is in the first KiB of the EBDA or in the read-only memory of the BIOS
+const EBDA_SEARCH_LENGTH: u64 = 1024;
+const BIOS_AREA_START: u64 = 0xE0000;
+const BIOS_AREA_END: u64 = 0x100000;
+/// Size of the part of the RSDP covered by the checksum of the first version
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Type of the MADT entries describing a processor and its local APIC
+const MADT_LOCAL_APIC: u8 = 0;
+const PROCESSOR_ENABLED: u32 = 1;
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header shared by every system description table
+#[repr(C, packed)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// A processor found in the MADT
+#[derive(Clone, Copy, Debug)]
+pub struct Processor {
+    pub acpi_id: u8,
+    pub apic_id: u8,
+}
+
+unsafe fn read<T>(physical: u64) -> T {
+    read_unaligned((physical + PHYSICAL_OFFSET) as *const T)
+}
+
+/// Returns true iff the bytes of the given physical area sum to 0
+unsafe fn checksum(physical: u64, length: usize) -> bool {
+    (0..length as u64).fold(0_u8, |sum, i| sum.wrapping_add(read::<u8>(physical + i))) == 0
+}
+
+/// Returns the physical address of the RSDP
+unsafe fn find_rsdp() -> Option<u64> {
+    let ebda = (read::<u16>(EBDA_POINTER) as u64) << 4;
+    let areas = [
+        (ebda, ebda + EBDA_SEARCH_LENGTH),
+        (BIOS_AREA_START, BIOS_AREA_END),
+    ];
+    for (start, end) in areas.iter() {
+        for address in (*start..*end).step_by(16) {
+            if &read::<[u8; 8]>(address) == RSDP_SIGNATURE && checksum(address, RSDP_V1_LENGTH) {
+                return Some(address);
+            }
+        }
+    }
+    None
+}
+
+/// Returns the physical address of the table with the given signature, using the XSDT if there is one
+unsafe fn find_table(signature: &[u8; 4]) -> Option<u64> {
+    let rsdp = read::<Rsdp>(find_rsdp()?);
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, size_of::<u64>())
+    } else {
+        (rsdp.rsdt_address as u64, size_of::<u32>())
+    };
+    let header = read::<SdtHeader>(root);
+    let entries = (header.length as usize - size_of::<SdtHeader>()) / entry_size;
+    for i in 0..entries {
+        let entry = root + (size_of::<SdtHeader>() + i * entry_size) as u64;
+        let table = if entry_size == size_of::<u64>() {
+            read::<u64>(entry)
+        } else {
+            read::<u32>(entry) as u64
+        };
+        let table_header = read::<SdtHeader>(table);
+        if &table_header.signature == signature && checksum(table, table_header.length as usize) {
+            return Some(table);
+        }
+    }
+    None
+}
+
+/// Returns the enabled processors listed in the MADT, or None if the ACPI tables were not found.
+/// # Safety
+/// Needs the physical memory to be mapped at `PHYSICAL_OFFSET`
+pub unsafe fn processors() -> Option<Vec<Processor>> {
+    let madt = find_table(MADT_SIGNATURE)?;
+    let end = madt + read::<SdtHeader>(madt).length as u64;
+    // The header is followed by the address of the local APICs and some flags
+    let mut entry = madt + size_of::<SdtHeader>() as u64 + 8;
+    let mut processors = Vec::new();
+    while entry + 2 <= end {
+        let entry_type = read::<u8>(entry);
+        let length = read::<u8>(entry + 1) as u64;
+        if length < 2 {
+            break;
+        }
+        if entry_type == MADT_LOCAL_APIC && length >= 8 {
+            let flags = read::<u32>(entry + 4);
+            if flags & PROCESSOR_ENABLED != 0 {
+                processors.push(Processor {
+                    acpi_id: read::<u8>(entry + 2),
+                    apic_id: read::<u8>(entry + 3),
+                });
+            }
+        }
+        entry += length;
+    }
+    Some(processors)
+}
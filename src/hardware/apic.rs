@@ -0,0 +1,222 @@
+//! Local APIC of the processor, used as the timer of the scheduler.
+//!
+//! Its timer is calibrated against the channel 2 of the PIT, so that a tick lasts as long as with the PIT.
+//! It raises the same vector as the PIT did, which is then masked, so the timer handler stays the same.
+
+use super::timer;
+use crate::interrupts::InterruptIndex;
+use crate::memory::PHYSICAL_OFFSET;
+use core::arch::x86_64::__cpuid;
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+const APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_MASK: u64 = 0xFFFF_F000;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+/// Bit of `edx` telling that the CPU has a local APIC, in the leaf 1 of `cpuid`
+const CPUID_APIC: u32 = 1 << 9;
+
+// Offsets of the registers
+const ID: u64 = 0x20;
+const EOI: u64 = 0xB0;
+const SPURIOUS_VECTOR: u64 = 0xF0;
+const LVT_TIMER: u64 = 0x320;
+const INITIAL_COUNT: u64 = 0x380;
+const CURRENT_COUNT: u64 = 0x390;
+const DIVIDE_CONFIGURATION: u64 = 0x3E0;
+const INTERRUPT_COMMAND_LOW: u64 = 0x300;
+const INTERRUPT_COMMAND_HIGH: u64 = 0x310;
+
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+const TIMER_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const DIVIDE_BY_16: u32 = 0b0011;
+
+// Inter-processor interrupts
+const DELIVERY_INIT: u32 = 0b101 << 8;
+const DELIVERY_STARTUP: u32 = 0b110 << 8;
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+const DESTINATION_SHIFT: u32 = 24;
+
+/// Virtual address of the registers, `None` while the local APIC is not used
+static mut APIC_ADDRESS: Option<u64> = None;
+
+/// Number of bus cycles (divided by 16) in a tick
+static mut TICK_COUNT: u32 = 0;
+
+unsafe fn read(address: u64, register: u64) -> u32 {
+    core::ptr::read_volatile((address + register) as *const u32)
+}
+
+unsafe fn write(address: u64, register: u64, value: u32) {
+    core::ptr::write_volatile((address + register) as *mut u32, value)
+}
+
+/// Returns true iff the local APIC drives the timer
+pub fn is_enabled() -> bool {
+    unsafe { APIC_ADDRESS.is_some() }
+}
+
+/// Acknowledges the interrupt being handled
+/// # Safety
+/// Needs to be called at the end of an interrupt raised by the local APIC
+pub unsafe fn end_of_interrupt() {
+    if let Some(address) = APIC_ADDRESS {
+        write(address, EOI, 0);
+    }
+}
+
+/// Number of bus cycles (divided by 16) in a tick, 0 if the local APIC is not used
+pub fn tick_count() -> u32 {
+    unsafe { TICK_COUNT }
+}
+
+/// Returns the id of the local APIC of the current processor, 0 if the local APIC is not used
+pub fn id() -> u8 {
+    unsafe {
+        match APIC_ADDRESS {
+            Some(address) => (read(address, ID) >> DESTINATION_SHIFT) as u8,
+            None => 0,
+        }
+    }
+}
+
+/// Sends an inter-processor interrupt to the processor of the local APIC `apic_id`
+/// # Safety
+/// `command` has to be a valid low half of the interrupt command register
+unsafe fn send(apic_id: u8, command: u32) {
+    if let Some(address) = APIC_ADDRESS {
+        write(
+            address,
+            INTERRUPT_COMMAND_HIGH,
+            (apic_id as u32) << DESTINATION_SHIFT,
+        );
+        write(address, INTERRUPT_COMMAND_LOW, command);
+        while read(address, INTERRUPT_COMMAND_LOW) & DELIVERY_PENDING != 0 {}
+    }
+}
+
+/// Raises the interrupt `vector` on the processor of the local APIC `apic_id`
+/// # Safety
+/// The vector must have a handler on the target
+pub unsafe fn send_ipi(apic_id: u8, vector: u8) {
+    send(apic_id, vector as u32);
+}
+
+/// Resets the processor of the local APIC `apic_id`, which then waits for a startup IPI
+/// # Safety
+/// The target must not be running anything
+pub unsafe fn send_init(apic_id: u8) {
+    send(apic_id, DELIVERY_INIT | LEVEL_ASSERT);
+}
+
+/// Starts the processor of the local APIC `apic_id` in real mode at the address `page * 0x1000`
+/// # Safety
+/// There must be code to run at this address
+pub unsafe fn send_startup(apic_id: u8, page: u8) {
+    send(apic_id, DELIVERY_STARTUP | page as u32);
+}
+
+/// Enables the local APIC at `address` and starts its timer, to raise the timer vector every `count` cycles
+unsafe fn start(address: u64, count: u32) {
+    write(
+        address,
+        SPURIOUS_VECTOR,
+        SOFTWARE_ENABLE | InterruptIndex::ApicSpurious.as_u8() as u32,
+    );
+    write(address, DIVIDE_CONFIGURATION, DIVIDE_BY_16);
+    write(
+        address,
+        LVT_TIMER,
+        TIMER_PERIODIC | InterruptIndex::Timer.as_u8() as u32,
+    );
+    write(address, INITIAL_COUNT, count);
+}
+
+/// Enables the local APIC of an application processor, its timer ticking at the same rate as the bootstrap one.
+/// # Safety
+/// Needs to be called once per application processor, after `init` succeeded on the bootstrap one
+pub unsafe fn init_ap() {
+    if let Some(address) = APIC_ADDRESS {
+        let mut base = Msr::new(APIC_BASE_MSR);
+        let value = base.read();
+        base.write(value | APIC_GLOBAL_ENABLE);
+        start(address, TICK_COUNT);
+    }
+}
+
+/// Maps the registers of the local APIC right after the physical memory, uncached.
+/// They are shared by every address space because the level 4 tables copy the kernel's.
+unsafe fn map(
+    physical: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Option<u64> {
+    let address = physical + PHYSICAL_OFFSET;
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(address));
+    let frame = PhysFrame::containing_address(PhysAddr::new(physical));
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH;
+    match mapper.map_to(page, frame, flags, frame_allocator) {
+        Ok(flush) => {
+            flush.flush();
+            Some(address)
+        }
+        // The bootloader already mapped it with the physical memory
+        Err(MapToError::PageAlreadyMapped(_)) | Err(MapToError::ParentEntryHugePage) => {
+            Some(address)
+        }
+        Err(MapToError::FrameAllocationFailed) => None,
+    }
+}
+
+/// Enables the local APIC and makes its timer drive the scheduler instead of the PIT.
+/// Returns false if there is no local APIC, in which case the PIT stays the timer.
+/// # Safety
+/// Needs to be called once, after the initialisation of the interrupts and before the first process is launched
+pub unsafe fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> bool {
+    if __cpuid(1).edx & CPUID_APIC == 0 {
+        return false;
+    }
+    let mut base = Msr::new(APIC_BASE_MSR);
+    let value = base.read();
+    let address = match map(value & APIC_BASE_MASK, mapper, frame_allocator) {
+        Some(address) => address,
+        None => return false,
+    };
+    base.write(value | APIC_GLOBAL_ENABLE);
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        write(
+            address,
+            SPURIOUS_VECTOR,
+            SOFTWARE_ENABLE | InterruptIndex::ApicSpurious.as_u8() as u32,
+        );
+
+        // Counts down during one tick of the PIT
+        write(address, DIVIDE_CONFIGURATION, DIVIDE_BY_16);
+        write(address, LVT_TIMER, TIMER_MASKED);
+        write(address, INITIAL_COUNT, u32::MAX);
+        timer::wait_cycles(timer::TIMER_DIVISOR);
+        let count = u32::MAX - read(address, CURRENT_COUNT);
+        write(address, INITIAL_COUNT, 0);
+        if count == 0 {
+            return false;
+        }
+
+        timer::disable();
+        TICK_COUNT = count;
+        APIC_ADDRESS = Some(address);
+        start(address, count);
+        true
+    })
+}
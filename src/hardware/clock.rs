@@ -1,3 +1,5 @@
+use super::timer;
+use core::arch::x86_64::_rdtsc;
 use x86_64::instructions::port::Port;
 
 const CMOS_ADDRESS: u16 = 0x70;
@@ -104,4 +106,95 @@ impl Time {
             year: year + 100 * (century - 1),
         }
     }
+
+    /// Number of seconds since the 1st of January 1970, the RTC being in UTC
+    pub fn to_unix(&self) -> u64 {
+        // Years start in March so that the leap day is the last one
+        let month = self.month.max(1) as u64;
+        let year = self.year as u64 - if month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + self.day.max(1) as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * 146_097 + day_of_era).saturating_sub(UNIX_EPOCH_DAYS);
+        ((days * 24 + self.hour as u64) * 60 + self.minute as u64) * 60 + self.second as u64
+    }
+}
+
+/// Days between the 1st of March of the year 0 and the 1st of January 1970
+const UNIX_EPOCH_DAYS: u64 = 719_468;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Clocks that can be read with `clock_gettime`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockId {
+    /// Time since the 1st of January 1970
+    Realtime = 0,
+    /// Time since boot, it never goes backwards
+    Monotonic = 1,
+}
+
+impl ClockId {
+    pub fn from_u64(clock: u64) -> Option<Self> {
+        match clock {
+            0 => Some(Self::Realtime),
+            1 => Some(Self::Monotonic),
+            _ => None,
+        }
+    }
+}
+
+/// Frequency of the time-stamp counter, in Hz. 0 until `init` is called
+static mut TSC_FREQUENCY: u64 = 0;
+
+/// Time-stamp counter at boot
+static mut BOOT_TSC: u64 = 0;
+
+/// Real time at boot, in nanoseconds since the 1st of January 1970
+static mut BOOT_REALTIME: u64 = 0;
+
+/// Calibrates the time-stamp counter against the PIT and seeds the real time clock from the RTC.
+/// # Safety
+/// Needs to be called once at boot, with the interrupts disabled
+pub unsafe fn init() {
+    let start = _rdtsc();
+    timer::wait_cycles(timer::TIMER_DIVISOR);
+    let cycles = _rdtsc().wrapping_sub(start);
+    TSC_FREQUENCY = cycles * timer::PIT_FREQUENCY / timer::TIMER_DIVISOR as u64;
+    BOOT_TSC = start;
+    BOOT_REALTIME = Time::get().to_unix() * NANOSECONDS_PER_SECOND;
+    BOOT_REALTIME = BOOT_REALTIME.saturating_sub(monotonic());
+}
+
+/// Returns the frequency of the time-stamp counter in Hz, or 0 if it was not calibrated
+pub fn tsc_frequency() -> u64 {
+    unsafe { TSC_FREQUENCY }
+}
+
+/// Nanoseconds elapsed since boot
+pub fn monotonic() -> u64 {
+    unsafe {
+        if TSC_FREQUENCY == 0 {
+            return 0;
+        }
+        let cycles = _rdtsc().wrapping_sub(BOOT_TSC) as u128;
+        (cycles * NANOSECONDS_PER_SECOND as u128 / TSC_FREQUENCY as u128) as u64
+    }
+}
+
+/// Nanoseconds elapsed since the 1st of January 1970
+pub fn realtime() -> u64 {
+    unsafe { BOOT_REALTIME + monotonic() }
+}
+
+/// Reads `clock`.
+/// Returns : (seconds, nanoseconds)
+pub fn get_time(clock: ClockId) -> (u64, u64) {
+    let time = match clock {
+        ClockId::Realtime => realtime(),
+        ClockId::Monotonic => monotonic(),
+    };
+    (time / NANOSECONDS_PER_SECOND, time % NANOSECONDS_PER_SECOND)
 }
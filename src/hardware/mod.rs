@@ -1,5 +1,7 @@
 //! Some basic hardware drivers for use from within the kernel only.
 
+pub mod acpi;
+pub mod apic;
 pub mod clock;
 pub mod mouse;
 pub mod power;
@@ -1,5 +1,24 @@
 use x86_64::instructions::port::Port;
 
+/// Frequency of the oscillator of the PIT, in Hz
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// Divisor given to the PIT, it sets the length of a tick of the scheduler
+pub const TIMER_DIVISOR: u16 = 0x8000;
+
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+/// Keyboard controller port B, holding the gate and the output of the channel 2
+const GATE_PORT: u16 = 0x61;
+/// Data port of the master PIC, holding its mask
+const PIC_1_DATA_PORT: u16 = 0x21;
+
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count)
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+const GATE_ENABLE: u8 = 0x01;
+const SPEAKER_ENABLE: u8 = 0x02;
+const CHANNEL_2_OUTPUT: u8 = 0x20;
+
 /// # Safety
 /// TODO
 pub unsafe fn set_timer(freq: u16) {
@@ -7,3 +26,29 @@ pub unsafe fn set_timer(freq: u16) {
     port.write((freq & 0xFF) as u8);
     port.write((freq >> 8) as u8)
 }
+
+/// Busy-waits for `cycles` cycles of the PIT, using its channel 2 with the speaker off.
+/// It is used to calibrate the other clocks, so interrupts should be disabled.
+/// # Safety
+/// Changes the state of the speaker
+pub unsafe fn wait_cycles(cycles: u16) {
+    let mut gate = Port::<u8>::new(GATE_PORT);
+    let mut command = Port::<u8>::new(COMMAND_PORT);
+    let mut channel_2 = Port::<u8>::new(CHANNEL_2_PORT);
+    let value = gate.read() & !SPEAKER_ENABLE;
+    gate.write(value & !GATE_ENABLE);
+    command.write(CHANNEL_2_ONE_SHOT);
+    channel_2.write((cycles & 0xFF) as u8);
+    channel_2.write((cycles >> 8) as u8);
+    gate.write(value | GATE_ENABLE);
+    while gate.read() & CHANNEL_2_OUTPUT == 0 {}
+}
+
+/// Masks the IRQ of the PIT, once another timer drives the scheduler.
+/// # Safety
+/// Needs to be called after the initialisation of the PIC
+pub unsafe fn disable() {
+    let mut mask = Port::<u8>::new(PIC_1_DATA_PORT);
+    let value = mask.read();
+    mask.write(value | 1);
+}
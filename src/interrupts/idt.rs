@@ -22,36 +22,40 @@ const SYSCALL_POSITION: usize = 0x80;
 const SYSCALL_POSITION_1: usize = 0x7E;
 const SYSCALL_POSITION_2: usize = 0x81;
 
+/// Position of the inter-processor interrupt asking a processor to reschedule, right before the syscall
+pub const RESCHEDULE_POSITION: usize = 0x7F;
+
 /// Interrupt Descriptor Table : table to store every interrupts handler
 #[repr(C)]
 #[repr(align(16))]
 pub struct Idt {
-    pub divide_error: Entry<HandlerFunc>,
+    pub divide_error: Entry<NakedCHandler>,
     pub debug: Entry<HandlerFunc>,
     pub non_maskable_interrupt: Entry<HandlerFunc>,
-    pub breakpoint: Entry<HandlerFunc>,
-    pub overflow: Entry<HandlerFunc>,
-    pub bound_range_exceeded: Entry<HandlerFunc>,
-    pub invalid_opcode: Entry<HandlerFunc>,
-    pub device_not_available: Entry<HandlerFunc>,
+    pub breakpoint: Entry<NakedCHandler>,
+    pub overflow: Entry<NakedCHandler>,
+    pub bound_range_exceeded: Entry<NakedCHandler>,
+    pub invalid_opcode: Entry<NakedCHandler>,
+    pub device_not_available: Entry<NakedCHandler>,
     pub double_fault: Entry<DivergingFuncWithErrorCode>,
     interrupt_09: Entry<HandlerFunc>,
-    pub invalid_tss: Entry<HandlerFuncWithErrorCode>,
-    pub segment_not_present: Entry<HandlerFuncWithErrorCode>,
-    pub stack_segment_fault: Entry<HandlerFuncWithErrorCode>,
-    pub general_protection_fault: Entry<HandlerFuncWithErrorCode>,
-    pub page_fault: Entry<PageFaultHandler>,
+    pub invalid_tss: Entry<NakedCHandler>,
+    pub segment_not_present: Entry<NakedCHandler>,
+    pub stack_segment_fault: Entry<NakedCHandler>,
+    pub general_protection_fault: Entry<NakedCHandler>,
+    pub page_fault: Entry<NakedCHandler>,
     interrupt_15: Entry<HandlerFunc>, // reserved
-    pub x87_floating_point: Entry<HandlerFunc>,
-    pub alignment_check: Entry<HandlerFuncWithErrorCode>,
+    pub x87_floating_point: Entry<NakedCHandler>,
+    pub alignment_check: Entry<NakedCHandler>,
     pub machine_check: Entry<DivergingFunc>,
-    pub simd_floating_point: Entry<HandlerFunc>,
-    pub virtualization: Entry<HandlerFunc>,
+    pub simd_floating_point: Entry<NakedCHandler>,
+    pub virtualization: Entry<NakedCHandler>,
     reserved_21_29: [Entry<HandlerFunc>; 9], // reserved
-    pub security_exception: Entry<HandlerFuncWithErrorCode>,
+    pub security_exception: Entry<NakedCHandler>,
     interrupt_31: Entry<HandlerFunc>, // reserved
     pub timer: Entry<NakedCHandler>,
-    pub interrupt_33_: [Entry<HandlerFunc>; SYSCALL_POSITION - 33],
+    pub interrupt_33_: [Entry<HandlerFunc>; RESCHEDULE_POSITION - 33],
+    pub reschedule: Entry<NakedCHandler>,
     pub syscall: Entry<SyscallFunc>,
     pub interrupt_post_syscall_: [Entry<HandlerFunc>; 255 - SYSCALL_POSITION],
 }
@@ -85,7 +89,8 @@ impl Idt {
             security_exception: Entry::missing(),
             interrupt_31: Entry::missing(),
             timer: Entry::missing(),
-            interrupt_33_: [Entry::missing(); SYSCALL_POSITION - 33],
+            interrupt_33_: [Entry::missing(); RESCHEDULE_POSITION - 33],
+            reschedule: Entry::missing(),
             syscall: Entry::missing(),
             interrupt_post_syscall_: [Entry::missing(); 255 - SYSCALL_POSITION],
         }
@@ -136,6 +141,7 @@ impl Index<usize> for Idt {
             31 => panic!("access not allowed! It is reserved"),
             32 => panic!("wrong function type"),
             i @ 33..=SYSCALL_POSITION_1 => &self.interrupt_33_[i - 33],
+            RESCHEDULE_POSITION => panic!("wrong function type"),
             SYSCALL_POSITION => panic!("wrong function type"),
             i @ SYSCALL_POSITION_2..=255 => &self.interrupt_post_syscall_[i - SYSCALL_POSITION - 1],
             _i => panic!("no such entry"),
@@ -170,6 +176,7 @@ impl IndexMut<usize> for Idt {
             31 => panic!("access not allowed! It is reserved"),
             32 => panic!("wrong function type"),
             i @ 33..=SYSCALL_POSITION_1 => &mut self.interrupt_33_[i - 33],
+            RESCHEDULE_POSITION => panic!("wrong function type"),
             SYSCALL_POSITION => panic!("wrong function type"),
             i @ SYSCALL_POSITION_2..=255 => {
                 &mut self.interrupt_post_syscall_[i - SYSCALL_POSITION - 1]
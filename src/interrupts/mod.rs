@@ -10,16 +10,17 @@ use x86_64::registers::control::{Cr2, Cr3};
 use x86_64::PrivilegeLevel;
 use x86_64::VirtAddr;
 
-use crate::scheduler::QUANTUM;
-
 pub mod idt;
 use idt::Idt as InterruptDescriptorTable;
 use idt::{InterruptStackFrame, PageFaultErrorCode};
 
 use crate::data_storage::registers::Registers;
 use crate::gdt;
-use crate::scheduler::process;
-use crate::sound;
+use crate::hardware;
+use crate::memory;
+use crate::scheduler::process::{self, regions::Fault};
+use crate::scheduler::wait_queue;
+use crate::smp::{self, MAX_CPUS};
 use crate::{bsod, errorln, warningln};
 use lazy_static::lazy_static;
 use pic8259_simple::ChainedPics;
@@ -28,7 +29,16 @@ mod syscalls;
 
 use syscalls::syscall_dispatch;
 
-static mut COUNTER: u64 = 0;
+/// Number of ticks the current process of each processor ran since it was switched in
+static mut COUNTER: [u64; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Inter-processor interrupt asking a processor to pick a new process
+pub const RESCHEDULE_VECTOR: u8 = idt::RESCHEDULE_POSITION as u8;
+
+/// Returns the number of ticks of the current process of this processor, and resets it
+fn take_counter() -> u64 {
+    unsafe { core::mem::take(&mut COUNTER[smp::cpu_id()]) }
+}
 
 #[derive(Clone, Debug, Copy)]
 #[repr(u8)]
@@ -37,10 +47,12 @@ pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
     Mouse = 12 + PIC_1_OFFSET,
+    /// Raised by the local APIC when an interrupt vanishes before being acknowledged
+    ApicSpurious = 0xFF,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
     fn as_usize(self) -> usize {
@@ -53,10 +65,15 @@ pub fn is_kernel_space(address: VirtAddr) -> bool {
 }
 
 macro_rules! new_process {
+    ($code: expr, $stack_frame: expr, $registers: expr) => {
+        core::mem::forget(smp::lock::acquire());
+        process::coredump::dump($code, &$stack_frame.as_real(), Some(&*$registers));
+        new_process!($code)
+    };
     ($code: expr) => {
         unsafe {
-            let new = process::process_died(COUNTER, $code); // TODO fetch return code
-            COUNTER = 0;
+            core::mem::forget(smp::lock::acquire());
+            let new = process::process_died(take_counter(), $code); // TODO fetch return code
             process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
         }
     };
@@ -87,10 +104,12 @@ macro_rules! saveRegisters {
                 "push r10",
                 "push r8",
                 "push r9",
+                "call {1}",
                 "mov rsi, rsp",
                 "mov rdi, rsp",
                 "add rdi, 15*8 + 32",
                 "call {0}",
+                "call {2}",
                 "pop r9",
                 "pop r8",
                 "pop r10",
@@ -111,6 +130,82 @@ macro_rules! saveRegisters {
                 "sti",
                 "iretq",
                 sym $name,
+                sym $crate::smp::lock::enter_kernel,
+                sym $crate::smp::lock::exit_kernel,
+                options(noreturn)
+                );
+            }
+        }
+        wrapper
+    }};
+}
+
+/// Like `saveRegisters!`, for the exceptions. `$name` also gets the error code pushed by the processor,
+/// 0 for the exceptions that push none, and the registers are the ones of the code that faulted,
+/// so that they can go into its core file, see `process::coredump`.
+#[macro_export]
+macro_rules! saveRegistersException {
+    ($name: ident) => {
+        $crate::saveRegistersException!($name, "push 0")
+    };
+    ($name: ident, error_code) => {
+        $crate::saveRegistersException!($name, "")
+    };
+    ($name: ident, $push: literal) => {{
+        #[naked]
+        extern "C" fn wrapper() {
+            unsafe {
+                asm!(
+                "cli",
+                $push,
+                // The 8 more bytes keep the stack aligned for the calls
+                "sub rsp, 40",
+                "vmovupd [rsp], ymm0",
+                "push r15",
+                "push r14",
+                "push r13",
+                "push r12",
+                "push r11",
+                "push rbp",
+                "push rcx",
+                "push rbx",
+                "push rax",
+                "push rdi",
+                "push rsi",
+                "push rdx",
+                "push r10",
+                "push r8",
+                "push r9",
+                "call {1}",
+                "mov rsi, rsp",
+                "mov rdx, [rsp + 15*8 + 40]",
+                "mov rdi, rsp",
+                "add rdi, 15*8 + 40 + 8",
+                "call {0}",
+                "call {2}",
+                "pop r9",
+                "pop r8",
+                "pop r10",
+                "pop rdx",
+                "pop rsi",
+                "pop rdi",
+                "pop rax",
+                "pop rbx",
+                "pop rcx",
+                "pop rbp",
+                "pop r11",
+                "pop r12",
+                "pop r13",
+                "pop r14",
+                "pop r15",
+                "vmovupd ymm0, [rsp]",
+                // Also drops the error code
+                "add rsp, 40 + 8",
+                "sti",
+                "iretq",
+                sym $name,
+                sym $crate::smp::lock::enter_kernel,
+                sym $crate::smp::lock::exit_kernel,
                 options(noreturn)
                 );
             }
@@ -123,63 +218,66 @@ lazy_static! {
     /// Defines the InterruptDescriptorTable and all the interruption handlers.
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.divide_error.set_handler_fn(divide_error_handler)
+        idt.divide_error.set_handler_fn(saveRegistersException!(divide_error_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.debug.set_handler_fn(debug_handler)
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.non_maskable_interrupt
             .set_handler_fn(non_maskable_interrupt_handler)
             .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.breakpoint.set_handler_fn(breakpoint_handler)
+        idt.breakpoint.set_handler_fn(saveRegistersException!(breakpoint_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.overflow.set_handler_fn(overflow_handler)
+        idt.overflow.set_handler_fn(saveRegistersException!(overflow_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.bound_range_exceeded
-            .set_handler_fn(bound_range_exceeded_handler)
+            .set_handler_fn(saveRegistersException!(bound_range_exceeded_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler)
+        idt.invalid_opcode.set_handler_fn(saveRegistersException!(invalid_opcode_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.device_not_available
-            .set_handler_fn(device_not_available_handler)
+            .set_handler_fn(saveRegistersException!(device_not_available_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.invalid_tss.set_handler_fn(invalid_tss_handler)
+        idt.invalid_tss.set_handler_fn(saveRegistersException!(invalid_tss_handler, error_code))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.segment_not_present
-            .set_handler_fn(segment_not_present_handler)
+            .set_handler_fn(saveRegistersException!(segment_not_present_handler, error_code))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.stack_segment_fault
-            .set_handler_fn(stack_segment_fault_handler)
+            .set_handler_fn(saveRegistersException!(stack_segment_fault_handler, error_code))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.general_protection_fault
-            .set_handler_fn(general_protection_fault_handler)
-            .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.page_fault.set_handler_fn(page_fault_handler)
+            .set_handler_fn(saveRegistersException!(general_protection_fault_handler, error_code))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.x87_floating_point
-            .set_handler_fn(x87_floating_point_handler)
+            .set_handler_fn(saveRegistersException!(x87_floating_point_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.alignment_check.set_handler_fn(alignment_check_handler)
+        idt.alignment_check.set_handler_fn(saveRegistersException!(alignment_check_handler, error_code))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.simd_floating_point
-            .set_handler_fn(simd_floating_point_handler)
+            .set_handler_fn(saveRegistersException!(simd_floating_point_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
-        idt.virtualization.set_handler_fn(virtualization_handler)
+        idt.virtualization.set_handler_fn(saveRegistersException!(virtualization_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.security_exception
-            .set_handler_fn(security_exception_handler)
+            .set_handler_fn(saveRegistersException!(security_exception_handler, error_code))
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt.timer.set_handler_fn(saveRegisters!(timer_interrupt_handler))
             .set_privilege_level(PrivilegeLevel::Ring3);
+        idt.reschedule.set_handler_fn(saveRegisters!(reschedule_interrupt_handler));
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler)
             .set_privilege_level(PrivilegeLevel::Ring3);
         idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler)
             .set_privilege_level(PrivilegeLevel::Ring3);
+        idt[InterruptIndex::ApicSpurious.as_usize()].set_handler_fn(spurious_interrupt_handler);
         idt.syscall.set_handler_fn(saveRegisters!(syscall_dispatch))
                     .set_privilege_level(PrivilegeLevel::Ring3);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+            idt.page_fault.set_handler_fn(saveRegistersException!(page_fault_handler, error_code))
+                .set_privilege_level(PrivilegeLevel::Ring3)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
         }
         idt
     };
@@ -198,9 +296,18 @@ pub fn init() {
     x86_64::instructions::interrupts::enable();
 }
 
-extern "x86-interrupt" fn divide_error_handler(_stack_frame: &mut InterruptStackFrame) {
+/// Loads the IDT on an application processor. Its interrupts are enabled once it runs its idle task.
+pub fn init_ap() {
+    IDT.load();
+}
+
+extern "C" fn divide_error_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("div 0");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 } // Rust catches this before the CPU, but it's a safeguard for asm/extern code.
 
 // probably would not need to panic ?
@@ -214,29 +321,49 @@ extern "x86-interrupt" fn non_maskable_interrupt_handler(_stack_frame: &mut Inte
     new_process!(3);
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
+extern "C" fn breakpoint_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("BREAKPOINT : {:#?}", stack_frame);
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn overflow_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn overflow_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("overflow");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn bound_range_exceeded_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn bound_range_exceeded_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("bound range");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn invalid_opcode_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn invalid_opcode_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("opcode");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn device_not_available_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn device_not_available_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("device");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
 extern "x86-interrupt" fn double_fault_handler(
@@ -249,69 +376,87 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION : DOUBLE FAULT : \n {:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn invalid_tss_handler(
-    _stack_frame: &mut InterruptStackFrame,
+extern "C" fn invalid_tss_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
     _error_code: u64,
 ) {
     errorln!("tss");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn segment_not_present_handler(
-    _stack_frame: &mut InterruptStackFrame,
+extern "C" fn segment_not_present_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
     error_code: u64,
 ) {
     errorln!("segment {}", error_code);
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn stack_segment_fault_handler(
-    _stack_frame: &mut InterruptStackFrame,
+extern "C" fn stack_segment_fault_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
     _error_code: u64,
 ) {
     errorln!("stack");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn general_protection_fault_handler(
-    _stack_frame: &mut InterruptStackFrame,
+extern "C" fn general_protection_fault_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
     error_code: u64,
 ) {
     bsod!("TRIED TO READ : {:#?}", Cr2::read());
     bsod!("CR3 : {:#?}", Cr3::read());
     bsod!("ERROR : {:#?}", error_code);
-    new_process!(11);
+    new_process!(11, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn x87_floating_point_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn x87_floating_point_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("x87 floating point handler");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn alignment_check_handler(
-    _stack_frame: &mut InterruptStackFrame,
+extern "C" fn alignment_check_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
     _error_code: u64,
 ) {
     errorln!("alignement");
-    new_process!(11);
+    new_process!(11, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn simd_floating_point_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn simd_floating_point_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("simd");
-    new_process!(3);
+    new_process!(3, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn virtualization_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "C" fn virtualization_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+    _error_code: u64,
+) {
     errorln!("virtualization");
-    new_process!(11);
+    new_process!(11, stack_frame, registers);
 }
 
-extern "x86-interrupt" fn security_exception_handler(
-    _stack_frame: &mut InterruptStackFrame,
+extern "C" fn security_exception_handler(
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
     _error_code: u64,
 ) {
     errorln!("security");
-    new_process!(11);
+    new_process!(11, stack_frame, registers);
 }
 
 // Should be entirely rewritten for multi-process handling
@@ -320,63 +465,146 @@ unsafe extern "C" fn timer_interrupt_handler(
     stack_frame: &mut InterruptStackFrame,
     registers: &mut Registers,
 ) {
-    sound::handle();
-
-    if COUNTER == QUANTUM {
-        COUNTER = 0;
+    // The time of the system is counted by the bootstrap processor only
+    if smp::cpu_id() == 0 {
+        wait_queue::tick();
+    }
 
+    if process::tick(is_kernel_space(stack_frame.as_real().instruction_pointer)) {
         let _stack_frame_2 = stack_frame.as_mut();
+        end_of_timer_interrupt();
+        switch_process(registers);
+    } else {
+        COUNTER[smp::cpu_id()] += 1;
+    }
 
-        let (next, mut old) = process::gives_switch(QUANTUM);
+    end_of_timer_interrupt();
+}
+
+/// Gives the processor to the next process, `registers` being the ones saved on the stack of the current one.
+#[allow(clippy::empty_loop, unreachable_code)]
+unsafe fn switch_process(registers: &mut Registers) -> ! {
+    let (next, mut old) = process::gives_switch(take_counter() + 1);
+
+    let (cr3, cr3f) = Cr3::read();
+    old.cr3 = cr3.start_address();
+    old.cr3f = cr3f;
+
+    old.rsp = VirtAddr::from_ptr(registers).as_u64();
 
-        let (cr3, cr3f) = Cr3::read();
-        old.cr3 = cr3.start_address();
-        old.cr3f = cr3f;
+    process::leave_context_cr3(next.cr3.as_u64() | next.cr3f.bits(), next.rsp);
+    loop {}
+}
 
-        old.rsp = VirtAddr::from_ptr(registers).as_u64();
+/// Inter-processor interrupt sent when a process was given to this processor.
+/// It switches to it right away if the processor was idle.
+unsafe extern "C" fn reschedule_interrupt_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+) {
+    hardware::apic::end_of_interrupt();
+    if process::is_idle(process::get_current().get_pid()) {
+        switch_process(registers);
+    }
+}
 
+/// Acknowledges the timer interrupt to the local APIC or to the PIC, depending on which one raised it.
+unsafe fn end_of_timer_interrupt() {
+    if hardware::apic::is_enabled() {
+        hardware::apic::end_of_interrupt();
+    } else {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-        process::leave_context_cr3(next.cr3.as_u64() | next.cr3f.bits(), next.rsp);
-        loop {}
-    } else {
-        COUNTER += 1;
     }
-
-    PICS.lock()
-        .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
 }
 
 /// Page fault handler, should verify wether killing the current process or allocating a new page !
-extern "x86-interrupt" fn page_fault_handler(
+extern "C" fn page_fault_handler(
     stack_frame: &mut InterruptStackFrame,
-    error_code: PageFaultErrorCode,
+    registers: &mut Registers,
+    error_code: u64,
 ) {
+    // The handler may fault again, below this frame
+    let _nested = gdt::NestedPageFault::enter();
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
     let read_addr = Cr2::read();
     if read_addr.as_u64() == 0x42 && error_code == PageFaultErrorCode::INSTRUCTION_FETCH {
         unsafe {
-            crate::errorln!("Process died normally. {}", process::CURRENT_PROCESS);
-            let new = process::process_died(COUNTER, 0); // TODO fetch return code
-            COUNTER = 0;
+            crate::errorln!("Process died normally. {}", process::current_pid());
+            let new = process::process_died(take_counter(), 0); // TODO fetch return code
             process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
         }
+    } else if error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+        && unsafe { memory::cow::handle_fault(read_addr) }
+    {
+        // First write to a page shared by a fork, it now has its own copy.
+        // It may come from the kernel writing into a buffer of the process.
+        unsafe {
+            process::get_current_as_mut().stats.page_faults += 1;
+        }
+    } else if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && unsafe { memory::swap::handle_fault(read_addr) }
+    {
+        // The page was swapped out, it was read back from the disk
+        unsafe {
+            process::get_current_as_mut().stats.page_faults += 1;
+        }
+    } else if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && demand_page(read_addr, stack_frame, registers)
+    {
+        // The page was reserved by the process, it is now backed
     } else if is_kernel_space(stack_frame.as_real().instruction_pointer) {
         bsod!("PAGE FAULT! {:#?}", stack_frame);
         bsod!("TRIED TO READ : {:#?}", Cr2::read());
         bsod!("ERROR : {:#?}", error_code);
         panic!();
     } else {
+        unsafe {
+            process::get_current_as_mut().stats.page_faults += 1;
+        }
         // TODO maybe write something into the process' stdout
         warningln!("Process just pagefault.");
         bsod!("TRIED TO READ : {:#?}", Cr2::read());
         bsod!("PAGE FAULT! {:#?}", stack_frame);
         bsod!("ERROR : {:#?}", error_code);
-        new_process!(11);
+        new_process!(11, stack_frame, registers);
+    }
+}
+
+/// Backs a page reserved by the current process, see `process::regions`.
+/// Returns false if the page was not reserved. The process is killed if it overflowed its stack
+/// or if there is no memory left.
+fn demand_page(
+    address: VirtAddr,
+    stack_frame: &mut InterruptStackFrame,
+    registers: &mut Registers,
+) -> bool {
+    match unsafe { process::handle_page_fault(address) } {
+        Fault::Backed => true,
+        Fault::Unmapped => false,
+        Fault::StackOverflow => {
+            errorln!(
+                "Stack overflow of process {} at {:#x}",
+                process::current_pid(),
+                address.as_u64()
+            );
+            new_process!(11, stack_frame, registers);
+        }
+        Fault::OutOfMemory => {
+            errorln!(
+                "No memory left for the page {:#x} of process {}",
+                address.as_u64(),
+                process::current_pid()
+            );
+            new_process!(11, stack_frame, registers);
+        }
     }
 }
 
 /// Keyboard interrupt handler
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    let _lock = smp::lock::acquire();
     let _keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
@@ -388,6 +616,9 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut Interrup
     }
 }
 
+/// Spurious interrupts of the local APIC must not be acknowledged
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {}
+
 extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
     unsafe {
         PICS.lock()
@@ -3,6 +3,7 @@
 //! Part of the OS responsible for handling syscalls
 
 use super::idt::InterruptStackFrame;
+use crate::allocator;
 use crate::data_storage::{
     path,
     registers::{Registers, RegistersMini},
@@ -12,7 +13,8 @@ use crate::filesystem::descriptor;
 use crate::hardware;
 use crate::interrupts;
 use crate::memory;
-use crate::scheduler::process;
+use crate::scheduler::process::{self, limits::Rlimit, stats};
+use crate::scheduler::wait_queue::{self, Resource};
 
 use crate::scheduler;
 use crate::{debug, warningln};
@@ -28,7 +30,10 @@ use crate::filesystem::partition::IoError;
 pub type SyscallFunc = extern "C" fn();
 
 /// total number of syscalls
-const SYSCALL_NUMBER: u64 = 24;
+const SYSCALL_NUMBER: u64 = 56;
+
+/// Length of the `int 0x80` instruction, used to restart a syscall
+const SYSCALL_INSTRUCTION_LENGTH: u64 = 2;
 
 /// table containing every syscall functions
 const SYSCALL_TABLE: [unsafe extern "C" fn(&mut RegistersMini, &mut InterruptStackFrame);
@@ -57,8 +62,58 @@ const SYSCALL_TABLE: [unsafe extern "C" fn(&mut RegistersMini, &mut InterruptSta
     syscall_21_memrequest,
     syscall_22_listen,
     syscall_23_kill,
+    syscall_24_sleep,
+    syscall_25_waitpid,
+    syscall_26_setpgid,
+    syscall_27_getpgid,
+    syscall_28_setsid,
+    syscall_29_set_foreground,
+    syscall_30_get_foreground,
+    syscall_31_stop,
+    syscall_32_continue,
+    syscall_33_getuid,
+    syscall_34_getgid,
+    syscall_35_setuid,
+    syscall_36_seteuid,
+    syscall_37_setgid,
+    syscall_38_setegid,
+    syscall_39_chmod,
+    syscall_40_chown,
+    syscall_41_getrlimit,
+    syscall_42_setrlimit,
+    syscall_43_set_core_directory,
+    syscall_44_clock_gettime,
+    syscall_45_sched_realtime,
+    syscall_46_set_aslr,
+    syscall_47_shm_open,
+    syscall_48_shm_resize,
+    syscall_49_shm_attach,
+    syscall_50_shm_detach,
+    syscall_51_shm_unlink,
+    syscall_52_mmap,
+    syscall_53_munmap,
+    syscall_54_msync,
+    syscall_55_set_heap_max,
 ];
 
+/// Option of `waitpid` to also report the children that were stopped
+const WAIT_STOPPED: u64 = 1;
+
+/// Return value given by `waitpid` for a stopped child
+const STOPPED_STATUS: u64 = u64::MAX;
+
+/// Option of `shm_open` to create the segment if it does not exist
+const SHM_CREATE: u64 = 1;
+
+/// Option of `shm_open` to fail if the segment already exists
+const SHM_EXCLUSIVE: u64 = 2;
+
+/// Option of `mmap` for a writable mapping
+const MMAP_WRITE: u64 = 1;
+
+/// Option of `mmap` to write back to the file instead of keeping the writes private
+const MMAP_SHARED: u64 = 2;
+
 /// highly dangerous function should use only when knowing what you are doing
 #[naked]
 unsafe extern "C" fn convert_register_to_full(_args: &mut RegistersMini) -> &'static mut Registers {
@@ -80,8 +135,40 @@ unsafe fn read_string_from_pointer(ptr: u64) -> String {
     buf.into_iter().collect()
 }
 
+/// Gives the CPU to another process. The current one is resumed right after this syscall.
+/// # Safety
+/// `args` must be the registers saved on the stack of the current process.
+unsafe fn switch_out(args: &mut RegistersMini) -> ! {
+    account_syscall();
+    let (next, mut old) = process::gives_switch(interrupts::take_counter());
+
+    let (cr3, cr3f) = Cr3::read();
+    old.cr3 = cr3.start_address();
+    old.cr3f = cr3f;
+
+    old.rsp = VirtAddr::from_ptr(args).as_u64();
+
+    process::leave_context_cr3(next.cr3.as_u64() | next.cr3f.bits(), next.rsp);
+}
+
+/// Gives the CPU to another process. The syscall `number` is executed again
+/// once the current process is woken up.
+/// # Safety
+/// Same as `switch_out`
+unsafe fn restart_after_sleep(
+    number: u64,
+    args: &mut RegistersMini,
+    isf: &mut InterruptStackFrame,
+) -> ! {
+    args.rax = number;
+    isf.as_mut().instruction_pointer -= SYSCALL_INSTRUCTION_LENGTH;
+    switch_out(args)
+}
+
 /// read. arg0 : unsigned int fd, arg1 : char *buf, size_t count
-unsafe extern "C" fn syscall_0_read(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+///
+/// If the driver has to wait for data, the process sleeps and the read is restarted when it is woken up.
+unsafe extern "C" fn syscall_0_read(args: &mut RegistersMini, isf: &mut InterruptStackFrame) {
     let (cr3, _) = Cr3::read();
     let mut size = min(args.rdx, 1024);
     if memory::check_if_has_flags(
@@ -107,22 +194,10 @@ unsafe extern "C" fn syscall_0_read(args: &mut RegistersMini, _isf: &mut Interru
                 Ok(x) => x,
                 Err(IoError::Continue) => Vec::new(),
                 Err(IoError::Kill) => {
-                    let new = process::process_died(interrupts::COUNTER, process::IO_ERROR);
-                    interrupts::COUNTER = 0;
+                    let new = process::process_died(interrupts::take_counter(), process::IO_ERROR);
                     process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
                 }
-                Err(IoError::Sleep) => {
-                    let (next, mut old) = process::gives_switch(interrupts::COUNTER);
-                    interrupts::COUNTER = 0;
-
-                    let (cr3, cr3f) = Cr3::read();
-                    old.cr3 = cr3.start_address();
-                    old.cr3f = cr3f;
-
-                    old.rsp = VirtAddr::from_ptr(args).as_u64();
-
-                    process::leave_context_cr3(next.cr3.as_u64() | next.cr3f.bits(), next.rsp);
-                }
+                Err(IoError::Sleep) => restart_after_sleep(0, args, isf),
             };
             let mut address = VirtAddr::new(args.rsi);
             for item in res.iter().take(min(size as usize, res.len())) {
@@ -187,7 +262,7 @@ unsafe extern "C" fn syscall_1_write(args: &mut RegistersMini, _isf: &mut Interr
                     }
                 };
             }
-            panic!("Failure {}", process::CURRENT_PROCESS);
+            panic!("Failure {}", process::current_pid());
         }
         //}
     } else {
@@ -207,11 +282,13 @@ unsafe extern "C" fn syscall_2_open(args: &mut RegistersMini, _isf: &mut Interru
     crate::debug!("{:?} {}", [&path], path.len());
     let current_process = process::get_current_as_mut();
     crate::debug!("syscall open mid");
+    let max_files = current_process.limits.soft(Rlimit::OpenFiles) as usize;
     let fd = current_process
         .open_files
         .create_file_table(
             path::Path::from(&path),
             crate::filesystem::fsflags::OpenFlags::from_bits_unchecked(args.rdx as usize),
+            max_files,
         )
         .into_u64();
     crate::debug!("syscall open end {}", fd);
@@ -221,14 +298,21 @@ unsafe extern "C" fn syscall_2_open(args: &mut RegistersMini, _isf: &mut Interru
 
 /// close file. arg0 : unsigned int fd
 unsafe extern "C" fn syscall_3_close(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    // The mappings of the file stay, their dirty pages are written back
+    if let Ok(oft) = process::get_current()
+        .open_files
+        .get_file_table(descriptor::FileDescriptor::new(args.rdi as usize))
+    {
+        process::sync_file_path(&oft.get_path().to());
+    }
     match process::get_current_as_mut()
         .open_files
         .close_fd(args.rdi as usize)
     {
         Ok(a) => args.rax = a as u64,
         Err(_) => {
-            let new = process::process_died(interrupts::COUNTER, process::BAD_FILE_MANIPULATION);
-            interrupts::COUNTER = 0;
+            let new =
+                process::process_died(interrupts::take_counter(), process::BAD_FILE_MANIPULATION);
             process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
         }
     }
@@ -239,8 +323,8 @@ unsafe extern "C" fn syscall_4_dup2(args: &mut RegistersMini, _isf: &mut Interru
     match process::dup2(args.rdi as usize, args.rsi as usize) {
         Ok(a) => args.rax = a as u64,
         Err(_) => {
-            let new = process::process_died(interrupts::COUNTER, process::BAD_FILE_MANIPULATION);
-            interrupts::COUNTER = 0;
+            let new =
+                process::process_died(interrupts::take_counter(), process::BAD_FILE_MANIPULATION);
             process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
         }
     }
@@ -256,8 +340,13 @@ unsafe extern "C" fn syscall_5_fork(args: &mut RegistersMini, _isf: &mut Interru
     current.cr3 = cr3.start_address();
     current.cr3f = cr3f;
     current.rsp = VirtAddr::from_ptr(args).as_u64();
-    let next: u64 = process::fork().0;
-    args.rax = next;
+    match process::fork() {
+        Ok(next) => args.rax = next.0,
+        Err(e) => {
+            warningln!("fork failed: {:?}", e);
+            args.rax = u64::MAX;
+        }
+    }
 }
 
 /// arg0 : address of file name
@@ -279,31 +368,20 @@ unsafe extern "C" fn syscall_6_exec(args: &mut RegistersMini, _isf: &mut Interru
         Err(a) => {
             warningln!("Killed process amid invalid exec : {:?}", a);
             // Write the error into the process' stdout
-            let new = process::process_died(interrupts::COUNTER, 1); // TODO fetch return code
-            interrupts::COUNTER = 0;
+            let new = process::process_died(interrupts::take_counter(), 1); // TODO fetch return code
             process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
         }
     }
 }
 
 unsafe extern "C" fn syscall_7_exit(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
-    warningln!("syscall exit {}", process::CURRENT_PROCESS);
-    let new = process::process_died(interrupts::COUNTER, args.rdi);
-    interrupts::COUNTER = 0;
+    warningln!("syscall exit {}", process::current_pid());
+    let new = process::process_died(interrupts::take_counter(), args.rdi);
     process::leave_context_cr3(new.cr3.as_u64() | new.cr3f.bits(), new.rsp);
 }
 
 unsafe extern "C" fn syscall_8_wait(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
-    let (next, mut old) = process::gives_switch(interrupts::COUNTER);
-    interrupts::COUNTER = 0;
-
-    let (cr3, cr3f) = Cr3::read();
-    old.cr3 = cr3.start_address();
-    old.cr3f = cr3f;
-
-    old.rsp = VirtAddr::from_ptr(args).as_u64();
-
-    process::leave_context_cr3(next.cr3.as_u64() | next.cr3f.bits(), next.rsp);
+    switch_out(args)
 }
 
 unsafe extern "C" fn syscall_9_shutdown(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
@@ -312,7 +390,7 @@ unsafe extern "C" fn syscall_9_shutdown(args: &mut RegistersMini, _isf: &mut Int
 }
 
 unsafe extern "C" fn syscall_10_get_puid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
-    args.rax = process::CURRENT_PROCESS as u64
+    args.rax = process::current_pid() as u64
 }
 
 unsafe extern "C" fn syscall_11_set_screen_size(
@@ -407,32 +485,31 @@ unsafe extern "C" fn syscall_20_debug(args: &mut RegistersMini, _isf: &mut Inter
 
 /// Syscall for requesting additionnal heap frames
 /// We might want to change the maximum
+/// The pages are only reserved, they are backed when they are first touched.
 unsafe extern "C" fn syscall_21_memrequest(
     args: &mut RegistersMini,
     _isf: &mut InterruptStackFrame,
 ) {
     // Number of requested frames
     debug!("starts memrequest");
-    let additional = core::cmp::max(args.rdi, 256);
     let current_process = scheduler::process::get_current_as_mut();
     let current_heap_size = current_process.heap_size;
-    // TODO out this in a cosntant
-    if current_heap_size >= 1024 {
+    let heap_limit = current_process.limits.soft(Rlimit::HeapPages);
+    if current_heap_size >= heap_limit {
         warningln!("Process got max allocatable heap.");
         args.rax = 0;
         return;
     }
-    let given;
-    if let Some(ref mut frame_allocator) = crate::memory::FRAME_ALLOCATOR {
-        given = scheduler::process::allocate_additional_heap_pages(
-            frame_allocator,
-            current_process.heap_address + current_heap_size * 0x1000,
-            additional,
-            &current_process,
-        );
+    let wanted = core::cmp::max(args.rdi, 256);
+    let additional = min(wanted, heap_limit - current_heap_size);
+    let given = if current_process
+        .regions
+        .extend(process::regions::RegionKind::Heap, additional)
+    {
+        additional
     } else {
-        given = 0;
-    }
+        0
+    };
     debug!("Fullfilled memrequest {}", given);
     current_process.heap_size += given;
     args.rax = given
@@ -445,10 +522,352 @@ unsafe extern "C" fn syscall_22_listen(args: &mut RegistersMini, _isf: &mut Inte
 }
 
 unsafe extern "C" fn syscall_23_kill(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
-    debug!("{} tried to kill {}", process::CURRENT_PROCESS, args.rdi);
+    debug!("{} tried to kill {}", process::current_pid(), args.rdi);
     args.rax = scheduler::process::kill(args.rdi as usize) as u64;
 }
 
+/// Sleeps for at least arg0 timer ticks. May return early if the process is woken up by something else.
+unsafe extern "C" fn syscall_24_sleep(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = 0;
+    if args.rdi != 0 {
+        wait_queue::sleep_for(args.rdi);
+        switch_out(args)
+    }
+}
+
+/// Blocking version of `listen`. arg0 : pid of the child, 0 for any child. arg1 : options (`WAIT_STOPPED`)
+/// Returns the pid in rax and the return value in rdi, or 0 in both if the process has no child.
+/// A stopped child is reported with `STOPPED_STATUS` as return value.
+unsafe extern "C" fn syscall_25_waitpid(args: &mut RegistersMini, isf: &mut InterruptStackFrame) {
+    let (rax, rdi) = scheduler::process::listen(args.rdi as usize);
+    if rax == 0 && args.rsi & WAIT_STOPPED != 0 {
+        if let Some(pid) = process::listen_stopped(args.rdi as usize) {
+            args.rax = pid.0;
+            args.rdi = STOPPED_STATUS;
+            return;
+        }
+    }
+    if rax == 0 {
+        let pid = process::get_current().get_pid();
+        if process::has_children(pid) {
+            wait_queue::sleep_on(Resource::ChildExit(pid), true);
+            restart_after_sleep(25, args, isf);
+        }
+    }
+    args.rax = rax as u64;
+    args.rdi = rdi as u64;
+}
+
+/// arg0 : pid, 0 for the current process. arg1 : process group, 0 to create a new one led by the process
+unsafe extern "C" fn syscall_26_setpgid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = process::setpgid(args.rdi as usize, args.rsi as usize) as u64;
+}
+
+/// arg0 : pid, 0 for the current process
+unsafe extern "C" fn syscall_27_getpgid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = process::getpgid(args.rdi as usize) as u64;
+}
+
+unsafe extern "C" fn syscall_28_setsid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = process::setsid() as u64;
+}
+
+/// arg0 : file descriptor of the screen. arg1 : process group
+unsafe extern "C" fn syscall_29_set_foreground(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    args.rax = scheduler::job::set_foreground(args.rdi as usize, args.rsi as usize) as u64;
+}
+
+/// arg0 : file descriptor of the screen
+unsafe extern "C" fn syscall_30_get_foreground(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    args.rax = scheduler::job::get_foreground(args.rdi as usize) as u64;
+}
+
+/// arg0 : pid or process group. arg1 : 1 if arg0 is a process group
+/// Returns the number of stopped processes
+unsafe extern "C" fn syscall_31_stop(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = scheduler::job::stop(args.rdi as usize, args.rsi == 1) as u64;
+    if process::get_current().state == process::State::Stopped {
+        switch_out(args)
+    }
+}
+
+/// arg0 : pid or process group. arg1 : 1 if arg0 is a process group
+/// Returns the number of resumed processes
+unsafe extern "C" fn syscall_32_continue(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = scheduler::job::resume(args.rdi as usize, args.rsi == 1) as u64;
+}
+
+/// Returns the real user id in rax and the effective one in rdi
+unsafe extern "C" fn syscall_33_getuid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let credentials = process::get_current().credentials;
+    args.rax = credentials.uid;
+    args.rdi = credentials.euid;
+}
+
+/// Returns the real group id in rax and the effective one in rdi
+unsafe extern "C" fn syscall_34_getgid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let credentials = process::get_current().credentials;
+    args.rax = credentials.gid;
+    args.rdi = credentials.egid;
+}
+
+/// arg0 : user id. Returns 0 if it succeeds, u64::MAX otherwise
+unsafe extern "C" fn syscall_35_setuid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let credentials = &mut process::get_current_as_mut().credentials;
+    args.rax = if credentials.setuid(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// arg0 : effective user id. Returns 0 if it succeeds, u64::MAX otherwise
+unsafe extern "C" fn syscall_36_seteuid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let credentials = &mut process::get_current_as_mut().credentials;
+    args.rax = if credentials.seteuid(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// arg0 : group id. Returns 0 if it succeeds, u64::MAX otherwise
+unsafe extern "C" fn syscall_37_setgid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let credentials = &mut process::get_current_as_mut().credentials;
+    args.rax = if credentials.setgid(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// arg0 : effective group id. Returns 0 if it succeeds, u64::MAX otherwise
+unsafe extern "C" fn syscall_38_setegid(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let credentials = &mut process::get_current_as_mut().credentials;
+    args.rax = if credentials.setegid(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// arg0 : address of the path. arg1 : unix-like `rwx` permissions (e.g. 0o644)
+/// Returns 0 if it succeeds, u64::MAX otherwise
+unsafe extern "C" fn syscall_39_chmod(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let path = read_string_from_pointer(args.rdi);
+    args.rax = if filesystem::chmod_file(&path::Path::from(&path), args.rsi as u16) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// arg0 : address of the path. arg1 : owner id. arg2 : group id
+/// Returns 0 if it succeeds, u64::MAX otherwise
+unsafe extern "C" fn syscall_40_chown(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let path = read_string_from_pointer(args.rdi);
+    args.rax = if filesystem::chown_file(&path::Path::from(&path), args.rsi, args.rdx) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// getrlimit. arg0 : resource (0 heap pages, 1 open files, 2 children, 3 cpu ticks, 4 stack pages)
+/// Returns the soft limit in rax and the hard one in rdi, or u64::MAX in both for an unknown resource.
+unsafe extern "C" fn syscall_41_getrlimit(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    let (soft, hard) = process::getrlimit(args.rdi);
+    args.rax = soft;
+    args.rdi = hard;
+}
+
+/// setrlimit. arg0 : resource, arg1 : soft limit, arg2 : hard limit
+unsafe extern "C" fn syscall_42_setrlimit(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    args.rax = process::setrlimit(args.rdi, args.rsi, args.rdx) as u64;
+}
+
+/// Sets where the core files are written. arg0 : path of a directory of the UsTar partition,
+/// an empty path disables the core files. Only root can do it.
+unsafe extern "C" fn syscall_43_set_core_directory(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    let path = read_string_from_pointer(args.rdi);
+    args.rax = if process::current_credentials().is_root() && process::coredump::set_directory(path)
+    {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// clock_gettime. arg0 : clock (0 real time, 1 monotonic)
+/// Returns the seconds in rax and the nanoseconds in rdi, or u64::MAX in both for an unknown clock.
+unsafe extern "C" fn syscall_44_clock_gettime(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    let (seconds, nanoseconds) = match hardware::clock::ClockId::from_u64(args.rdi) {
+        Some(clock) => hardware::clock::get_time(clock),
+        None => (u64::MAX, u64::MAX),
+    };
+    args.rax = seconds;
+    args.rdi = nanoseconds;
+}
+
+/// Puts the current process into the real-time class. arg0 : period in ticks, 0 to leave the class.
+/// arg1 : ticks it may run in each period. Only root can enter the class, anyone can leave it.
+/// Returns 0 if it succeeds, u64::MAX if the parameters are invalid or if the reservation is refused.
+unsafe extern "C" fn syscall_45_sched_realtime(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    if args.rdi != 0 && !process::current_credentials().is_root() {
+        args.rax = u64::MAX;
+        return;
+    }
+    let pid = process::get_current().get_pid();
+    args.rax = match scheduler::realtime::set(pid, args.rdi, args.rsi) {
+        Ok(()) => 0,
+        Err(_) => u64::MAX,
+    };
+}
+
+/// Enables (arg0 = 1) or disables (arg0 = 0) the address space layout randomisation of the programs
+/// loaded afterwards, to debug them with the same layout at every run. Only root can do it.
+/// Returns the previous state, or u64::MAX if it is refused.
+unsafe extern "C" fn syscall_46_set_aslr(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    if !process::current_credentials().is_root() || args.rdi > 1 {
+        args.rax = u64::MAX;
+        return;
+    }
+    args.rax = process::aslr::is_enabled() as u64;
+    process::aslr::set_enabled(args.rdi == 1);
+}
+
+/// Opens the shared-memory segment named by the string at arg0. arg1 : options, `SHM_CREATE` to create
+/// it empty if it does not exist and `SHM_EXCLUSIVE` to fail if it does.
+/// Returns the id of the segment, or u64::MAX if it fails.
+unsafe extern "C" fn syscall_47_shm_open(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    let name = read_string_from_pointer(args.rdi);
+    args.rax = memory::shm::open(
+        &name,
+        args.rsi & SHM_CREATE != 0,
+        args.rsi & SHM_EXCLUSIVE != 0,
+    )
+    .unwrap_or(u64::MAX);
+}
+
+/// Sets the size of the shared-memory segment arg0 to arg1 bytes, rounded up to pages.
+/// The processes that mapped it keep their pages. Returns 0 if it succeeds, u64::MAX otherwise.
+unsafe extern "C" fn syscall_48_shm_resize(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    let pages = (args.rsi + 0xFFF) / 0x1000;
+    args.rax = if memory::shm::resize(args.rdi, pages) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// Maps the shared-memory segment arg0 at arg1, or where the kernel chooses if it is 0.
+/// arg2 : 1 for a writable mapping, 0 for a read-only one.
+/// Returns the address of the mapping, or u64::MAX if it fails.
+unsafe extern "C" fn syscall_49_shm_attach(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    args.rax = process::attach_shared(args.rdi, args.rsi, args.rdx == 1).unwrap_or(u64::MAX);
+}
+
+/// Unmaps the shared-memory segment mapped at arg0. Returns 0 if it succeeds, u64::MAX otherwise.
+unsafe extern "C" fn syscall_50_shm_detach(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    args.rax = if process::detach_shared(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// Removes the name of the shared-memory segment named by the string at arg0, its memory is freed
+/// once no process maps it anymore. Returns 0 if it succeeds, u64::MAX otherwise.
+unsafe extern "C" fn syscall_51_shm_unlink(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    let name = read_string_from_pointer(args.rdi);
+    args.rax = if memory::shm::unlink(&name) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// Maps arg1 bytes of the file open as arg0 from the offset arg3, a multiple of 4096, at arg4,
+/// or where the kernel chooses if it is 0. arg2 : options, `MMAP_WRITE` for a writable mapping and
+/// `MMAP_SHARED` to write back to the file. The range can not go past the last page of the file, whose end
+/// reads as zeroes. Returns the address of the mapping, or u64::MAX if it fails.
+unsafe extern "C" fn syscall_52_mmap(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = process::map_file(
+        args.rdi as usize,
+        args.rsi,
+        args.r10,
+        args.r8,
+        args.rdx & MMAP_WRITE != 0,
+        args.rdx & MMAP_SHARED != 0,
+    )
+    .unwrap_or(u64::MAX);
+}
+
+/// Writes back and unmaps the file mapped at arg0. Returns 0 if it succeeds, u64::MAX otherwise.
+unsafe extern "C" fn syscall_53_munmap(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = if process::unmap_file(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// Writes back the dirty pages of the file mapped at arg0. Returns 0 if it succeeds, u64::MAX otherwise.
+unsafe extern "C" fn syscall_54_msync(args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
+    args.rax = if process::sync_file(args.rdi) {
+        0
+    } else {
+        u64::MAX
+    };
+}
+
+/// Sets to arg0 bytes the size the kernel heap can not grow past, never under its current size.
+/// Only root can do it. Returns the previous maximum, or u64::MAX if it is refused.
+unsafe extern "C" fn syscall_55_set_heap_max(
+    args: &mut RegistersMini,
+    _isf: &mut InterruptStackFrame,
+) {
+    if !process::current_credentials().is_root() {
+        args.rax = u64::MAX;
+        return;
+    }
+    args.rax = allocator::heap_stats().max_size as u64;
+    allocator::set_max_size(args.rdi as usize);
+}
+
 unsafe extern "C" fn syscall_test(_args: &mut RegistersMini, _isf: &mut InterruptStackFrame) {
     debug!("Test syscall.");
 }
@@ -465,10 +884,24 @@ pub unsafe extern "C" fn syscall_dispatch(isf: &mut InterruptStackFrame, args: &
     if args.rax >= SYSCALL_NUMBER {
         panic!("no such syscall : {:?}", args);
     } else {
-        SYSCALL_TABLE[args.rax as usize](args, isf)
+        SYSCALL_START = stats::cycles();
+        SYSCALL_TABLE[args.rax as usize](args, isf);
+        account_syscall();
     }
 }
 
+/// Time-stamp counter at the beginning of the syscall being executed
+static mut SYSCALL_START: u64 = 0;
+
+/// Accounts the time spent in the current syscall to the current process.
+unsafe fn account_syscall() {
+    let now = stats::cycles();
+    process::get_current_as_mut()
+        .stats
+        .add_syscall_cycles(now.wrapping_sub(SYSCALL_START));
+    SYSCALL_START = now;
+}
+
 /// interface function for syscalls, saves every register before giving control to the dispatch function
 /// it disables interrupts at entry !
 /// DEPRECIATED
@@ -1,6 +1,7 @@
 //! Kernel keyboard logic.
 
 use crate::println;
+use crate::scheduler::wait_queue::{self, Resource};
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::{ArrayQueue, PopError, PushError};
 use lazy_static::lazy_static;
@@ -32,6 +33,7 @@ pub fn add_scancode(scancode: u8) {
         if queue.push(scancode).is_err() {
             println!("Scancode queue full; dropping keyboard input.");
         }
+        wait_queue::wake_up(Resource::Keyboard);
     } else {
         println!("Scancode queue uninitialized.");
         ScancodeStream::new();
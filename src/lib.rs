@@ -15,8 +15,13 @@
 #![feature(vec_into_raw_parts)]
 #![feature(never_type)]
 #![feature(asm_sym)]
+#![feature(global_asm)]
 
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{OffsetPageTable, PageTableFlags};
+use x86_64::VirtAddr;
 extern crate vga as vga_video;
 
 pub mod allocator;
@@ -30,6 +35,7 @@ pub mod memory;
 pub mod programs;
 pub mod scheduler;
 pub mod serial;
+pub mod smp;
 pub mod sound;
 pub mod vga;
 
@@ -88,11 +94,38 @@ pub fn test_panic(_info: &PanicInfo) -> ! {
 }
 
 #[cfg(test)]
-#[no_mangle]
-#[allow(clippy::empty_loop)]
-pub extern "C" fn _start() -> ! {
+bootloader::entry_point!(test_kernel_main);
+
+/// Entry point of the unit tests, some of them need the frame allocator and the kernel heap
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    init_memory(boot_info);
     test_main();
-    loop {}
+    halt_loop()
+}
+
+/// Initializes the frame allocator and the kernel heap from the memory map of the bootloader.
+/// Returns the mapper of the kernel address space.
+pub fn init_memory(boot_info: &'static BootInfo) -> OffsetPageTable<'static> {
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    println!("Physical memory offset : 0x{:x?}", phys_mem_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    unsafe {
+        memory::BootInfoAllocator::init(&boot_info.memory_map, phys_mem_offset);
+        if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
+            let (level_4_frame, _) = Cr3::read();
+            frame_allocator.deallocate_level_4_page(
+                level_4_frame.start_address(),
+                PageTableFlags::BIT_9,
+                false,
+            );
+            VGA_BUFFER += boot_info.physical_memory_offset;
+            allocator::init(&mut mapper, frame_allocator).expect("Heap init failed :((");
+        } else {
+            panic!("Frame allocator wasn't initialized");
+        }
+    };
+    mapper
 }
 
 #[cfg(test)]
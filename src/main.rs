@@ -35,8 +35,8 @@ use x86_64::{
 /// It's here that we perform the Frankenstein magic of assembling all the parts together.
 use ferr_os::{
     allocator, data_storage, debug, errorln, filesystem, gdt, halt_loop, hardware, initdebugln,
-    interrupts, keyboard, long_halt, memory, print, println, scheduler, serial, sound, test_panic,
-    vga, warningln, FIRST_PROGRAM, VGA_BUFFER,
+    interrupts, keyboard, long_halt, memory, print, println, scheduler, serial, smp, sound,
+    test_panic, vga, warningln, VGA_BUFFER,
 };
 use x86_64::instructions::random::RdRand;
 use x86_64::registers::control::Cr3;
@@ -97,24 +97,7 @@ pub fn init(_boot_info: &'static BootInfo) {
     gdt::init();
 
     // Memory allocation Initialization
-    let phys_mem_offset = VirtAddr::new(_boot_info.physical_memory_offset);
-    println!("Physical memory offset : 0x{:x?}", phys_mem_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    unsafe {
-        memory::BootInfoAllocator::init(&_boot_info.memory_map, phys_mem_offset);
-        if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
-            let (level_4_frame, _) = Cr3::read();
-            frame_allocator.deallocate_level_4_page(
-                level_4_frame.start_address(),
-                PageTableFlags::BIT_9,
-                false,
-            );
-            VGA_BUFFER += _boot_info.physical_memory_offset;
-            allocator::init(&mut mapper, frame_allocator).expect("Heap init failed :((");
-        } else {
-            panic!("Frame allocator wasn't initialized");
-        }
-    };
+    let mut mapper = ferr_os::init_memory(_boot_info);
     // I/O Initialization
     hardware::mouse::init().unwrap();
     keyboard::init();
@@ -124,11 +107,20 @@ pub fn init(_boot_info: &'static BootInfo) {
 
     println!("Changing timer frequence");
     unsafe {
-        hardware::timer::set_timer(0x8000); // 0 = 0x10000 = frequence min
+        hardware::timer::set_timer(hardware::timer::TIMER_DIVISOR); // 0 = 0x10000 = frequence min
     }
 
     // Interrupt initialisation put at the end to avoid messing up with I/O
     interrupts::init();
+
+    unsafe {
+        x86_64::instructions::interrupts::without_interrupts(|| hardware::clock::init());
+        if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
+            if !hardware::apic::init(&mut mapper, frame_allocator) {
+                warningln!("No local APIC, the PIT stays the timer");
+            }
+        }
+    }
     //println!(":( :(");
 
     long_halt(0);
@@ -144,7 +136,18 @@ pub fn init(_boot_info: &'static BootInfo) {
         filesystem::init_vfs();
     }
     debug!("vfs initialised");
+    memory::swap::init();
     scheduler::process::spawn_first_process();
+    scheduler::idle::spawn();
+    scheduler::kthread::spawn_all();
+
+    unsafe {
+        if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
+            let cpus =
+                x86_64::instructions::interrupts::without_interrupts(|| smp::init(frame_allocator));
+            println!("Running on {} processor(s)", cpus);
+        }
+    }
 }
 
 entry_point!(kernel_main);
@@ -156,9 +159,12 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     init(boot_info);
 
     unsafe {
+        // The other processors may be running kernel threads, `towards_user` releases it
+        x86_64::instructions::interrupts::disable();
+        core::mem::forget(smp::lock::acquire());
         if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
             scheduler::process::disassemble_and_launch(
-                FIRST_PROGRAM,
+                &scheduler::supervisor::first_program(),
                 frame_allocator,
                 1,
                 10,
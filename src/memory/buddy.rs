@@ -0,0 +1,374 @@
+//! Buddy allocator of the physical frames.
+//!
+//! The free memory is split into blocks of `2^order` frames, from 4 KiB (order 0) to 2 MiB (`MAX_ORDER`),
+//! every block being aligned on its size. Each order has a doubly linked list of its free blocks,
+//! threaded through the blocks themselves. Freeing a block merges it with its buddy, the other half
+//! of the block of the order above, as long as this buddy is free too.
+//!
+//! The order of the free block starting at each frame is kept in a table of one byte per frame,
+//! taken from the usable memory when the allocator is created.
+
+use super::PHYSICAL_OFFSET;
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+
+/// Largest order, a block of this order is 2 MiB
+pub const MAX_ORDER: usize = 9;
+/// Number of orders
+pub const ORDERS: usize = MAX_ORDER + 1;
+
+const FRAME_SIZE: u64 = 0x1000;
+
+/// Marks an entry of the order table as the start of a free block, the low bits being its order
+const FREE: u8 = 0x80;
+
+/// Statistics of an order
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrderStats {
+    /// Free blocks
+    pub free: u64,
+    /// Blocks handed out since the boot
+    pub allocations: u64,
+    /// Allocations that found no block
+    pub failures: u64,
+    /// Blocks of the order above split to serve an allocation
+    pub splits: u64,
+    /// Blocks freed that were merged with their buddy
+    pub merges: u64,
+}
+
+/// Links of a free block, written at its start
+#[repr(C)]
+struct FreeBlock {
+    next: u64,
+    prev: u64,
+}
+
+pub struct BuddyAllocator {
+    /// First free block of each order, 0 if there is none. The frame 0 is never given.
+    heads: [u64; ORDERS],
+    /// Entry of each frame, `FREE | order` if a free block starts there, 0 otherwise
+    orders: &'static mut [u8],
+    /// Frames handed out
+    used: u64,
+    stats: [OrderStats; ORDERS],
+}
+
+/// Returns the size in bytes of a block of order `order`
+pub const fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+unsafe fn block(address: u64) -> &'static mut FreeBlock {
+    &mut *((address + PHYSICAL_OFFSET) as *mut FreeBlock)
+}
+
+impl BuddyAllocator {
+    pub fn empty() -> Self {
+        Self {
+            heads: [0; ORDERS],
+            orders: &mut [],
+            used: 0,
+            stats: [OrderStats::default(); ORDERS],
+        }
+    }
+
+    /// Creates the allocator from the usable regions of `memory_map`, except the frames for which `is_reserved` is true.
+    /// Returns None if no region can hold the order table.
+    /// # Safety
+    /// The usable regions must not be used by anything else, and the physical memory must be mapped at `PHYSICAL_OFFSET`.
+    pub unsafe fn new(memory_map: &MemoryMap, is_reserved: fn(u64) -> bool) -> Option<Self> {
+        // The frames used by the bootloader may be given back later
+        let end = memory_map
+            .iter()
+            .filter(|r| {
+                matches!(
+                    r.region_type,
+                    MemoryRegionType::Usable
+                        | MemoryRegionType::Bootloader
+                        | MemoryRegionType::PageTable
+                )
+            })
+            .map(|r| r.range.end_addr())
+            .max()?;
+        let frames = (end / FRAME_SIZE) as usize;
+        let table_size = (frames as u64 + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+                .map(|r| (r.range.start_addr().max(FRAME_SIZE), r.range.end_addr()))
+        };
+        let (table_start, _) = usable().find(|(start, end)| {
+            start + table_size <= *end
+                && !(*start..start + table_size)
+                    .step_by(FRAME_SIZE as usize)
+                    .any(is_reserved)
+        })?;
+        let orders =
+            core::slice::from_raw_parts_mut((table_start + PHYSICAL_OFFSET) as *mut u8, frames);
+        orders.iter_mut().for_each(|entry| *entry = 0);
+
+        let mut allocator = Self {
+            heads: [0; ORDERS],
+            orders,
+            used: 0,
+            stats: [OrderStats::default(); ORDERS],
+        };
+        let table = table_start..table_start + table_size;
+        let excluded = |address: u64, size: u64| {
+            address == 0
+                || (address < table.end && table.start < address + size)
+                || (address..address + size)
+                    .step_by(FRAME_SIZE as usize)
+                    .any(is_reserved)
+        };
+        for (start, end) in usable() {
+            let mut address = start;
+            while address < end {
+                let mut order = ((address / FRAME_SIZE).trailing_zeros() as usize).min(MAX_ORDER);
+                while order > 0
+                    && (address + block_size(order) > end || excluded(address, block_size(order)))
+                {
+                    order -= 1;
+                }
+                if !excluded(address, FRAME_SIZE) {
+                    allocator.free(address, order);
+                }
+                address += block_size(order);
+            }
+        }
+        Some(allocator)
+    }
+
+    fn entry(&self, address: u64) -> Option<u8> {
+        self.orders.get((address / FRAME_SIZE) as usize).copied()
+    }
+
+    fn set_entry(&mut self, address: u64, value: u8) {
+        if let Some(entry) = self.orders.get_mut((address / FRAME_SIZE) as usize) {
+            *entry = value;
+        }
+    }
+
+    unsafe fn push(&mut self, address: u64, order: usize) {
+        let head = self.heads[order];
+        *block(address) = FreeBlock {
+            next: head,
+            prev: 0,
+        };
+        if head != 0 {
+            block(head).prev = address;
+        }
+        self.heads[order] = address;
+        self.set_entry(address, FREE | order as u8);
+        self.stats[order].free += 1;
+    }
+
+    unsafe fn unlink(&mut self, address: u64, order: usize) {
+        let FreeBlock { next, prev } = *block(address);
+        if prev == 0 {
+            self.heads[order] = next;
+        } else {
+            block(prev).next = next;
+        }
+        if next != 0 {
+            block(next).prev = prev;
+        }
+        self.set_entry(address, 0);
+        self.stats[order].free -= 1;
+    }
+
+    /// Returns a block of `2^order` frames aligned on its size, or None if there is no such block left
+    pub fn allocate(&mut self, order: usize) -> Option<u64> {
+        if order > MAX_ORDER {
+            return None;
+        }
+        let found = match (order..ORDERS).find(|o| self.heads[*o] != 0) {
+            Some(found) => found,
+            None => {
+                self.stats[order].failures += 1;
+                return None;
+            }
+        };
+        unsafe {
+            let address = self.heads[found];
+            self.unlink(address, found);
+            // Gives back the upper halves
+            for split in (order..found).rev() {
+                self.stats[split + 1].splits += 1;
+                self.push(address + block_size(split), split);
+            }
+            self.stats[order].allocations += 1;
+            self.used += 1 << order;
+            Some(address)
+        }
+    }
+
+    /// Returns true if a free block overlaps the block of `2^order` frames at `address`, aligned on its size
+    fn overlaps_free(&self, address: u64, order: usize) -> bool {
+        let is_free = |entry: Option<u8>, least_order: usize| match entry {
+            Some(entry) => entry & FREE != 0 && (entry & !FREE) as usize >= least_order,
+            None => false,
+        };
+        // A larger free block containing it
+        (order + 1..ORDERS).any(|o| is_free(self.entry(address & !(block_size(o) - 1)), o))
+            // A free block starting inside of it
+            || (address..address + block_size(order))
+                .step_by(FRAME_SIZE as usize)
+                .any(|frame| is_free(self.entry(frame), 0))
+    }
+
+    /// Gives back the block of `2^order` frames at `address`, allocated with the same order.
+    /// Frames outside of the memory managed, and blocks of which any frame is already free, are ignored.
+    pub fn free(&mut self, address: u64, order: usize) {
+        if order > MAX_ORDER || self.entry(address).is_none() {
+            return;
+        }
+        let mut address = address & !(block_size(order) - 1);
+        if self.overlaps_free(address, order) {
+            return;
+        }
+        // The frames of the bootloader given back were never handed out
+        self.used = self.used.saturating_sub(1 << order);
+        let mut order = order;
+        unsafe {
+            while order < MAX_ORDER {
+                let buddy = address ^ block_size(order);
+                if self.entry(buddy) != Some(FREE | order as u8) {
+                    break;
+                }
+                self.unlink(buddy, order);
+                self.stats[order].merges += 1;
+                address &= !block_size(order);
+                order += 1;
+            }
+            self.push(address, order);
+        }
+    }
+
+    /// Returns the number of free frames
+    pub fn free_frames(&self) -> u64 {
+        self.stats
+            .iter()
+            .enumerate()
+            .map(|(order, stats)| stats.free << order)
+            .sum()
+    }
+
+    /// Returns the number of frames handed out
+    pub fn used_frames(&self) -> u64 {
+        self.used
+    }
+
+    pub fn stats(&self) -> &[OrderStats; ORDERS] {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::FRAME_ALLOCATOR;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use x86_64::PhysAddr;
+
+    /// Allocator of a single free block of `MAX_ORDER`, taken from the frame allocator and given back when dropped
+    struct Block {
+        buddy: BuddyAllocator,
+        start: u64,
+    }
+
+    impl Block {
+        fn new() -> Self {
+            let start = unsafe { FRAME_ALLOCATOR.as_mut() }
+                .and_then(|frame_allocator| frame_allocator.allocate_contiguous(MAX_ORDER))
+                .expect("No block left for the test")
+                .as_u64();
+            let frames = ((start + block_size(MAX_ORDER)) / FRAME_SIZE) as usize;
+            let mut buddy = BuddyAllocator::empty();
+            buddy.orders = Box::leak(vec![0; frames].into_boxed_slice());
+            buddy.free(start, MAX_ORDER);
+            Self { buddy, start }
+        }
+    }
+
+    impl Drop for Block {
+        fn drop(&mut self) {
+            unsafe {
+                drop(Box::from_raw(
+                    core::mem::take(&mut self.buddy.orders) as *mut [u8]
+                ));
+                if let Some(frame_allocator) = &mut FRAME_ALLOCATOR {
+                    frame_allocator.deallocate_contiguous(PhysAddr::new(self.start), MAX_ORDER);
+                }
+            }
+        }
+    }
+
+    #[test_case]
+    fn allocation_splits_the_block() {
+        let mut block = Block::new();
+        let start = block.start;
+        let buddy = &mut block.buddy;
+        assert_eq!(buddy.allocate(0), Some(start));
+        // The upper half of each order is left free
+        for order in 0..MAX_ORDER {
+            assert_eq!(buddy.stats()[order].free, 1);
+            assert_eq!(buddy.stats()[order + 1].splits, 1);
+        }
+        assert_eq!(buddy.stats()[MAX_ORDER].free, 0);
+        assert_eq!(buddy.free_frames(), (1 << MAX_ORDER) - 1);
+        assert_eq!(buddy.used_frames(), 1);
+    }
+
+    #[test_case]
+    fn blocks_are_aligned_on_their_size() {
+        let mut block = Block::new();
+        let buddy = &mut block.buddy;
+        let frame = buddy.allocate(0).unwrap();
+        let large = buddy.allocate(3).unwrap();
+        assert_eq!(large % block_size(3), 0);
+        assert!(frame + block_size(0) <= large);
+        assert_eq!(buddy.used_frames(), 1 + (1 << 3));
+    }
+
+    #[test_case]
+    fn freeing_merges_the_buddies() {
+        let mut block = Block::new();
+        let start = block.start;
+        let buddy = &mut block.buddy;
+        let first = buddy.allocate(0).unwrap();
+        let second = buddy.allocate(0).unwrap();
+        assert_eq!(second, first ^ block_size(0));
+        buddy.free(first, 0);
+        // Its buddy is still in use
+        assert_eq!(buddy.stats()[0].merges, 0);
+        assert_eq!(buddy.stats()[0].free, 1);
+        buddy.free(second, 0);
+        for order in 0..MAX_ORDER {
+            assert_eq!(buddy.stats()[order].merges, 1);
+            assert_eq!(buddy.stats()[order].free, 0);
+        }
+        assert_eq!(buddy.used_frames(), 0);
+        assert_eq!(buddy.allocate(MAX_ORDER), Some(start));
+    }
+
+    #[test_case]
+    fn freeing_twice_is_ignored() {
+        let mut block = Block::new();
+        let buddy = &mut block.buddy;
+        let frame = buddy.allocate(0).unwrap();
+        // Its buddy is free
+        buddy.free(frame, 1);
+        assert_eq!(buddy.used_frames(), 1);
+        buddy.free(frame, 0);
+        buddy.free(frame, 0);
+        // Frames inside of the merged block
+        buddy.free(frame + block_size(0), 0);
+        buddy.free(frame + block_size(MAX_ORDER - 1), MAX_ORDER - 1);
+        assert_eq!(buddy.stats()[MAX_ORDER].free, 1);
+        assert_eq!(buddy.free_frames(), 1 << MAX_ORDER);
+        assert_eq!(buddy.used_frames(), 0);
+    }
+}
@@ -0,0 +1,184 @@
+//! Copy-on-write sharing of the frames of the user processes.
+//!
+//! `fork` does not copy the memory of the parent: every user frame is shared by both address spaces
+//! and its number of references is kept in `REFERENCES`. The writable pages become read-only and are marked
+//! `COPY_ON_WRITE`, so the first write to one of them raises a page fault and `handle_fault`
+//! gives a private copy of the frame to the writer.
+//!
+//! The kernel writes into the user pages through the MMU, so the write protection of `CR0` has to be enabled.
+
+use super::{swap, BootInfoAllocator, FRAME_ALLOCATOR, PHYSICAL_OFFSET};
+use alloc::collections::BTreeMap;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{PageTable, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Marks a page shared by a fork that was writable before
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_53;
+
+/// Number of level 1 entries pointing to each shared frame.
+/// A frame used by a single entry is not in it.
+static mut REFERENCES: BTreeMap<u64, u64> = BTreeMap::new();
+
+/// Returns the number of level 1 entries pointing to `frame`
+pub fn references(frame: PhysAddr) -> u64 {
+    unsafe { REFERENCES.get(&frame.as_u64()).copied().unwrap_or(1) }
+}
+
+/// Adds a reference to `frame`, when an entry pointing to it is copied
+pub fn share(frame: PhysAddr) {
+    unsafe {
+        *REFERENCES.entry(frame.as_u64()).or_insert(1) += 1;
+    }
+}
+
+/// Removes a reference to `frame`.
+/// Returns true iff it was the last one, in which case the frame has to be deallocated.
+pub fn release(frame: PhysAddr) -> bool {
+    unsafe {
+        match REFERENCES.get_mut(&frame.as_u64()) {
+            None => true,
+            Some(count) => {
+                *count -= 1;
+                if *count == 1 {
+                    REFERENCES.remove(&frame.as_u64());
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Shares the page of the level 1 entry `entry` with a new address space, it becomes copy-on-write if it was writable.
+/// Returns the flags the entry of the new address space must have.
+pub fn share_entry(entry: &mut PageTableEntry) -> PageTableFlags {
+    let mut flags = entry.flags();
+    if flags.contains(PageTableFlags::WRITABLE) {
+        flags.remove(PageTableFlags::WRITABLE);
+        flags.insert(COPY_ON_WRITE);
+        entry.set_flags(flags);
+    }
+    share(entry.addr());
+    flags
+}
+
+/// Returns the level 1 entry mapping `address` in `level_4`, if the page is present
+unsafe fn level_1_entry(
+    level_4: PhysFrame,
+    address: VirtAddr,
+) -> Option<&'static mut PageTableEntry> {
+    table_entry(level_4, address).filter(|entry| entry.flags().contains(PageTableFlags::PRESENT))
+}
+
+/// Returns the level 1 entry of `address` in `level_4`, present or not, if its level 1 table exists
+pub(super) unsafe fn table_entry(
+    level_4: PhysFrame,
+    address: VirtAddr,
+) -> Option<&'static mut PageTableEntry> {
+    let mut table = &mut *((level_4.start_address().as_u64() + PHYSICAL_OFFSET) as *mut PageTable);
+    for index in [address.p4_index(), address.p3_index(), address.p2_index()].iter() {
+        let flags = table[*index].flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        table = &mut *((table[*index].addr().as_u64() + PHYSICAL_OFFSET) as *mut PageTable);
+    }
+    Some(&mut table[address.p1_index()])
+}
+
+/// Makes the page at `address` of the address space `level_4` writable again if it is copy-on-write,
+/// copying its frame if it is still shared.
+/// Returns false if the page is not copy-on-write, or if there is no frame left for the copy.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor, and the kernel lock must be held.
+pub unsafe fn make_private(
+    level_4: PhysFrame,
+    address: VirtAddr,
+    frame_allocator: &mut BootInfoAllocator,
+) -> bool {
+    let entry = match level_1_entry(level_4, address) {
+        Some(entry) => entry,
+        None => return false,
+    };
+    let mut flags = entry.flags();
+    if !flags.contains(COPY_ON_WRITE) {
+        return false;
+    }
+    flags.remove(COPY_ON_WRITE);
+    flags.insert(PageTableFlags::WRITABLE);
+    let frame = entry.addr();
+    if references(frame) > 1 {
+        // Some pages are written to the swap first if the frames run low
+        swap::balance();
+        let copy = match frame_allocator.allocate_4k_frame() {
+            Some(copy) => copy,
+            None => return false,
+        };
+        core::ptr::copy_nonoverlapping(
+            (frame.as_u64() + PHYSICAL_OFFSET) as *const u8,
+            (copy.as_u64() + PHYSICAL_OFFSET) as *mut u8,
+            0x1000,
+        );
+        release(frame);
+        entry.set_addr(copy, flags);
+    } else {
+        // The other address spaces already made their own copy
+        entry.set_flags(flags);
+    }
+    x86_64::instructions::tlb::flush(address);
+    true
+}
+
+/// Handles a write to a read-only page of the current address space.
+/// Returns true iff it was a copy-on-write page, the write can then be retried.
+/// # Safety
+/// Needs to be called from the page fault handler, with the kernel lock held.
+pub unsafe fn handle_fault(address: VirtAddr) -> bool {
+    let (level_4, _) = Cr3::read();
+    match &mut FRAME_ALLOCATOR {
+        Some(frame_allocator) => make_private(level_4, address, frame_allocator),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory;
+
+    #[test_case]
+    fn references_are_counted_by_share_and_release() {
+        let frame = memory::allocate_frame().expect("No frame left for the test");
+        assert_eq!(references(frame), 1);
+        share(frame);
+        share(frame);
+        assert_eq!(references(frame), 3);
+        assert!(!release(frame));
+        assert!(!release(frame));
+        // A frame used by a single entry is not counted anymore
+        assert_eq!(references(frame), 1);
+        assert!(unsafe { !REFERENCES.contains_key(&frame.as_u64()) });
+        // The last reference gives the frame back
+        assert!(release(frame));
+        memory::deallocate_frame(frame);
+    }
+
+    #[test_case]
+    fn sharing_an_entry_makes_it_copy_on_write() {
+        let frame = memory::allocate_frame().expect("No frame left for the test");
+        let mut entry = PageTableEntry::new();
+        entry.set_addr(
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        );
+        let flags = share_entry(&mut entry);
+        assert_eq!(flags, entry.flags());
+        assert!(flags.contains(COPY_ON_WRITE));
+        assert!(!flags.contains(PageTableFlags::WRITABLE));
+        assert_eq!(references(frame), 2);
+        assert!(!release(frame));
+        assert!(release(frame));
+        memory::deallocate_frame(frame);
+    }
+}
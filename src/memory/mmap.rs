@@ -0,0 +1,297 @@
+//! Files of the UsTar partition mapped into address spaces.
+//!
+//! A page of a mapped file is read from the sectors of the file the first time it is touched, into a page cache
+//! shared by all the mappings of the file. A shared mapping maps the frames of the cache with `shm::SHARED`,
+//! so its writes are seen by the other mappings of the file, and `sync` writes its dirty pages back in place.
+//! A private mapping maps them read-only, copy-on-write if it is writable: its writes go to its own copy.
+//!
+//! The size of the file never changes: the end of its last page reads as zeroes and is not part of the file.
+//! The cache holds a reference to each of its frames, counted in `cow`, until the last mapping of the file is gone.
+
+use super::{cow, shm, swap, FRAME_ALLOCATOR, PHYSICAL_OFFSET};
+use crate::data_storage::path::Path;
+use crate::filesystem;
+use crate::filesystem::drivers::disk_operations;
+use crate::filesystem::partition::FileSectors;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+const SECTOR_SIZE: u64 = 512;
+
+const SECTORS_PER_PAGE: u64 = PAGE_SIZE / SECTOR_SIZE;
+
+/// A call to `mmap`, shared by the address spaces forked from the one that made it
+struct MappedFile {
+    path: String,
+    /// Offset in the file of the first page, page aligned
+    offset: u64,
+    shared: bool,
+    /// Number of regions using it
+    references: u64,
+}
+
+/// Pages of a mapped file read so far, by index in the file
+struct CachedFile {
+    sectors: FileSectors,
+    pages: BTreeMap<u64, PhysAddr>,
+    /// Number of `MappedFile` of the file
+    mappings: u64,
+}
+
+static mut MAPPINGS: BTreeMap<u64, MappedFile> = BTreeMap::new();
+
+static mut CACHE: BTreeMap<String, CachedFile> = BTreeMap::new();
+
+static mut NEXT_ID: u64 = 1;
+
+/// Creates a mapping of the file at `path` from `offset`, which must be page aligned.
+/// Returns its id, None if the file can not be mapped or if `offset` is past its end.
+pub fn create(path: &str, offset: u64, shared: bool) -> Option<u64> {
+    unsafe {
+        if !CACHE.contains_key(path) {
+            let sectors = filesystem::file_sectors(&Path::from(path))?;
+            CACHE.insert(
+                String::from(path),
+                CachedFile {
+                    sectors,
+                    pages: BTreeMap::new(),
+                    mappings: 0,
+                },
+            );
+        }
+        let cached = CACHE.get_mut(path)?;
+        if offset % PAGE_SIZE != 0 || offset >= cached.sectors.length {
+            if cached.mappings == 0 {
+                CACHE.remove(path);
+            }
+            return None;
+        }
+        cached.mappings += 1;
+        let id = NEXT_ID;
+        NEXT_ID += 1;
+        MAPPINGS.insert(
+            id,
+            MappedFile {
+                path: String::from(path),
+                offset,
+                shared,
+                references: 1,
+            },
+        );
+        Some(id)
+    }
+}
+
+/// Returns the number of pages of the file from the offset of the mapping `id`
+pub fn pages(id: u64) -> Option<u64> {
+    unsafe {
+        let mapping = MAPPINGS.get(&id)?;
+        let cached = CACHE.get(&mapping.path)?;
+        Some((cached.sectors.length - mapping.offset + PAGE_SIZE - 1) / PAGE_SIZE)
+    }
+}
+
+/// Returns true iff the mapping `id` is of the file at `path`
+pub fn maps(id: u64, path: &str) -> bool {
+    unsafe {
+        MAPPINGS
+            .get(&id)
+            .map_or(false, |mapping| mapping.path == path)
+    }
+}
+
+/// Adds a reference to the mapping `id`, when the regions of an address space are copied by a fork
+pub fn share(id: u64) {
+    unsafe {
+        if let Some(mapping) = MAPPINGS.get_mut(&id) {
+            mapping.references += 1;
+        }
+    }
+}
+
+/// Removes a reference to the mapping `id`. The cache of the file is dropped with its last mapping,
+/// the pages stay in the address spaces that still map them.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn release(id: u64) {
+    match MAPPINGS.get_mut(&id) {
+        Some(mapping) if mapping.references > 1 => {
+            mapping.references -= 1;
+            return;
+        }
+        Some(_) => (),
+        None => return,
+    }
+    let path = match MAPPINGS.remove(&id) {
+        Some(mapping) => mapping.path,
+        None => return,
+    };
+    match CACHE.get_mut(&path) {
+        Some(cached) if cached.mappings > 1 => {
+            cached.mappings -= 1;
+            return;
+        }
+        Some(_) => (),
+        None => return,
+    }
+    if let (Some(cached), Some(frame_allocator)) = (CACHE.remove(&path), &mut FRAME_ALLOCATOR) {
+        for frame in cached.pages.values() {
+            if cow::release(*frame) {
+                frame_allocator.deallocate_4k_frame(*frame);
+            }
+        }
+    }
+}
+
+/// Reads the page `index` of the file into the frame at `frame`
+unsafe fn read_page(sectors: &FileSectors, index: u64, frame: PhysAddr) {
+    let data = (frame.as_u64() + PHYSICAL_OFFSET) as *mut u8;
+    core::ptr::write_bytes(data, 0, PAGE_SIZE as usize);
+    for sector in 0..SECTORS_PER_PAGE {
+        let lba = match sectors
+            .sectors
+            .get((index * SECTORS_PER_PAGE + sector) as usize)
+        {
+            Some(lba) => *lba,
+            None => break,
+        };
+        let words = disk_operations::read_sector(lba, sectors.port);
+        for (i, word) in words.iter().enumerate() {
+            let [high, low] = word.to_be_bytes();
+            let byte = (sector * SECTOR_SIZE) as usize + 2 * i;
+            *data.add(byte) = high;
+            *data.add(byte + 1) = low;
+        }
+    }
+    // The end of the last sector is not part of the file
+    let end = sectors.length.saturating_sub(index * PAGE_SIZE);
+    if end < PAGE_SIZE {
+        core::ptr::write_bytes(data.add(end as usize), 0, (PAGE_SIZE - end) as usize);
+    }
+}
+
+/// Writes the frame at `frame` back to the page `index` of the file
+unsafe fn write_page(sectors: &FileSectors, index: u64, frame: PhysAddr) {
+    let data = (frame.as_u64() + PHYSICAL_OFFSET) as *const u8;
+    for sector in 0..SECTORS_PER_PAGE {
+        let lba = match sectors
+            .sectors
+            .get((index * SECTORS_PER_PAGE + sector) as usize)
+        {
+            Some(lba) => *lba,
+            None => break,
+        };
+        let mut words = [0_u16; 256];
+        for (i, word) in words.iter_mut().enumerate() {
+            let byte = (sector * SECTOR_SIZE) as usize + 2 * i;
+            *word = u16::from_be_bytes([*data.add(byte), *data.add(byte + 1)]);
+        }
+        disk_operations::write_sector(&words, lba, sectors.port);
+    }
+}
+
+/// Maps the page at `page` of the mapping `id` in `level_4`, the region of the mapping starting at `start`.
+/// `flags` are the flags of the region: a shared mapping adds `shm::SHARED` to them,
+/// a private one removes `WRITABLE` and adds `cow::COPY_ON_WRITE` instead.
+/// Returns false if the page is past the end of the file or if no frame could be found for it.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor, and the page must not be mapped.
+pub unsafe fn handle_fault(
+    id: u64,
+    level_4: PhysFrame,
+    start: u64,
+    page: VirtAddr,
+    flags: PageTableFlags,
+) -> bool {
+    let mapping = match MAPPINGS.get(&id) {
+        Some(mapping) => mapping,
+        None => return false,
+    };
+    let index = (mapping.offset + page.as_u64() - start) / PAGE_SIZE;
+    let allocate = || match &mut FRAME_ALLOCATOR {
+        Some(frame_allocator) => frame_allocator.allocate_4k_frame(),
+        None => None,
+    };
+    let frame = match CACHE.get(&mapping.path) {
+        Some(cached) if index * PAGE_SIZE >= cached.sectors.length => return false,
+        Some(cached) => cached.pages.get(&index).copied(),
+        None => return false,
+    };
+    let frame = match frame {
+        Some(frame) => frame,
+        None => {
+            swap::balance();
+            let frame = match allocate() {
+                Some(frame) => frame,
+                None => return false,
+            };
+            match CACHE.get_mut(&mapping.path) {
+                Some(cached) => {
+                    read_page(&cached.sectors, index, frame);
+                    cached.pages.insert(index, frame);
+                }
+                None => return false,
+            }
+            frame
+        }
+    };
+    let mut flags = flags;
+    if mapping.shared {
+        flags |= shm::SHARED;
+    } else if flags.contains(PageTableFlags::WRITABLE) {
+        flags.remove(PageTableFlags::WRITABLE);
+        flags.insert(cow::COPY_ON_WRITE);
+    }
+    match &mut FRAME_ALLOCATOR {
+        Some(frame_allocator)
+            if frame_allocator
+                .map_frame(level_4, frame, page, flags)
+                .is_ok() =>
+        {
+            cow::share(frame);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Writes back the dirty pages of the mapping `id` among the `pages` pages mapped from `start` in `level_4`.
+/// Does nothing for a private mapping.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor.
+pub unsafe fn sync(id: u64, level_4: PhysFrame, start: VirtAddr, pages: u64) {
+    let mapping = match MAPPINGS.get(&id) {
+        Some(mapping) if mapping.shared => mapping,
+        _ => return,
+    };
+    let cached = match CACHE.get(&mapping.path) {
+        Some(cached) => cached,
+        None => return,
+    };
+    for index in 0..pages {
+        let page = start + index * PAGE_SIZE;
+        let entry = match cow::table_entry(level_4, page) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT | PageTableFlags::DIRTY | shm::SHARED) {
+            continue;
+        }
+        write_page(
+            &cached.sectors,
+            mapping.offset / PAGE_SIZE + index,
+            entry.addr(),
+        );
+        flags.remove(PageTableFlags::DIRTY);
+        entry.set_flags(flags);
+        if Cr3::read().0 == level_4 {
+            x86_64::instructions::tlb::flush(page);
+        }
+    }
+}
@@ -1,17 +1,27 @@
 //! Crate for managing the paging: allocating and desallocating pages and editing page tables
 use crate::println;
 use alloc::string::String;
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use alloc::vec::Vec;
+use bootloader::bootinfo::MemoryMap;
 use x86_64::structures::paging::OffsetPageTable;
-use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB, Size4KiB};
 use x86_64::{
-    registers::control::Cr3,
+    registers::control::{Cr0, Cr0Flags, Cr3},
+    registers::model_specific::{Efer, EferFlags},
     structures::paging::{PageTable, PageTableFlags},
     PhysAddr, VirtAddr,
 };
 
 use crate::warningln;
 
+pub mod buddy;
+pub mod cow;
+pub mod mmap;
+pub mod shm;
+pub mod swap;
+
+use buddy::BuddyAllocator;
+
 /// Static structure holding the frame allocator. You can borrow it but never place it back to None !.
 /// You can asume it is never None.
 pub static mut FRAME_ALLOCATOR: Option<BootInfoAllocator> = None;
@@ -22,9 +32,6 @@ pub struct MemoryError(pub String);
 /// Memory address translation (virtual -> physical) now has to be done with `Translate::translate_addr`
 pub static mut PHYSICAL_OFFSET: u64 = 0;
 
-/// Number of allocatable tables
-static mut NUMBER_TABLES: u64 = 0;
-
 /// Read Cr3 to give the current level_4 table
 /// Should only be called one
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
@@ -46,6 +53,10 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
 /// Must be called at least once to avoid `&mut` aliasing
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     PHYSICAL_OFFSET = physical_memory_offset.as_u64();
+    // The kernel has to fault when it writes into a copy-on-write page, see `cow`
+    Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    // Without it, the processor ignores `NO_EXECUTE`. The application processors copy it.
+    Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
     let level_4_table: &'static mut PageTable = active_level_4_table(physical_memory_offset);
 
     // Just for the stats, can be removed
@@ -114,10 +125,30 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Frames of the buddy allocator, behind a lock of their own: the kernel heap takes its frames here
+/// while `FRAME_ALLOCATOR` may be borrowed by the code that allocates, see `allocate_frame`.
+static FRAMES: spin::Mutex<Option<BuddyAllocator>> = spin::Mutex::new(None);
+
+/// Returns a free frame of the buddy allocator, None if there is none left or if it is not initialised.
+/// Unlike `BootInfoAllocator::allocate_4k_frame` it does not need `FRAME_ALLOCATOR`.
+pub fn allocate_frame() -> Option<PhysAddr> {
+    FRAMES
+        .lock()
+        .as_mut()
+        .and_then(|buddy| buddy.allocate(0))
+        .map(PhysAddr::new)
+}
+
+/// Gives back a frame of `allocate_frame`
+pub fn deallocate_frame(addr: PhysAddr) {
+    if let Some(buddy) = FRAMES.lock().as_mut() {
+        buddy.free(addr.as_u64(), 0)
+    }
+}
+
 /// Structure of the page allocator, holding the data of every page available.
-/// It can be improved in terms of place taken and performances
+/// The frames are given by a buddy allocator, see `buddy`, which is shared with the kernel heap through `FRAMES`.
 pub struct BootInfoAllocator {
-    head: u64,
     level4_table: &'static PageTable, // level4_table : kernel's level 4 table
 }
 
@@ -127,47 +158,50 @@ impl BootInfoAllocator {
     /// Creates a new allocator from the RAM map given by the bootloader
     /// and the offset to the physical memory given also by the bootloader
     pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) {
-        let regions = memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-
-        let mut head = 0;
-
-        // Fill up the table with every addresses
-        for next in frame_addresses {
-            let v: *mut u64 = (next + PHYSICAL_OFFSET) as *mut u64;
-            *v = head;
-            head = next;
-        }
-        println!("Number of available tables in RAM : {}", NUMBER_TABLES); // just for show, should be removed
+        let buddy = match BuddyAllocator::new(memory_map, crate::smp::is_reserved_frame) {
+            Some(buddy) => buddy,
+            None => panic!("No usable memory for the frame allocator"),
+        };
+        println!(
+            "Number of available tables in RAM : {}",
+            buddy.free_frames()
+        );
 
+        *FRAMES.lock() = Some(buddy);
         FRAME_ALLOCATOR = Some(BootInfoAllocator {
-            head,
             level4_table: active_level_4_table(physical_memory_offset),
         });
     }
 
+    /// Returns the number of free frames
     pub fn state(&self) -> usize {
-        unsafe {
-            let mut h = self.head;
-            let mut total = 1;
-            loop {
-                let v: u64 = *((h + PHYSICAL_OFFSET) as *const u64);
-                if v == 0 {
-                    return total
-                }
-                total += 1;
-                h = v;
-            }
-        }
+        FRAMES
+            .lock()
+            .as_ref()
+            .map_or(0, |buddy| buddy.free_frames() as usize)
+    }
+
+    /// Returns the number of frames handed out
+    pub fn used_frames(&self) -> usize {
+        FRAMES
+            .lock()
+            .as_ref()
+            .map_or(0, |buddy| buddy.used_frames() as usize)
+    }
+
+    /// Returns the statistics of each order of the buddy allocator
+    pub fn order_stats(&self) -> [buddy::OrderStats; buddy::ORDERS] {
+        FRAMES
+            .lock()
+            .as_ref()
+            .map_or([buddy::OrderStats::default(); buddy::ORDERS], |buddy| {
+                *buddy.stats()
+            })
     }
 
     pub fn empty() -> Self {
         unsafe {
             Self {
-                head: 0,
                 level4_table: &*VirtAddr::zero().as_ptr(),
             }
         }
@@ -175,24 +209,28 @@ impl BootInfoAllocator {
 
     /// Returns a new unallocated Frame and marks it as allocated.
     pub fn allocate_4k_frame(&mut self) -> Option<PhysAddr> {
-        unsafe {
-            let v: *const u64 = (self.head + PHYSICAL_OFFSET) as *const u64;
-            if *v == 0 {
-                return None
-            } else {
-                let head = self.head;
-                self.head = *v;
-                Some(PhysAddr::new(head))
-            }
-        }
+        allocate_frame()
     }
 
     /// Can be used to deallocate a specific 4Ki frame
     pub fn deallocate_4k_frame(&mut self, addr: PhysAddr) {
-        unsafe {
-            let next = addr.as_u64();
-            *((next + PHYSICAL_OFFSET) as *mut u64) = self.head; 
-            self.head = next;
+        deallocate_frame(addr)
+    }
+
+    /// Returns `2^order` physically contiguous frames, aligned on their size, for DMA buffers or huge pages.
+    /// `order` goes up to `buddy::MAX_ORDER` (2 MiB).
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<PhysAddr> {
+        FRAMES
+            .lock()
+            .as_mut()
+            .and_then(|buddy| buddy.allocate(order))
+            .map(PhysAddr::new)
+    }
+
+    /// Deallocates frames given by `allocate_contiguous` with the same `order`
+    pub fn deallocate_contiguous(&mut self, addr: PhysAddr, order: usize) {
+        if let Some(buddy) = FRAMES.lock().as_mut() {
+            buddy.free(addr.as_u64(), order)
         }
     }
 
@@ -235,6 +273,25 @@ impl BootInfoAllocator {
         self.add_entry_to_table_4(&mut *page_table_ptr, virt_4, flags, allow_duplicate)
     }
 
+    /// Maps `virt_4` to the existing frame `frame` in the level 4 table `table_4`, with the given flags.
+    /// The missing tables are created as by `add_entry_to_table`.
+    /// # Safety
+    /// You should mark it as USER_ACCESSIBLE and PRESENT !
+    pub unsafe fn map_frame(
+        &mut self,
+        table_4: PhysFrame,
+        frame: PhysAddr,
+        virt_4: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<(), MemoryError> {
+        self.add_entry_to_table(table_4, virt_4, flags, false)?;
+        if let Some(entry) = cow::table_entry(table_4, virt_4) {
+            self.deallocate_4k_frame(entry.addr());
+            entry.set_addr(frame, flags);
+        }
+        Ok(())
+    }
+
     /// Creates a new entry in the level_4 table at the given entry (virt) with the given flags
     /// # Safety
     /// You should mark it as USER_ACCESSIBLE and PRESENT !
@@ -373,7 +430,12 @@ impl BootInfoAllocator {
         let entry = table_1[p_1].flags();
         if entry.contains(PageTableFlags::PRESENT) {
             if allow_duplicate {
-                table_1[p_1].set_flags(flag_union(entry, flags));
+                let mut new_flags = flag_union(entry, flags);
+                // A shared page stays read-only until it is written to
+                if entry.contains(cow::COPY_ON_WRITE) {
+                    new_flags.remove(PageTableFlags::WRITABLE);
+                }
+                table_1[p_1].set_flags(new_flags);
                 Ok(())
             } else {
                 warningln!("already here, l.301 {:#?} {:#?}", virt_1, entry);
@@ -714,17 +776,19 @@ impl BootInfoAllocator {
 
     /// # Safety
     /// Function to duplicate an level 4 table into a new one.
-    /// Give a level 4 table, it gives you a new one holding the same datas
+    /// Give a level 4 table, it gives you a new one holding the same datas.
+    /// The user pages are shared copy-on-write, so `table_4` has to be the current table
+    /// or one that is not running. Nothing is left allocated if it fails.
     pub unsafe fn copy_table_entries(
         &mut self,
         table_4: PhysAddr,
     ) -> Result<PhysAddr, MemoryError> {
         let virt = VirtAddr::new(table_4.as_u64() + PHYSICAL_OFFSET);
         let table4: *mut PageTable = virt.as_mut_ptr();
-        match self.copy_table_4(&*table4) {
-            Ok(phys) => Ok(phys),
-            Err(MemoryError(a)) => Err(MemoryError(a)),
-        }
+        let result = self.copy_table_4(&*table4);
+        // The writable pages of `table_4` became read-only
+        x86_64::instructions::tlb::flush_all();
+        result
     }
 
     /// Inner function to copy a table of level 4 in order to allow fork operations
@@ -748,6 +812,12 @@ impl BootInfoAllocator {
                                 (*new_table)[i].set_flags(PageTableFlags::empty());
                             }
                             println!("failure under level 3");
+                            self.deallocate_level_4_page(
+                                new_table_addr,
+                                PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
+                                true,
+                            );
+                            self.deallocate_4k_frame(new_table_addr);
                             return Err(MemoryError(String::from("Could not copy level 3 table")));
                         }
                     } else {
@@ -786,6 +856,12 @@ impl BootInfoAllocator {
                                 (*new_table)[i].set_flags(PageTableFlags::empty());
                             }
                             println!("failure under level 2");
+                            self.deallocate_level_3_page(
+                                &mut *new_table,
+                                PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
+                                true,
+                            );
+                            self.deallocate_4k_frame(new_table_addr);
                             return Err(MemoryError(String::from("Could not copy level 2 table")));
                         }
                     } else {
@@ -818,13 +894,19 @@ impl BootInfoAllocator {
                     if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
                         let virt = VirtAddr::new(table_2[index].addr().as_u64() + PHYSICAL_OFFSET);
                         let level_1: *mut PageTable = virt.as_mut_ptr();
-                        if let Ok(level_1_addr) = self.copy_table_1(&*level_1) {
+                        if let Ok(level_1_addr) = self.copy_table_1(&mut *level_1) {
                             (*new_table)[index].set_addr(level_1_addr, flags);
                         } else {
                             for i in index..512 {
                                 (*new_table)[i].set_flags(PageTableFlags::empty());
                             }
                             println!("failure under level 1");
+                            self.deallocate_level_2_page(
+                                &mut *new_table,
+                                PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
+                                true,
+                            );
+                            self.deallocate_4k_frame(new_table_addr);
                             return Err(MemoryError(String::from("Could not copy level 1 table")));
                         }
                     } else {
@@ -843,10 +925,11 @@ impl BootInfoAllocator {
         }
     }
 
-    /// Function to copy a table of level 1 in order to allow fork operations
+    /// Function to copy a table of level 1 in order to allow fork operations.
+    /// The user frames are not copied but shared, see `cow`.
     unsafe fn copy_table_1(
         &mut self,
-        table_1: &'static PageTable,
+        table_1: &'static mut PageTable,
     ) -> Result<PhysAddr, MemoryError> {
         if let Some(new_table_addr) = self.allocate_4k_frame() {
             let virt_table = VirtAddr::new(new_table_addr.as_u64() + PHYSICAL_OFFSET);
@@ -854,30 +937,21 @@ impl BootInfoAllocator {
             for index in 0..512 {
                 let flags = table_1[index].flags();
                 if flags.contains(PageTableFlags::PRESENT) {
-                    if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
-                        if let Some(data_table) = self.allocate_4k_frame() {
-                            let virt =
-                                VirtAddr::new(table_1[index].addr().as_u64() + PHYSICAL_OFFSET);
-                            let old_table: *mut [u64; 512] = virt.as_mut_ptr();
-                            let virt_next = VirtAddr::new(data_table.as_u64() + PHYSICAL_OFFSET);
-                            let next_table: *mut [u64; 512] = virt_next.as_mut_ptr();
-                            for i in 0..512 {
-                                (*next_table)[i] = (*old_table)[i];
-                            }
-                            (*new_table)[index].set_addr(data_table, flags);
-                        } else {
-                            for i in index..512 {
-                                (*new_table)[i].set_flags(PageTableFlags::empty());
-                            }
-                            println!("failure at level 1");
-                            return Err(MemoryError(String::from(
-                                "Could not allocate 4k frame in level 1 copy #0",
-                            )));
-                        }
+                    if flags.contains(shm::SHARED) {
+                        // Shared memory stays shared, without copy-on-write
+                        cow::share(table_1[index].addr());
+                        (*new_table)[index].set_addr(table_1[index].addr(), flags);
+                    } else if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                        let shared_flags = cow::share_entry(&mut table_1[index]);
+                        (*new_table)[index].set_addr(table_1[index].addr(), shared_flags);
                     } else {
                         println!("Not user Accessible at level 1");
                         (*new_table)[index].set_addr(table_1[index].addr(), flags);
                     }
+                } else if flags.contains(swap::SWAPPED) {
+                    // Both address spaces read the page back from the same slot
+                    swap::share(table_1[index].addr());
+                    (*new_table)[index].set_addr(table_1[index].addr(), flags);
                 } else {
                     (*new_table)[index].set_flags(flags);
                 }
@@ -1016,9 +1090,16 @@ impl BootInfoAllocator {
                 let flags = table_1[i].flags();
                 if flags.contains(remove_flags) {
                     table_1[i].set_flags(PageTableFlags::empty());
-                    if full_deallocate {
+                    if full_deallocate && cow::release(table_1[i].addr()) {
                         self.deallocate_4k_frame(table_1[i].addr())
                     }
+                } else if flags.contains(swap::SWAPPED)
+                    && flags.contains(remove_flags - PageTableFlags::PRESENT)
+                {
+                    table_1[i].set_flags(PageTableFlags::empty());
+                    if full_deallocate {
+                        swap::release(table_1[i].addr())
+                    }
                 } else {
                     flags_left |= flags
                 }
@@ -1043,7 +1124,22 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoAllocator {
     }
 }
 
+/// 2 MiB frames, given by the buddy allocator
+unsafe impl FrameAllocator<Size2MiB> for BootInfoAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.allocate_contiguous(buddy::MAX_ORDER)
+            .and_then(|addr| PhysFrame::from_start_address(addr).ok())
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BootInfoAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        self.deallocate_contiguous(frame.start_address(), buddy::MAX_ORDER)
+    }
+}
+
 /// This may be totally wrong
+/// The pages in the swap are brought back and the copy-on-write pages written to get their own frame first.
 /// # Safety
 /// TODO
 pub unsafe fn write_into_virtual_memory(
@@ -1054,6 +1150,8 @@ pub unsafe fn write_into_virtual_memory(
     let offset: usize = virt_4.page_offset().into();
     let length = data.len();
     let mut virtaddr: VirtAddr = virt_4;
+    swap::swap_in(table_4, virtaddr);
+    make_private(table_4, virtaddr);
     let mut physaddr: PhysAddr = match translate_addr(table_4, virtaddr) {
         Some(a) => a,
         None => {
@@ -1064,6 +1162,8 @@ pub unsafe fn write_into_virtual_memory(
     };
     for i in offset..(length + offset) {
         if virtaddr.as_u64() & 0xfff == 0 {
+            swap::swap_in(table_4, virtaddr);
+            make_private(table_4, virtaddr);
             physaddr = match translate_addr(table_4, virtaddr) {
                 Some(a) => a,
                 None => {
@@ -1081,6 +1181,43 @@ pub unsafe fn write_into_virtual_memory(
     Ok(())
 }
 
+/// Removes the `pages` pages mapped from `start` in `level_4`, freeing the frames and the swap slots
+/// they were the last to use.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor.
+pub unsafe fn unmap_pages(level_4: PhysFrame, start: VirtAddr, pages: u64) {
+    for index in 0..pages {
+        let page = start + index * 0x1000;
+        let entry = match cow::table_entry(level_4, page) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let flags = entry.flags();
+        let address = entry.addr();
+        if flags.contains(PageTableFlags::PRESENT) {
+            entry.set_flags(PageTableFlags::empty());
+            if Cr3::read().0 == level_4 {
+                x86_64::instructions::tlb::flush(page);
+            }
+            if cow::release(address) {
+                if let Some(frame_allocator) = &mut FRAME_ALLOCATOR {
+                    frame_allocator.deallocate_4k_frame(address);
+                }
+            }
+        } else if flags.contains(swap::SWAPPED) {
+            entry.set_flags(PageTableFlags::empty());
+            swap::release(address);
+        }
+    }
+}
+
+/// Gives its own frame to the page at `address` if it is copy-on-write
+unsafe fn make_private(table_4: PhysFrame, address: VirtAddr) {
+    if let Some(frame_allocator) = &mut FRAME_ALLOCATOR {
+        cow::make_private(table_4, address, frame_allocator);
+    }
+}
+
 /// # Safety
 /// TODO
 pub unsafe fn translate_addr_inner(table_4: PhysFrame, addr: VirtAddr) -> Option<PhysAddr> {
@@ -1113,6 +1250,117 @@ unsafe fn translate_addr(table_4: PhysFrame, addr: VirtAddr) -> Option<PhysAddr>
     Some(frame.start_address())
 }
 
+/// Returns every page of the lower half of the address space of `level_4` whose entries all have `flags`,
+/// with the frame behind it and the flags of its level 1 entry.
+/// # Safety
+/// `level_4` must be a valid level 4 table.
+pub unsafe fn mapped_pages(
+    level_4: PhysFrame,
+    flags: PageTableFlags,
+) -> Vec<(VirtAddr, PhysAddr, PageTableFlags)> {
+    let mut pages = Vec::new();
+    walk_table(level_4.start_address(), 4, 0, flags, &mut pages);
+    pages
+}
+
+unsafe fn walk_table(
+    table: PhysAddr,
+    level: u64,
+    base: u64,
+    flags: PageTableFlags,
+    pages: &mut Vec<(VirtAddr, PhysAddr, PageTableFlags)>,
+) {
+    let table = &*((table.as_u64() + PHYSICAL_OFFSET) as *const PageTable);
+    // Only the lower half belongs to the process
+    let entries = if level == 4 { 256 } else { 512 };
+    for index in 0..entries {
+        let entry = &table[index];
+        if !entry.flags().contains(flags) {
+            continue;
+        }
+        let address = base | ((index as u64) << (12 + 9 * (level - 1)));
+        if level == 1 {
+            pages.push((VirtAddr::new(address), entry.addr(), entry.flags()));
+        } else if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            walk_table(entry.addr(), level - 1, address, flags, pages);
+        }
+    }
+}
+
+/// Returns the first page of the lower half of the address space of `level_4` at or after `from`
+/// whose entries all have `flags`, with the frame behind it and the flags of its level 1 entry.
+/// Unlike `mapped_pages`, it walks the tables in place and allocates nothing.
+/// # Safety
+/// `level_4` must be a valid level 4 table.
+pub unsafe fn next_mapped_page(
+    level_4: PhysFrame,
+    from: VirtAddr,
+    flags: PageTableFlags,
+) -> Option<(VirtAddr, PhysAddr, PageTableFlags)> {
+    next_in_table(level_4.start_address(), 4, 0, from.as_u64(), flags)
+}
+
+unsafe fn next_in_table(
+    table: PhysAddr,
+    level: u64,
+    base: u64,
+    from: u64,
+    flags: PageTableFlags,
+) -> Option<(VirtAddr, PhysAddr, PageTableFlags)> {
+    let table = &*((table.as_u64() + PHYSICAL_OFFSET) as *const PageTable);
+    // Only the lower half belongs to the process
+    let entries = if level == 4 { 256 } else { 512 };
+    let shift = 12 + 9 * (level - 1);
+    // The entries before the one of `from` only hold pages before it
+    let first = if from > base {
+        ((from - base) >> shift) as usize
+    } else {
+        0
+    };
+    for index in first..entries {
+        let entry = &table[index];
+        if !entry.flags().contains(flags) {
+            continue;
+        }
+        let address = base | ((index as u64) << shift);
+        if level == 1 {
+            return Some((VirtAddr::new(address), entry.addr(), entry.flags()));
+        } else if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            if let Some(page) = next_in_table(entry.addr(), level - 1, address, from, flags) {
+                return Some(page);
+            }
+        }
+    }
+    None
+}
+
+/// Returns the number of frames used by the user tables of the lower half of the address space of `level_4`,
+/// the level 4 table included.
+/// # Safety
+/// `level_4` must be a valid level 4 table.
+pub unsafe fn table_frames(level_4: PhysFrame) -> u64 {
+    count_tables(level_4.start_address(), 4)
+}
+
+unsafe fn count_tables(table: PhysAddr, level: u64) -> u64 {
+    if level == 1 {
+        return 1;
+    }
+    let table = &*((table.as_u64() + PHYSICAL_OFFSET) as *const PageTable);
+    // Only the lower half belongs to the process
+    let entries = if level == 4 { 256 } else { 512 };
+    let mut count = 1;
+    for entry in table.iter().take(entries) {
+        let flags = entry.flags();
+        if flags.contains(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE)
+            && !flags.contains(PageTableFlags::HUGE_PAGE)
+        {
+            count += count_tables(entry.addr(), level - 1);
+        }
+    }
+    count
+}
+
 fn flag_union(f1: PageTableFlags, f2: PageTableFlags) -> PageTableFlags {
     if f1.contains(PageTableFlags::NO_EXECUTE) == f2.contains(PageTableFlags::NO_EXECUTE) {
         f1 | f2
@@ -0,0 +1,174 @@
+//! Named shared-memory segments.
+//!
+//! A segment is a set of frames known by a name, that several processes map into their address spaces
+//! to share memory without copying it through syscalls. Its pages are mapped with the flag `SHARED`:
+//! a fork shares them without copy-on-write and they are never swapped out.
+//!
+//! The segment holds a reference to each of its frames and every page mapping one holds another,
+//! counted in `cow`. A frame is freed once the segment was unlinked (or shrunk) and the last page mapping it is gone.
+
+use super::{cow, MemoryError, FRAME_ALLOCATOR, PHYSICAL_OFFSET};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Marks a page of a shared-memory segment
+pub const SHARED: PageTableFlags = PageTableFlags::BIT_55;
+
+/// Largest size of a segment, 64 MiB
+pub const MAX_PAGES: u64 = 1 << 14;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+struct Segment {
+    name: String,
+    frames: Vec<PhysAddr>,
+}
+
+/// Description of a segment, as listed in `/proc/shm`
+#[derive(Clone, Debug)]
+pub struct SegmentInfo {
+    pub id: u64,
+    pub name: String,
+    pub pages: u64,
+    /// Number of address spaces mapping it
+    pub mappings: u64,
+}
+
+/// Segments that still have a name, by id
+static mut SEGMENTS: BTreeMap<u64, Segment> = BTreeMap::new();
+
+static mut NEXT_ID: u64 = 1;
+
+/// Returns the id of the segment `name`, creating an empty one if `create` is true.
+/// Returns None if it does not exist and `create` is false, or if it exists and `exclusive` is true.
+pub fn open(name: &str, create: bool, exclusive: bool) -> Option<u64> {
+    unsafe {
+        match SEGMENTS.iter().find(|(_, segment)| segment.name == name) {
+            Some(_) if exclusive => None,
+            Some((id, _)) => Some(*id),
+            None if create && !name.is_empty() => {
+                let id = NEXT_ID;
+                NEXT_ID += 1;
+                SEGMENTS.insert(
+                    id,
+                    Segment {
+                        name: String::from(name),
+                        frames: Vec::new(),
+                    },
+                );
+                Some(id)
+            }
+            None => None,
+        }
+    }
+}
+
+/// Returns the number of pages of the segment `id`
+pub fn pages(id: u64) -> Option<u64> {
+    unsafe { SEGMENTS.get(&id).map(|segment| segment.frames.len() as u64) }
+}
+
+/// Gives `pages` zeroed pages to the segment `id`. The pages removed stay in the address spaces mapping them.
+/// Returns false if there is no such segment, if it is too large or if there is no frame left.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn resize(id: u64, pages: u64) -> bool {
+    let (segment, frame_allocator) = match (SEGMENTS.get_mut(&id), &mut FRAME_ALLOCATOR) {
+        (Some(segment), Some(frame_allocator)) if pages <= MAX_PAGES => (segment, frame_allocator),
+        _ => return false,
+    };
+    while (segment.frames.len() as u64) > pages {
+        if let Some(frame) = segment.frames.pop() {
+            if cow::release(frame) {
+                frame_allocator.deallocate_4k_frame(frame);
+            }
+        }
+    }
+    let old = segment.frames.len();
+    while (segment.frames.len() as u64) < pages {
+        match frame_allocator.allocate_4k_frame() {
+            Some(frame) => {
+                core::ptr::write_bytes((frame.as_u64() + PHYSICAL_OFFSET) as *mut u8, 0, 0x1000);
+                segment.frames.push(frame);
+            }
+            None => {
+                for frame in segment.frames.drain(old..) {
+                    frame_allocator.deallocate_4k_frame(frame);
+                }
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Removes the name `name`, the frames are freed once no page maps them anymore.
+/// Returns false if there is no such segment.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn unlink(name: &str) -> bool {
+    let id = match SEGMENTS.iter().find(|(_, segment)| segment.name == name) {
+        Some((id, _)) => *id,
+        None => return false,
+    };
+    if let (Some(segment), Some(frame_allocator)) = (SEGMENTS.remove(&id), &mut FRAME_ALLOCATOR) {
+        for frame in segment.frames {
+            if cow::release(frame) {
+                frame_allocator.deallocate_4k_frame(frame);
+            }
+        }
+    }
+    true
+}
+
+/// Maps the whole segment `id` from `start` in `level_4` with `flags`, to which `SHARED` is added.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor, and the range must be free.
+pub unsafe fn map(
+    id: u64,
+    level_4: PhysFrame,
+    start: VirtAddr,
+    flags: PageTableFlags,
+) -> Result<(), MemoryError> {
+    let (segment, frame_allocator) = match (SEGMENTS.get(&id), &mut FRAME_ALLOCATOR) {
+        (Some(segment), Some(frame_allocator)) => (segment, frame_allocator),
+        _ => return Err(MemoryError(String::from("No such shared memory segment"))),
+    };
+    for (index, frame) in segment.frames.iter().enumerate() {
+        let page = start + index as u64 * PAGE_SIZE;
+        if let Err(error) = frame_allocator.map_frame(level_4, *frame, page, flags | SHARED) {
+            unmap(level_4, start, index as u64);
+            return Err(error);
+        }
+        cow::share(*frame);
+    }
+    Ok(())
+}
+
+/// Removes the `pages` pages of a segment mapped from `start` in `level_4`
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor.
+pub unsafe fn unmap(level_4: PhysFrame, start: VirtAddr, pages: u64) {
+    super::unmap_pages(level_4, start, pages)
+}
+
+/// Returns the segments that still have a name
+pub fn list() -> Vec<SegmentInfo> {
+    unsafe {
+        SEGMENTS
+            .iter()
+            .map(|(id, segment)| SegmentInfo {
+                id: *id,
+                name: segment.name.clone(),
+                pages: segment.frames.len() as u64,
+                mappings: segment
+                    .frames
+                    .first()
+                    .map_or(0, |frame| cow::references(*frame) - 1),
+            })
+            .collect()
+    }
+}
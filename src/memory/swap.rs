@@ -0,0 +1,305 @@
+//! Swap space on the disk of the file system.
+//!
+//! When the free frames run low, `balance` writes user pages to a region of the disk on `ustar::DISK_PORT`
+//! that lies after the sectors the UsTar tables can address. The pages are picked by a clock:
+//! the hand goes around the user pages of the processes, a page whose `ACCESSED` bit is set gets
+//! a second chance (the bit is cleared) and the first one found without it is written out.
+//!
+//! The level 1 entry of a page swapped out is not present, is marked `SWAPPED` and holds the slot
+//! in its address bits. Its other flags are kept, so that `handle_fault` maps the page back as it was.
+//! A fork shares the slots the same way it shares the frames, so each slot has a number of references.
+
+use super::{cow, shm, FRAME_ALLOCATOR, PHYSICAL_OFFSET};
+use crate::filesystem::drivers::{disk_operations, ustar};
+use crate::scheduler::process::{self, State, ID};
+use alloc::vec::Vec;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Marks a level 1 entry whose page is in the swap, its address being the slot
+pub const SWAPPED: PageTableFlags = PageTableFlags::BIT_54;
+
+/// First sector of the swap. It is past the `ustar::PARTITION_SECTORS` sectors the kernel addresses,
+/// and past the 32 LBA tables of 512 sectors laid out by `disk/createDisk.py`, which has room for more of them.
+pub const SWAP_START_SECTOR: u32 = 1 << 15;
+
+/// Largest number of pages in the swap, 16 MiB
+pub const SWAP_PAGES: usize = 4096;
+
+const SECTORS_PER_PAGE: u32 = 8;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Pages are swapped out when there are less free frames than this
+pub const LOW_FRAMES: usize = 128;
+
+/// Free frames `balance` tries to reach
+pub const HIGH_FRAMES: usize = 256;
+
+/// Largest number of pages written by a call to `balance`, each one costs 8 sector writes
+const BATCH: usize = 32;
+
+/// Statistics of the swap, in pages
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SwapStats {
+    pub slots: usize,
+    pub used: usize,
+    /// Pages written to the disk since the boot
+    pub swapped_out: u64,
+    /// Pages read back since the boot
+    pub swapped_in: u64,
+    /// Pages spared by the clock because they were accessed
+    pub second_chances: u64,
+}
+
+/// Number of level 1 entries pointing to each slot, 0 if it is free.
+/// Empty if there is no room for a swap on the disk.
+static mut SLOTS: Vec<u32> = Vec::new();
+
+/// Position of the clock: the pid and the address of the last page looked at
+static mut HAND: (u64, u64) = (0, 0);
+
+static mut STATS: SwapStats = SwapStats {
+    slots: 0,
+    used: 0,
+    swapped_out: 0,
+    swapped_in: 0,
+    second_chances: 0,
+};
+
+/// Sizes the swap from the room left on the disk after `SWAP_START_SECTOR`.
+/// The swap stays disabled if the disk image was not made large enough for it.
+pub fn init() {
+    let sectors = disk_operations::sector_count(ustar::DISK_PORT);
+    let slots = (sectors.saturating_sub(SWAP_START_SECTOR) / SECTORS_PER_PAGE) as usize;
+    let slots = slots.min(SWAP_PAGES);
+    unsafe {
+        SLOTS = alloc::vec![0; slots];
+        STATS.slots = slots;
+    }
+    if slots == 0 {
+        crate::warningln!("No room for a swap on the disk, pages will not be swapped out");
+    }
+}
+
+pub fn stats() -> SwapStats {
+    unsafe { STATS }
+}
+
+fn first_sector(slot: usize) -> u32 {
+    SWAP_START_SECTOR + slot as u32 * SECTORS_PER_PAGE
+}
+
+/// Returns the slot stored in a swapped entry
+fn slot_of(address: PhysAddr) -> usize {
+    (address.as_u64() >> 12) as usize
+}
+
+/// Adds a reference to the slot of a swapped entry, when the entry is copied by a fork
+pub fn share(address: PhysAddr) {
+    unsafe {
+        if let Some(references) = SLOTS.get_mut(slot_of(address)) {
+            *references += 1;
+        }
+    }
+}
+
+/// Removes a reference to the slot of a swapped entry, the slot is free once it has none left
+pub fn release(address: PhysAddr) {
+    unsafe {
+        if let Some(references) = SLOTS.get_mut(slot_of(address)) {
+            if *references == 1 {
+                STATS.used -= 1;
+            }
+            *references = references.saturating_sub(1);
+        }
+    }
+}
+
+/// Writes the frame at `frame` into `slot`
+unsafe fn write_slot(slot: usize, frame: PhysAddr) {
+    let data = (frame.as_u64() + PHYSICAL_OFFSET) as *const [u16; 256];
+    for sector in 0..SECTORS_PER_PAGE {
+        disk_operations::write_sector(
+            &*data.add(sector as usize),
+            first_sector(slot) + sector,
+            ustar::DISK_PORT,
+        );
+    }
+}
+
+/// Reads `slot` into the frame at `frame`
+unsafe fn read_slot(slot: usize, frame: PhysAddr) {
+    let data = (frame.as_u64() + PHYSICAL_OFFSET) as *mut [u16; 256];
+    for sector in 0..SECTORS_PER_PAGE {
+        *data.add(sector as usize) =
+            disk_operations::read_sector(first_sector(slot) + sector, ustar::DISK_PORT);
+    }
+}
+
+/// Returns true iff the clock may swap out the pages of `process`.
+/// The processes running on another processor are left alone, their TLB could not be flushed.
+fn is_candidate(process: &process::Process, current: u64) -> bool {
+    let pid = process.get_pid();
+    !(process::is_idle(pid)
+        || process.is_kernel_thread()
+        || matches!(process.state, State::Zombie(_))
+        || (process.state == State::Running && pid.0 != current))
+}
+
+/// Moves the hand to the next user page the clock may swap out, walking the page tables in place:
+/// it runs when frames are scarce, so it must not grow the kernel heap.
+/// The frames shared by a fork and the shared memory are left alone.
+/// Returns the address space and the page, and whether the hand went back to the first process.
+unsafe fn next_candidate() -> Option<(PhysFrame, VirtAddr, bool)> {
+    let current = process::current_pid() as u64;
+    let (hand_pid, hand_address) = HAND;
+    // The process of the hand from its page on, the ones after it, then all the ones up to it
+    let after = process::ID_TABLE
+        .range(ID(hand_pid)..)
+        .map(|(_, process)| (false, process));
+    let before = process::ID_TABLE
+        .range(..=ID(hand_pid))
+        .map(|(_, process)| (true, process));
+    for (wrapped, process) in after.chain(before) {
+        if !is_candidate(process, current) {
+            continue;
+        }
+        let pid = process.get_pid().0;
+        let level_4 = PhysFrame::containing_address(process.cr3);
+        let mut from = if !wrapped && pid == hand_pid {
+            hand_address + PAGE_SIZE
+        } else {
+            0
+        };
+        while let Some((address, frame, flags)) = super::next_mapped_page(
+            level_4,
+            VirtAddr::new(from),
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+        ) {
+            if cow::references(frame) == 1 && !flags.contains(shm::SHARED) {
+                HAND = (pid, address.as_u64());
+                return Some((level_4, address, wrapped));
+            }
+            from = address.as_u64() + PAGE_SIZE;
+        }
+    }
+    None
+}
+
+/// Writes the present page at `address` of `level_4` to a free slot and gives back its frame.
+/// Returns false if the swap is full.
+unsafe fn swap_out(level_4: PhysFrame, address: VirtAddr, entry: &mut PageTableEntry) -> bool {
+    let slot = match SLOTS.iter().position(|references| *references == 0) {
+        Some(slot) => slot,
+        None => return false,
+    };
+    let frame = entry.addr();
+    write_slot(slot, frame);
+    let mut flags = entry.flags();
+    flags.remove(PageTableFlags::PRESENT | PageTableFlags::ACCESSED | PageTableFlags::DIRTY);
+    flags.insert(SWAPPED);
+    entry.set_addr(PhysAddr::new((slot as u64) << 12), flags);
+    if Cr3::read().0 == level_4 {
+        x86_64::instructions::tlb::flush(address);
+    }
+    SLOTS[slot] = 1;
+    STATS.used += 1;
+    STATS.swapped_out += 1;
+    if let Some(frame_allocator) = &mut FRAME_ALLOCATOR {
+        if cow::release(frame) {
+            frame_allocator.deallocate_4k_frame(frame);
+        }
+    }
+    true
+}
+
+/// Swaps pages out until `HIGH_FRAMES` frames are free, if there are less than `LOW_FRAMES`.
+/// Returns the number of pages swapped out.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn balance() -> usize {
+    let free = || match &FRAME_ALLOCATOR {
+        Some(frame_allocator) => frame_allocator.state(),
+        None => usize::MAX,
+    };
+    if free() >= LOW_FRAMES || STATS.used == STATS.slots {
+        return 0;
+    }
+    // Stops after two whole laps past the current one, the first lap may only clear the bits
+    let mut laps = 0;
+    let mut swapped = 0;
+    while swapped < BATCH && free() < HIGH_FRAMES {
+        let (level_4, address, wrapped) = match next_candidate() {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        if wrapped {
+            laps += 1;
+            if laps > 2 {
+                break;
+            }
+        }
+        let entry = match cow::table_entry(level_4, address) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut flags = entry.flags();
+        if flags.contains(PageTableFlags::ACCESSED) {
+            flags.remove(PageTableFlags::ACCESSED);
+            entry.set_flags(flags);
+            if Cr3::read().0 == level_4 {
+                x86_64::instructions::tlb::flush(address);
+            }
+            STATS.second_chances += 1;
+        } else if swap_out(level_4, address, entry) {
+            swapped += 1;
+        } else {
+            break;
+        }
+    }
+    swapped
+}
+
+/// Brings back the page at `address` of the current address space if it was swapped out.
+/// Returns false if it was not, or if no frame could be found for it.
+/// # Safety
+/// Needs to be called from the page fault handler, with the kernel lock held.
+pub unsafe fn handle_fault(address: VirtAddr) -> bool {
+    swap_in(Cr3::read().0, address)
+}
+
+/// Brings back the page at `address` of `level_4` if it was swapped out.
+/// Returns false if it was not, or if no frame could be found for it.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor.
+pub unsafe fn swap_in(level_4: PhysFrame, address: VirtAddr) -> bool {
+    let entry = match cow::table_entry(level_4, address) {
+        Some(entry) if entry.flags().contains(SWAPPED) => entry,
+        _ => return false,
+    };
+    let allocate = || match &mut FRAME_ALLOCATOR {
+        Some(frame_allocator) => frame_allocator.allocate_4k_frame(),
+        None => None,
+    };
+    balance();
+    let frame = match allocate() {
+        Some(frame) => frame,
+        None => return false,
+    };
+    let slot = entry.addr();
+    read_slot(slot_of(slot), frame);
+    let mut flags = entry.flags();
+    flags.remove(SWAPPED);
+    // Not to be picked again by the next turn of the clock
+    flags.insert(PageTableFlags::PRESENT | PageTableFlags::ACCESSED);
+    entry.set_addr(frame, flags);
+    if Cr3::read().0 == level_4 {
+        x86_64::instructions::tlb::flush(address);
+    }
+    release(slot);
+    STATS.swapped_in += 1;
+    true
+}
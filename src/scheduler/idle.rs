@@ -0,0 +1,36 @@
+//! Idle task, run by the scheduler when no process is runnable.
+//!
+//! It halts the CPU with interrupts enabled, so it sleeps until the next IRQ instead of spinning.
+//! Each processor has its own, which is never put in the queues of the scheduling policy.
+
+use super::{kthread, process};
+
+/// Size of the stack of the idle task. Interrupts are handled on it.
+const IDLE_STACK_SIZE: usize = kthread::KERNEL_STACK_SIZE;
+
+#[repr(C, align(32))]
+struct IdleStack([u8; IDLE_STACK_SIZE]);
+
+static mut IDLE_STACK: IdleStack = IdleStack([0; IDLE_STACK_SIZE]);
+
+/// Creates the idle task of the bootstrap processor. Needs to be called before the first process is launched.
+pub fn spawn() {
+    unsafe {
+        let top = IDLE_STACK.0.as_ptr() as u64 + IDLE_STACK_SIZE as u64;
+        process::spawn_idle(0, kthread::prepare_stack(top, crate::halt_loop));
+    }
+}
+
+/// Creates the idle task of the application processor `cpu`, its stack is taken from the heap.
+/// Returns false if the stack could not be allocated.
+/// # Safety
+/// Needs to be called once by the processor `cpu`, with the kernel lock held
+pub unsafe fn spawn_ap(cpu: usize) -> bool {
+    match kthread::allocate_stack() {
+        Some(top) => {
+            process::spawn_idle(cpu, kthread::prepare_stack(top, crate::halt_loop));
+            true
+        }
+        None => false,
+    }
+}
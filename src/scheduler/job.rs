@@ -0,0 +1,123 @@
+//! Job control: foreground process groups and stopping or resuming whole groups.
+//!
+//! The terminal of a process is the virtual screen behind its standard output.
+//! Each virtual screen may have a foreground process group, which is the only one allowed
+//! to read the keyboard. A background job that tries to read is stopped, like with `SIGTTIN`.
+
+use super::process::{self, Process, ID, ID_TABLE};
+use crate::filesystem::descriptor::FileDescriptor;
+use crate::vga::mainscreen::VirtualScreenID;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Where the screens are mounted
+const SCREEN_PATH: &str = "/hard/screen";
+
+/// Foreground process group of each virtual screen
+static mut FOREGROUND: BTreeMap<VirtualScreenID, ID> = BTreeMap::new();
+
+/// Returns the virtual screen behind the file descriptor `fd` of `process`, if any.
+fn get_screen(process: &Process, fd: usize) -> Option<VirtualScreenID> {
+    let oft = process
+        .open_files
+        .get_file_table(FileDescriptor::new(fd))
+        .ok()?;
+    if oft.get_path().to() == SCREEN_PATH {
+        Some(VirtualScreenID::forge(oft.get_id()))
+    } else {
+        None
+    }
+}
+
+/// Makes `pgid` the foreground group of the screen behind the file descriptor `fd` of the current process.
+/// The group must belong to the session of the current process.
+/// Returns : usize::MAX or 0 if it succeeds
+pub fn set_foreground(fd: usize, pgid: usize) -> usize {
+    let current = process::get_current();
+    let screen = match get_screen(current, fd) {
+        Some(screen) => screen,
+        None => return usize::MAX,
+    };
+    let pgid = ID(pgid as u64);
+    unsafe {
+        if !ID_TABLE
+            .values()
+            .any(|process| process.get_pgid() == pgid && process.get_sid() == current.get_sid())
+        {
+            return usize::MAX;
+        }
+        FOREGROUND.insert(screen, pgid);
+    }
+    0
+}
+
+/// Returns the foreground group of the screen behind the file descriptor `fd` of the current process,
+/// or usize::MAX if there is none.
+pub fn get_foreground(fd: usize) -> usize {
+    get_screen(process::get_current(), fd)
+        .and_then(|screen| unsafe { FOREGROUND.get(&screen) })
+        .map_or(usize::MAX, |pgid| pgid.as_usize())
+}
+
+/// Returns true iff `process` may read from the keyboard:
+/// its terminal has no foreground group, or it belongs to it.
+pub fn is_foreground(process: &Process) -> bool {
+    match get_screen(process, 1).and_then(|screen| unsafe { FOREGROUND.get(&screen) }) {
+        None => true,
+        Some(pgid) => *pgid == process.get_pgid(),
+    }
+}
+
+/// Returns every process of the group `pgid`
+fn members(pgid: ID) -> Vec<ID> {
+    unsafe {
+        ID_TABLE
+            .values()
+            .filter(|process| process.get_pgid() == pgid)
+            .map(|process| process.get_pid())
+            .collect()
+    }
+}
+
+/// Returns the processes designated by `target`: the group `target` if `group` is true,
+/// the process `target` otherwise. They have to be in the session of the current process.
+fn targets(target: usize, group: bool) -> Vec<ID> {
+    let sid = process::get_current().get_sid();
+    let pids = if group {
+        members(ID(target as u64))
+    } else {
+        vec![ID(target as u64)]
+    };
+    pids.into_iter()
+        .filter(|pid| {
+            unsafe { process::get_process(pid.as_usize()) }
+                .map_or(false, |process| process.get_sid() == sid)
+        })
+        .collect()
+}
+
+/// Stops the process (or the process group if `group` is true) `target`.
+/// Returns : the number of stopped processes
+pub fn stop(target: usize, group: bool) -> usize {
+    targets(target, group)
+        .into_iter()
+        .filter(|pid| process::stop(*pid))
+        .count()
+}
+
+/// Resumes the process (or the process group if `group` is true) `target`.
+/// Returns : the number of resumed processes
+pub fn resume(target: usize, group: bool) -> usize {
+    targets(target, group)
+        .into_iter()
+        .filter(|pid| process::resume(*pid))
+        .count()
+}
+
+/// Stops the whole group of `process`, used when a background job reads its terminal.
+pub fn stop_group(process: &Process) {
+    for pid in members(process.get_pgid()) {
+        process::stop(pid);
+    }
+}
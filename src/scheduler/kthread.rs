@@ -0,0 +1,149 @@
+//! Kernel threads, used for background kernel work.
+//!
+//! They run in ring 0 on their own kernel stack, with the page table of the kernel, so without
+//! any user mapping. The scheduler treats them like any other process, with the given priority.
+//! They give the CPU back with the same syscalls as the user programs.
+
+use super::process::{self, ProcessError, ID};
+use super::realtime;
+use super::supervisor;
+use crate::data_storage::registers::Registers;
+use crate::errorln;
+use alloc::alloc::{alloc_zeroed, Layout};
+
+/// Size of the stack of a kernel thread. Interrupts are handled on it.
+pub const KERNEL_STACK_SIZE: usize = 4096 * 4;
+
+/// `ymm0` is restored with an aligned move
+const STACK_ALIGNMENT: usize = 32;
+
+/// Interrupts enabled, and the always-one bit
+const KERNEL_RFLAGS: u64 = 0x202;
+
+/// Kernel code segment
+const KERNEL_CODE_SEGMENT: u64 = 0x08;
+
+/// Number of the `sleep` syscall
+const SLEEP_SYSCALL: u64 = 24;
+
+/// Priority of the sound thread, it has to run at each tick
+const SOUND_PRIORITY: usize = 0;
+
+/// Real-time reservation of the sound thread, in ticks.
+/// The sound clock is the one of the timer, so when the thread is throttled the notes change late but do not drift.
+const SOUND_PERIOD: u64 = 10;
+const SOUND_BUDGET: u64 = 1;
+
+/// What `leave_context_cr3` pops before running a new task
+#[repr(C)]
+pub struct InitialFrame {
+    registers: Registers,
+    ymm0: [u64; 4],
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl InitialFrame {
+    pub const fn new(registers: Registers, rip: u64, rflags: u64, rsp: u64) -> Self {
+        Self {
+            registers,
+            ymm0: [0; 4],
+            rip,
+            cs: KERNEL_CODE_SEGMENT,
+            rflags,
+            rsp,
+            ss: 0,
+        }
+    }
+
+    /// Returns where the frame goes on a stack ending at `top`
+    pub const fn address(top: u64) -> u64 {
+        let ymm0_address = (top - 5 * 8 - 32) & !(STACK_ALIGNMENT as u64 - 1);
+        ymm0_address - core::mem::size_of::<Registers>() as u64
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const Self as *const u8,
+                core::mem::size_of::<Self>(),
+            )
+        }
+    }
+}
+
+/// Prepares the stack ending at `top` so that `entry` is started by `leave_context_cr3`.
+/// Returns the address of the saved registers.
+/// # Safety
+/// `top` must be the end of a writable stack, which is not used by anything else.
+pub unsafe fn prepare_stack(top: u64, entry: fn() -> !) -> u64 {
+    let frame_address = InitialFrame::address(top);
+    *(frame_address as *mut InitialFrame) = InitialFrame::new(
+        Registers::new(),
+        entry as u64,
+        KERNEL_RFLAGS,
+        // As if `entry` had just been called
+        (frame_address & !15) - 8,
+    );
+    frame_address
+}
+
+/// Allocates a kernel stack on the heap and returns its top.
+/// The stack is never freed, kernel threads and idle tasks do not exit.
+pub fn allocate_stack() -> Option<u64> {
+    let layout = Layout::from_size_align(KERNEL_STACK_SIZE, STACK_ALIGNMENT).ok()?;
+    let stack = unsafe { alloc_zeroed(layout) };
+    if stack.is_null() {
+        return None;
+    }
+    Some(stack as u64 + KERNEL_STACK_SIZE as u64)
+}
+
+/// Creates a kernel thread running `entry`, and makes it runnable.
+/// A lower `priority` is more urgent.
+pub fn spawn(name: &[u8], priority: usize, entry: fn() -> !) -> Result<ID, ProcessError> {
+    let top = allocate_stack().ok_or(ProcessError::StackError)?;
+    let rsp = unsafe { prepare_stack(top, entry) };
+    process::spawn_kernel_thread(name, priority, rsp)
+}
+
+/// Gives the CPU back until `ticks` more timer interrupts have happened.
+pub fn sleep(ticks: u64) {
+    unsafe {
+        asm!(
+            "int 0x80",
+            inout("rax") SLEEP_SYSCALL => _,
+            inout("rdi") ticks => _,
+        );
+    }
+}
+
+/// Advances the sound queue at each tick
+fn sound_thread() -> ! {
+    loop {
+        crate::smp::lock::with_kernel_lock(crate::sound::handle);
+        sleep(1);
+    }
+}
+
+/// Launches the kernel threads doing the deferred work of the interrupt handlers.
+pub fn spawn_all() {
+    match spawn(b"ksound", SOUND_PRIORITY, sound_thread) {
+        Ok(pid) => {
+            if let Err(error) = realtime::set(pid, SOUND_PERIOD, SOUND_BUDGET) {
+                errorln!("The sound thread is not real-time : {:?}", error);
+            }
+        }
+        Err(error) => errorln!("Could not launch the sound thread : {:?}", error),
+    }
+    if let Err(error) = spawn(
+        b"ksupervisor",
+        supervisor::SUPERVISOR_PRIORITY,
+        supervisor::supervisor,
+    ) {
+        errorln!("Could not launch the supervisor : {:?}", error);
+    }
+}
@@ -1,13 +1,103 @@
 //! Contains all the logic used to create, manage, switch and kill processes.
 
-pub mod process;
-
-/// Maximum number of co-existing processes.
-///
-/// /!\ For now, it is the maximum number of processes that can be spawned
+use crate::smp;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
-pub const PROCESS_MAX_NUMBER: u64 = 32;
+pub mod idle;
+pub mod job;
+pub mod kthread;
+pub mod policy;
+pub mod process;
+pub mod realtime;
+pub mod supervisor;
+pub mod wait_queue;
 
 /// Number of consecutive time slices a process can use
 /// before another one is executed
 pub const QUANTUM: u64 = 20;
+
+/// Number of priority levels, 0 being the most urgent one
+pub const MAX_PRIO: usize = 8;
+
+/// Scheduling policy of each processor, each one having its own run queues
+static mut POLICIES: Vec<Box<dyn policy::Policy>> = Vec::new();
+
+/// Returns the scheduling policy of the current processor. It is the multilevel feedback queue by default.
+pub fn get_policy() -> &'static mut dyn policy::Policy {
+    get_cpu_policy(smp::cpu_id())
+}
+
+/// Returns the scheduling policy of the processor `cpu`
+pub fn get_cpu_policy(cpu: usize) -> &'static mut dyn policy::Policy {
+    unsafe {
+        while POLICIES.len() <= cpu {
+            POLICIES.push(Box::new(policy::mlfq::Mlfq::new()));
+        }
+        &mut *POLICIES[cpu]
+    }
+}
+
+/// Replaces the scheduling policy of every processor, the runnable processes are handed over to the new ones.
+/// Useful to compare policies.
+pub fn set_policy(new_policy: fn() -> Box<dyn policy::Policy>) {
+    for cpu in 0..smp::cpu_count() {
+        let mut policy = new_policy();
+        for pid in get_cpu_policy(cpu).drain() {
+            if let Some(process) = unsafe { process::get_process(pid.as_usize()) } {
+                policy.enqueue(process);
+            }
+        }
+        crate::debug!(
+            "Scheduling policy of processor {} is now {}",
+            cpu,
+            policy.name()
+        );
+        unsafe {
+            POLICIES[cpu] = policy;
+        }
+    }
+}
+
+/// Returns the processor with the fewest runnable processes, counting the one it runs
+fn least_loaded_cpu() -> usize {
+    (0..smp::cpu_count())
+        .min_by_key(|cpu| {
+            let running = !process::is_idle(process::current_pid_on(*cpu));
+            get_cpu_policy(*cpu).load() + running as usize
+        })
+        .unwrap_or(0)
+}
+
+/// Adds a process that became runnable to the run queues of the least loaded processor,
+/// which is woken up if it was idle. Real-time processes wake up an idle processor instead.
+/// Returns false if there is no room left for it.
+pub fn enqueue(process: &process::Process) -> bool {
+    if realtime::is_realtime(process.get_pid()) {
+        // Real-time processes are not in the run queues, an idle processor picks them up
+        if let Some(cpu) =
+            (0..smp::cpu_count()).find(|cpu| process::is_idle(process::current_pid_on(*cpu)))
+        {
+            smp::reschedule(cpu);
+        }
+        return true;
+    }
+    let cpu = least_loaded_cpu();
+    if !get_cpu_policy(cpu).enqueue(process) {
+        return false;
+    }
+    if process::is_idle(process::current_pid_on(cpu)) {
+        smp::reschedule(cpu);
+    }
+    true
+}
+
+/// Takes a runnable process from the most loaded other processor.
+/// Used when the current processor has nothing left to run.
+pub fn steal() -> Option<process::ID> {
+    let current = smp::cpu_id();
+    let busiest = (0..smp::cpu_count())
+        .filter(|cpu| *cpu != current)
+        .max_by_key(|cpu| get_cpu_policy(*cpu).load())?;
+    get_cpu_policy(busiest).pick_next()
+}
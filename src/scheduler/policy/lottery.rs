@@ -0,0 +1,107 @@
+//! Lottery scheduler: the priority level to run is drawn using a pseudo-random ticket
+
+use super::Policy;
+use crate::data_storage::{queue::Queue, random};
+use crate::scheduler::process::{Process, ID};
+use crate::scheduler::wait_queue::Resource;
+use crate::scheduler::{MAX_PRIO, QUANTUM};
+use alloc::vec::Vec;
+
+pub struct Lottery {
+    queues: [Queue<ID>; MAX_PRIO],
+}
+
+impl Lottery {
+    pub const fn new() -> Self {
+        Self {
+            queues: [
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+            ],
+        }
+    }
+
+    /// Draws the priority level that should run next
+    fn next_priority_to_run() -> usize {
+        let mut ticket = random::random_u8();
+        // Look for the most significant non null bit in the ticket
+        let mut idx = 7;
+        while idx > 0 && ticket != 0 {
+            ticket <<= 1;
+            idx -= 1;
+        }
+        idx
+    }
+}
+
+impl Default for Lottery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Policy for Lottery {
+    fn name(&self) -> &'static str {
+        "lottery"
+    }
+
+    /// It tries to push the process in its priority, but if it is full,
+    /// it will promote the process until it finds room.
+    fn enqueue(&mut self, process: &Process) -> bool {
+        let mut effective_prio = process.get_priority();
+        while self.queues[effective_prio].is_full() && effective_prio > 0 {
+            effective_prio -= 1
+        }
+        self.queues[effective_prio].push(process.get_pid()).is_ok()
+    }
+
+    fn pick_next(&mut self) -> Option<ID> {
+        let mut prio = Self::next_priority_to_run();
+        // Find the lowest priority at least as urgent as the one indicated by the ticket that is not empty
+        while prio < MAX_PRIO && self.queues[prio].is_empty() {
+            prio = prio.wrapping_sub(1);
+        }
+        if prio >= MAX_PRIO {
+            prio = 0;
+            while prio < MAX_PRIO && self.queues[prio].is_empty() {
+                prio += 1;
+            }
+            if prio == MAX_PRIO {
+                return None;
+            }
+        }
+        self.queues[prio].pop().ok()
+    }
+
+    fn tick(&mut self, current: &mut Process) -> bool {
+        current.quantum += 1;
+        if current.quantum >= QUANTUM {
+            current.quantum = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn woken_up(&mut self, _process: &mut Process, _resource: Resource) {}
+
+    fn drain(&mut self) -> Vec<ID> {
+        let mut res = Vec::new();
+        for queue in self.queues.iter_mut() {
+            while let Ok(pid) = queue.pop() {
+                res.push(pid);
+            }
+        }
+        res
+    }
+
+    fn load(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+}
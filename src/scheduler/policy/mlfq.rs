@@ -0,0 +1,140 @@
+//! Multilevel feedback queue scheduler.
+//!
+//! * A process starts at the level given by its priority, level 0 being the most urgent.
+//! * The first non-empty level is always run first, in a round-robin way.
+//! * A process that uses up the time slice of its level is demoted to the next level.
+//!   The `quantum` field of the process accounts for the ticks it used at its level,
+//!   even across sleeps so that yielding right before the end of the slice does not help.
+//! * A process woken up by keyboard input is interactive and goes back to its best level.
+//! * Every `BOOST_PERIOD` ticks, every process goes back to its best level to prevent starvation.
+
+use super::Policy;
+use crate::data_storage::queue::Queue;
+use crate::scheduler::process::{self, Process, ID, ID_TABLE};
+use crate::scheduler::wait_queue::Resource;
+use crate::scheduler::{MAX_PRIO, QUANTUM};
+use alloc::vec::Vec;
+
+/// Number of ticks between two boosts of every process
+pub const BOOST_PERIOD: u64 = 50 * QUANTUM;
+
+pub struct Mlfq {
+    queues: [Queue<ID>; MAX_PRIO],
+    /// Number of ticks since the last boost
+    since_boost: u64,
+}
+
+impl Mlfq {
+    pub const fn new() -> Self {
+        Self {
+            queues: [
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+                Queue::new(),
+            ],
+            since_boost: 0,
+        }
+    }
+
+    /// Number of ticks a process can run at the given level before being demoted.
+    /// Less urgent levels get longer slices.
+    pub fn time_slice(level: usize) -> u64 {
+        QUANTUM * (level as u64 + 1)
+    }
+
+    /// Moves every process back to the level given by its priority
+    fn boost(&mut self) {
+        let runnable = self.drain();
+        unsafe {
+            for process in ID_TABLE.values_mut() {
+                process.level = process.get_priority();
+                process.quantum = 0;
+            }
+            for pid in runnable {
+                if let Some(process) = process::get_process(pid.as_usize()) {
+                    self.enqueue(process);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Mlfq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Policy for Mlfq {
+    fn name(&self) -> &'static str {
+        "mlfq"
+    }
+
+    /// The process goes at the end of the queue of its level.
+    /// If it is full, the closest level with some room is used.
+    fn enqueue(&mut self, process: &Process) -> bool {
+        let level = process.level;
+        let pid = process.get_pid();
+        for distance in 0..MAX_PRIO {
+            if level >= distance && self.queues[level - distance].push(pid).is_ok() {
+                return true;
+            }
+            if level + distance < MAX_PRIO && self.queues[level + distance].push(pid).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn pick_next(&mut self) -> Option<ID> {
+        self.queues
+            .iter_mut()
+            .find(|queue| !queue.is_empty())
+            .and_then(|queue| queue.pop().ok())
+    }
+
+    fn tick(&mut self, current: &mut Process) -> bool {
+        current.quantum += 1;
+        let preempt = if current.quantum >= Self::time_slice(current.level) {
+            current.quantum = 0;
+            if current.level + 1 < MAX_PRIO {
+                current.level += 1;
+            }
+            true
+        } else {
+            false
+        };
+        self.since_boost += 1;
+        if self.since_boost >= BOOST_PERIOD {
+            self.since_boost = 0;
+            self.boost();
+        }
+        preempt
+    }
+
+    fn woken_up(&mut self, process: &mut Process, resource: Resource) {
+        if resource == Resource::Keyboard {
+            process.level = process.get_priority();
+            process.quantum = 0;
+        }
+    }
+
+    fn drain(&mut self) -> Vec<ID> {
+        let mut res = Vec::new();
+        for queue in self.queues.iter_mut() {
+            while let Ok(pid) = queue.pop() {
+                res.push(pid);
+            }
+        }
+        res
+    }
+
+    fn load(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+}
@@ -0,0 +1,37 @@
+//! Scheduling policies.
+//!
+//! A policy only decides which runnable process gets the CPU next and for how long.
+//! The book-keeping of the states of the processes is done in `process`.
+
+use super::process::{Process, ID};
+use super::wait_queue::Resource;
+use alloc::vec::Vec;
+
+pub mod lottery;
+pub mod mlfq;
+
+/// Interface every scheduling policy has to implement.
+pub trait Policy {
+    /// Name of the policy, used for debugging
+    fn name(&self) -> &'static str;
+
+    /// Adds a runnable process into the run queues.
+    /// Returns false if there is no room left for it.
+    fn enqueue(&mut self, process: &Process) -> bool;
+
+    /// Removes and returns the next process to run, if any
+    fn pick_next(&mut self) -> Option<ID>;
+
+    /// Accounts a timer tick to the running process.
+    /// Returns true iff it has to give the CPU to another process.
+    fn tick(&mut self, current: &mut Process) -> bool;
+
+    /// Called when `process` is woken up after sleeping on `resource`, before it is enqueued.
+    fn woken_up(&mut self, process: &mut Process, resource: Resource);
+
+    /// Removes every process from the run queues. Used when changing the policy.
+    fn drain(&mut self) -> Vec<ID>;
+
+    /// Number of processes in the run queues, used to balance the load between the processors
+    fn load(&self) -> usize;
+}
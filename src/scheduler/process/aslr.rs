@@ -0,0 +1,50 @@
+//! Address space layout randomisation of the user programs.
+//!
+//! The stack top, the heap, the page of the arguments and the base of the position independent executables
+//! are moved by a random number of pages, drawn from `random::random_u64`.
+//! It can be disabled, so that a program gets the same layout at every run when it is debugged.
+
+use crate::data_storage::random;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Pages the stack top can be moved down by (256 MiB)
+pub const STACK_PAGES: u64 = 1 << 16;
+
+/// Pages the heap can be moved up by, after the end of the program (256 MiB)
+pub const HEAP_PAGES: u64 = 1 << 16;
+
+/// Pages the page of the arguments can be moved up by, it stays under the program
+pub const ARGS_PAGES: u64 = 0xFF;
+
+/// Lowest base of the position independent executables. Its level 4 entry is not used by the kernel.
+pub const PIE_BASE: u64 = 0x0000_5550_0000_0000;
+
+/// Pages the base of the position independent executables can be moved up by (64 GiB)
+pub const PIE_PAGES: u64 = 1 << 24;
+
+static mut ENABLED: bool = true;
+
+pub fn is_enabled() -> bool {
+    unsafe { ENABLED }
+}
+
+/// Enables or disables the randomisation, for the programs loaded afterwards
+pub fn set_enabled(enabled: bool) {
+    unsafe {
+        ENABLED = enabled;
+    }
+}
+
+/// Returns a random number of pages under `pages`, in bytes. Returns 0 if the randomisation is disabled.
+pub fn offset(pages: u64) -> u64 {
+    if !is_enabled() || pages == 0 {
+        return 0;
+    }
+    (random::random_u64() % pages) * PAGE_SIZE
+}
+
+/// Returns the top of the stack of a new address space, `top` moved down by a random offset
+pub fn stack_top(top: u64) -> u64 {
+    top - offset(STACK_PAGES)
+}
@@ -0,0 +1,273 @@
+//! ELF core files of the processes killed by an exception.
+//!
+//! A core contains a `NT_PRSTATUS` note with the registers of the process, and a `PT_LOAD` segment
+//! for each run of contiguous user pages. It can be loaded in gdb along with the executable.
+
+use super::{is_idle, Process};
+use crate::data_storage::path::Path;
+use crate::data_storage::registers::Registers;
+use crate::filesystem::{self, descriptor::OpenFileTable, fsflags::OpenFlags};
+use crate::interrupts::idt::InterruptStackFrameValue;
+use crate::memory;
+use crate::{errorln, warningln};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+
+/// Directory used until another one is configured
+const DEFAULT_CORE_DIRECTORY: &str = "/usr/cores";
+
+/// Only the UsTar partition can hold core files
+const CORE_PARTITION: &str = "/usr/";
+
+const PAGE_SIZE: u64 = 0x1000;
+
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const ET_CORE: u16 = 4;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+/// Size of `struct elf_prstatus` on x86_64
+const PRSTATUS_SIZE: usize = 336;
+/// Offset of the registers in `struct elf_prstatus`
+const PRSTATUS_REGISTERS: usize = 112;
+
+/// Where the core files are written. `None` means `DEFAULT_CORE_DIRECTORY`, an empty path disables them.
+static mut CORE_DIRECTORY: Option<String> = None;
+
+/// Changes the directory in which the core files are written. An empty path disables them.
+/// Returns true iff the directory is on the UsTar partition
+pub fn set_directory(path: String) -> bool {
+    if !path.is_empty() && !path.starts_with(CORE_PARTITION) {
+        return false;
+    }
+    unsafe {
+        CORE_DIRECTORY = Some(path);
+    }
+    true
+}
+
+fn get_directory() -> String {
+    unsafe { CORE_DIRECTORY.clone() }.unwrap_or_else(|| String::from(DEFAULT_CORE_DIRECTORY))
+}
+
+/// A run of contiguous user pages with the same flags
+struct Segment {
+    start: u64,
+    flags: u32,
+    frames: Vec<u64>,
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Groups the user pages of `process` into segments
+unsafe fn segments(process: &Process) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let pages = memory::mapped_pages(
+        PhysFrame::containing_address(process.cr3),
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
+    );
+    for (page, frame, flags) in pages {
+        let mut elf_flags = PF_R;
+        if flags.contains(PageTableFlags::WRITABLE) {
+            elf_flags |= PF_W;
+        }
+        if !flags.contains(PageTableFlags::NO_EXECUTE) {
+            elf_flags |= PF_X;
+        }
+        match segments.last_mut() {
+            Some(last)
+                if last.flags == elf_flags
+                    && last.start + last.frames.len() as u64 * PAGE_SIZE == page.as_u64() =>
+            {
+                last.frames.push(frame.as_u64())
+            }
+            _ => segments.push(Segment {
+                start: page.as_u64(),
+                flags: elf_flags,
+                frames: vec![frame.as_u64()],
+            }),
+        }
+    }
+    segments
+}
+
+/// Builds the `NT_PRSTATUS` note. The general purpose registers that were not saved are left to 0.
+fn prstatus(
+    process: &Process,
+    code: u64,
+    stack_frame: &InterruptStackFrameValue,
+    registers: Option<&Registers>,
+) -> Vec<u8> {
+    let mut status = Vec::new();
+    push_u32(&mut status, code as u32); // si_signo
+    push_u32(&mut status, 0); // si_code
+    push_u32(&mut status, 0); // si_errno
+    push_u16(&mut status, code as u16); // pr_cursig
+    push_u16(&mut status, 0);
+    push_u64(&mut status, 0); // pr_sigpend
+    push_u64(&mut status, 0); // pr_sighold
+    push_u32(&mut status, process.pid.0 as u32);
+    push_u32(&mut status, process.ppid.0 as u32);
+    push_u32(&mut status, process.pgid.0 as u32);
+    push_u32(&mut status, process.sid.0 as u32);
+    // The times are not kept as timevals
+    status.resize(PRSTATUS_REGISTERS, 0);
+    let registers = registers.copied().unwrap_or_else(Registers::new);
+    // Same order as `struct user_regs_struct`
+    for value in [
+        registers.r15,
+        registers.r14,
+        registers.r13,
+        registers.r12,
+        registers.rbp,
+        registers.rbx,
+        registers.r11,
+        registers.r10,
+        registers.r9,
+        registers.r8,
+        registers.rax,
+        registers.rcx,
+        registers.rdx,
+        registers.rsi,
+        registers.rdi,
+        u64::MAX, // orig_rax
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.code_segment,
+        stack_frame.cpu_flags,
+        stack_frame.stack_pointer.as_u64(),
+        stack_frame.stack_segment,
+        0, // fs_base
+        0, // gs_base
+        0, // ds
+        0, // es
+        0, // fs
+        0, // gs
+    ]
+    .iter()
+    {
+        push_u64(&mut status, *value);
+    }
+    // pr_fpvalid
+    status.resize(PRSTATUS_SIZE, 0);
+    status
+}
+
+/// Builds the whole core file of `process`
+unsafe fn build(
+    process: &Process,
+    code: u64,
+    stack_frame: &InterruptStackFrameValue,
+    registers: Option<&Registers>,
+) -> Vec<u8> {
+    let segments = segments(process);
+    let phnum = segments.len() as u64 + 1;
+
+    let mut note = Vec::new();
+    push_u32(&mut note, 5); // "CORE" and its null byte
+    push_u32(&mut note, PRSTATUS_SIZE as u32);
+    push_u32(&mut note, NT_PRSTATUS);
+    note.extend_from_slice(NOTE_NAME);
+    note.extend_from_slice(&prstatus(process, code, stack_frame, registers));
+
+    let note_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let data_offset = (note_offset + note.len() as u64 + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+    let mut file = Vec::new();
+    file.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    file.resize(16, 0);
+    push_u16(&mut file, ET_CORE);
+    push_u16(&mut file, EM_X86_64);
+    push_u32(&mut file, 1); // e_version
+    push_u64(&mut file, 0); // e_entry
+    push_u64(&mut file, ELF_HEADER_SIZE); // e_phoff
+    push_u64(&mut file, 0); // e_shoff
+    push_u32(&mut file, 0); // e_flags
+    push_u16(&mut file, ELF_HEADER_SIZE as u16);
+    push_u16(&mut file, PROGRAM_HEADER_SIZE as u16);
+    push_u16(&mut file, phnum as u16);
+    push_u16(&mut file, 0); // e_shentsize
+    push_u16(&mut file, 0); // e_shnum
+    push_u16(&mut file, 0); // e_shstrndx
+
+    push_u32(&mut file, PT_NOTE);
+    push_u32(&mut file, 0);
+    push_u64(&mut file, note_offset);
+    push_u64(&mut file, 0);
+    push_u64(&mut file, 0);
+    push_u64(&mut file, note.len() as u64);
+    push_u64(&mut file, 0);
+    push_u64(&mut file, 4);
+
+    let mut offset = data_offset;
+    for segment in segments.iter() {
+        let size = segment.frames.len() as u64 * PAGE_SIZE;
+        push_u32(&mut file, PT_LOAD);
+        push_u32(&mut file, segment.flags);
+        push_u64(&mut file, offset);
+        push_u64(&mut file, segment.start);
+        push_u64(&mut file, 0);
+        push_u64(&mut file, size);
+        push_u64(&mut file, size);
+        push_u64(&mut file, PAGE_SIZE);
+        offset += size;
+    }
+
+    file.extend_from_slice(&note);
+    file.resize(data_offset as usize, 0);
+    for segment in segments.iter() {
+        for frame in segment.frames.iter() {
+            let page = (frame + memory::PHYSICAL_OFFSET) as *const u8;
+            file.extend_from_slice(core::slice::from_raw_parts(page, PAGE_SIZE as usize));
+        }
+    }
+    file
+}
+
+/// Writes the core file of the current process, killed by an exception with the return value `code`.
+/// `registers` are the general purpose registers, if the handler saved them.
+/// Nothing is written for the kernel threads or if the core files are disabled.
+pub fn dump(code: u64, stack_frame: &InterruptStackFrameValue, registers: Option<&Registers>) {
+    let process = super::get_current();
+    let directory = get_directory();
+    if process.is_kernel_thread() || is_idle(process.pid) || directory.is_empty() {
+        return;
+    }
+    let file = unsafe { build(process, code, stack_frame, registers) };
+    let size = file.len();
+    let path = Path::from(&format!("{}/core.{}", directory, process.pid.0));
+    let flags = OpenFlags::OWR | OpenFlags::OCREAT;
+    let id = match filesystem::open_file(&path, flags) {
+        Some(id) => id,
+        None => {
+            errorln!("Could not create the core file {:?}", path);
+            return;
+        }
+    };
+    let mut oft = OpenFileTable::new(path, flags, id);
+    if filesystem::write_file(&mut oft, file) == size {
+        warningln!("Core dumped into {:?}", oft.get_path());
+    } else {
+        errorln!("Could not write the core file {:?}", oft.get_path());
+    }
+    filesystem::close_file(&oft);
+}
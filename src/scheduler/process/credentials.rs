@@ -0,0 +1,90 @@
+//! User and group credentials of a process.
+//!
+//! The real ids tell who launched the process, the effective ids are the ones used for permission checks.
+
+/// The super-user, it bypasses every permission check
+pub const ROOT: u64 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u64,
+    pub euid: u64,
+    pub gid: u64,
+    pub egid: u64,
+}
+
+impl Credentials {
+    pub const fn new(uid: u64, gid: u64) -> Self {
+        Self {
+            uid,
+            euid: uid,
+            gid,
+            egid: gid,
+        }
+    }
+
+    /// Credentials of the kernel and of the first process
+    pub const fn root() -> Self {
+        Self::new(ROOT, ROOT)
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.euid == ROOT
+    }
+
+    /// Sets every user id if the process is root, or only the effective one otherwise.
+    /// An unprivileged process can only go back to its real user id.
+    /// Returns true iff it succeeds
+    pub fn setuid(&mut self, uid: u64) -> bool {
+        if self.is_root() {
+            self.uid = uid;
+            self.euid = uid;
+            true
+        } else if uid == self.uid {
+            self.euid = uid;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sets the effective user id, to the real one if the process is not root.
+    /// Returns true iff it succeeds
+    pub fn seteuid(&mut self, euid: u64) -> bool {
+        if self.is_root() || euid == self.uid {
+            self.euid = euid;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Same as `setuid`, for the group ids
+    pub fn setgid(&mut self, gid: u64) -> bool {
+        if self.is_root() {
+            self.gid = gid;
+            self.egid = gid;
+            true
+        } else if gid == self.gid {
+            self.egid = gid;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Same as `seteuid`, for the group ids
+    pub fn setegid(&mut self, egid: u64) -> bool {
+        if self.is_root() || egid == self.gid {
+            self.egid = egid;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns true iff a process with these credentials may kill a process with the `target` ones
+    pub fn can_signal(&self, target: &Credentials) -> bool {
+        self.is_root() || self.uid == target.uid || self.euid == target.uid
+    }
+}
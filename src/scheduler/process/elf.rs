@@ -1,38 +1,101 @@
 use super::ProcessError;
 use crate::data_storage::path::Path;
-use crate::filesystem::read_file_from_path;
+use crate::filesystem::read_program_from_path;
 use crate::memory;
 use crate::{debug, warningln};
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
 
-use x86_64::structures::paging::PageTableFlags;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
 
-use xmas_elf::sections::ShType;
+use xmas_elf::program::{self, ProgramHeader};
+use xmas_elf::ElfFile;
 
 pub const MODIFY_WITH_EXEC: PageTableFlags = PageTableFlags::BIT_9;
 pub const STACK: PageTableFlags = PageTableFlags::BIT_10;
 pub const HEAP: PageTableFlags = PageTableFlags::BIT_11;
 pub const HEAP_ADDED: PageTableFlags = PageTableFlags::BIT_52;
 
-// TODO : change this to respect the conventions
-// For now, it is very probably wrong
-// for certain writable segment types
-pub fn get_table_flags(section: ShType) -> PageTableFlags {
-    match section {
-        ShType::ProgBits => PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
-        ShType::SymTab => PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
-        ShType::StrTab => PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
-        ShType::NoBits => {
-            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT | PageTableFlags::ACCESSED
+/// Returns the flags of the pages of a segment, from its `p_flags`.
+/// Code is readable and executable, data is writable and no-execute: a page is never both.
+pub fn segment_flags(flags: program::Flags) -> PageTableFlags {
+    let mut res = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | MODIFY_WITH_EXEC;
+    if flags.is_write() {
+        res |= PageTableFlags::WRITABLE;
+    }
+    if !flags.is_execute() || flags.is_write() {
+        res |= PageTableFlags::NO_EXECUTE;
+    }
+    res
+}
+
+/// Returns the first user page of `level_4` that is writable without `NO_EXECUTE`, None if there is none
+/// # Safety
+/// `level_4` must be a valid level 4 table.
+pub unsafe fn writable_and_executable(level_4: PhysFrame) -> Option<VirtAddr> {
+    memory::mapped_pages(
+        level_4,
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
+    )
+    .into_iter()
+    .find(|(_, _, flags)| {
+        flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PageTableFlags::NO_EXECUTE)
+    })
+    .map(|(page, _, _)| page)
+}
+
+/// Returns true iff `program` is a segment to load, `base` being the base of the executable.
+/// Segments at 0 are not loaded, except the ones of a position independent executable.
+pub fn is_loaded(program: &ProgramHeader, base: u64) -> bool {
+    matches!(program.get_type(), Ok(program::Type::Load))
+        && program.mem_size() != 0
+        && (program.virtual_addr() != 0 || base != 0)
+}
+
+/// Returns the flags of every page of the segments of `elf` loaded at `base`.
+/// A page shared by two segments gets the permissions of both. Fails if a segment, or a page,
+/// would be both writable and executable.
+pub fn segment_pages(
+    elf: &ElfFile,
+    base: u64,
+) -> Result<BTreeMap<u64, PageTableFlags>, ProcessError> {
+    let mut pages: BTreeMap<u64, PageTableFlags> = BTreeMap::new();
+    for program in elf
+        .program_iter()
+        .filter(|program| is_loaded(program, base))
+    {
+        if program.flags().is_write() && program.flags().is_execute() {
+            warningln!("Refused a segment both writable and executable");
+            return Err(ProcessError::InvalidExec);
         }
-        _ => {
-            PageTableFlags::USER_ACCESSIBLE
-                | PageTableFlags::PRESENT
-                | PageTableFlags::NO_EXECUTE
-                | PageTableFlags::WRITABLE
+        let flags = segment_flags(program.flags());
+        let start = (base + program.virtual_addr()) & !0xFFF;
+        let end = base + program.virtual_addr() + program.mem_size();
+        for page in (start..end).step_by(0x1000) {
+            let page_flags = match pages.get(&page) {
+                None => flags,
+                Some(other) => {
+                    let mut both = *other | flags;
+                    if !other.contains(PageTableFlags::NO_EXECUTE)
+                        || !flags.contains(PageTableFlags::NO_EXECUTE)
+                    {
+                        both.remove(PageTableFlags::NO_EXECUTE);
+                    }
+                    if both.contains(PageTableFlags::WRITABLE)
+                        && !both.contains(PageTableFlags::NO_EXECUTE)
+                    {
+                        warningln!("The page {:#x} would be both writable and executable", page);
+                        return Err(ProcessError::InvalidExec);
+                    }
+                    both
+                }
+            };
+            pages.insert(page, page_flags);
         }
     }
+    Ok(pages)
 }
 
 #[allow(dead_code)]
@@ -49,7 +112,7 @@ pub unsafe fn load_elf_for_exec(file_name: &str, args: &[String]) -> Result<!, P
         Some(fa) => fa,
         None => panic!("the frame allocator wasn't initialized"),
     };
-    let read = &read_file_from_path(Path::from(file_name));
+    let read = &read_program_from_path(Path::from(file_name));
     let code: &[u8] = match read {
         Ok(x) => x,
         Err(_) => return Err(ProcessError::ReadError),
@@ -70,7 +133,10 @@ pub unsafe fn load_elf_for_exec(file_name: &str, args: &[String]) -> Result<!, P
     warningln!("Code len 1 => {}", code.len());
 
     if let Ok(_level_4_table_addr) = frame_allocator.allocate_level_4_frame() {
-        let current = super::get_current();
+        let current = super::get_current_as_mut();
+
+        // the mapped files are written back before their pages go
+        super::release_files(current);
 
         // deallocate precedent file
         if !frame_allocator.deallocate_level_4_page(current.cr3, MODIFY_WITH_EXEC, true) {
@@ -86,7 +152,14 @@ pub unsafe fn load_elf_for_exec(file_name: &str, args: &[String]) -> Result<!, P
             debug!("heap page table is empty")
         }
 
-        super::disassemble_and_launch(code, frame_allocator, 0, 0, &args2, false)
+        super::disassemble_and_launch(
+            code,
+            frame_allocator,
+            0,
+            super::SPAWN_STACK_SIZE,
+            &args2,
+            false,
+        )
     } else {
         Err(ProcessError::AllocatorError)
     }
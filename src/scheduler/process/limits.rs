@@ -0,0 +1,93 @@
+//! Per-process resource limits, in the spirit of `getrlimit`/`setrlimit`.
+//!
+//! Each limit has a soft value, which is the one enforced, and a hard value, the ceiling of the soft one.
+//! Any process may lower its limits, only root may raise a hard limit. They are inherited through `fork`.
+
+/// No limit at all
+pub const UNLIMITED: u64 = u64::MAX;
+
+/// Number of different limits
+const RLIMIT_NUMBER: usize = 5;
+
+/// Resources a process can be limited on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rlimit {
+    /// Pages of heap given by `memrequest`
+    HeapPages = 0,
+    /// Open file descriptors
+    OpenFiles = 1,
+    /// Children that have not been reaped yet
+    Children = 2,
+    /// Ticks spent running, in user and kernel mode
+    CpuTicks = 3,
+    /// Pages the stack can grow to, counted when a program is loaded
+    StackPages = 4,
+}
+
+impl Rlimit {
+    pub fn from_u64(resource: u64) -> Option<Self> {
+        match resource {
+            0 => Some(Self::HeapPages),
+            1 => Some(Self::OpenFiles),
+            2 => Some(Self::Children),
+            3 => Some(Self::CpuTicks),
+            4 => Some(Self::StackPages),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl Limit {
+    pub const fn new(soft: u64, hard: u64) -> Self {
+        Self { soft, hard }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits([Limit; RLIMIT_NUMBER]);
+
+impl Limits {
+    /// Limits of the first process
+    pub const fn new() -> Self {
+        Self([
+            Limit::new(1024, 1 << 16),
+            Limit::new(64, 1024),
+            Limit::new(64, 1024),
+            Limit::new(UNLIMITED, UNLIMITED),
+            Limit::new(256, 1 << 14),
+        ])
+    }
+
+    pub fn get(&self, resource: Rlimit) -> Limit {
+        self.0[resource as usize]
+    }
+
+    /// Returns the limit that is enforced for `resource`
+    pub fn soft(&self, resource: Rlimit) -> u64 {
+        self.0[resource as usize].soft
+    }
+
+    /// Changes the limits of `resource`. Only a `privileged` process can raise the hard limit.
+    /// Returns true iff it succeeds
+    pub fn set(&mut self, resource: Rlimit, limit: Limit, privileged: bool) -> bool {
+        let current = &mut self.0[resource as usize];
+        if limit.soft > limit.hard || (limit.hard > current.hard && !privileged) {
+            false
+        } else {
+            *current = limit;
+            true
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
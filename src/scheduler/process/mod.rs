@@ -1,6 +1,7 @@
 //! All the logic around `Process`
 
-use super::PROCESS_MAX_NUMBER;
+use super::realtime;
+use super::MAX_PRIO;
 
 use bit_field::BitField;
 use core::{
@@ -13,15 +14,25 @@ use x86_64::structures::paging::PageTableFlags;
 use x86_64::structures::paging::PhysFrame;
 use x86_64::{PhysAddr, VirtAddr};
 
-use xmas_elf::{program::SegmentData, program::Type, ElfFile};
+use xmas_elf::{header, program::SegmentData, sections::SectionData, ElfFile};
 
+use credentials::Credentials;
+use limits::Rlimit;
+use regions::{RegionKind, Regions};
+
+use super::kthread::InitialFrame;
+use crate::data_storage::registers::Registers;
+
+use crate::alloc::boxed::Box;
 use crate::alloc::collections::{BTreeMap, BTreeSet};
 use crate::alloc::vec::Vec;
-use crate::data_storage::{path::Path, queue::Queue, random};
+use crate::data_storage::path::Path;
 use crate::filesystem;
 use crate::filesystem::descriptor::{FileDesciptorError, FileDescriptor, ProcessDescriptorTable};
 use crate::filesystem::fsflags::OpenFlags;
 use crate::memory;
+use crate::scheduler::wait_queue::{self, Resource};
+use crate::smp::{self, MAX_CPUS};
 use crate::{debug, errorln, println};
 use alloc::string::String;
 
@@ -30,12 +41,44 @@ use core::cmp::min;
 /// Default allocated heap size (in number of pages)
 const DEFAULT_HEAP_SIZE: u64 = 2;
 
+/// Top of the stack of a new program
+const USER_STACK_TOP: u64 = 0x00007ffffffffff8;
+
+/// Number of pages of stack of a program launched by `spawn_program` that are backed right away
+const SPAWN_STACK_SIZE: u64 = 10;
+
+/// Largest stack that can be reserved, whatever `Rlimit::StackPages` says (1 GiB)
+const MAX_STACK_PAGES: u64 = 1 << 18;
+
+/// Where a program goes when its `main` returns, see `page_fault_handler`
+const RETURN_ADDRESS: u64 = 0x42;
+
+/// Lowest address the kernel picks for the shared-memory segments, over the position independent executables
+const SHARED_BASE: u64 = 0x0000_6000_0000_0000;
+
+/// Lowest address of the page holding the arguments of a program, see `aslr`
+pub const ARGS_ADDRESS: u64 = 0x1000;
+
+/// Type of the relocations adding the base of a position independent executable
+const R_X86_64_RELATIVE: u32 = 8;
+
+/// Interrupts enabled, as set by `towards_user_give_heap_args`
+const USER_RFLAGS: u64 = 518;
+
 pub const IO_ERROR: u64 = 2;
 pub const BAD_FILE_MANIPULATION: u64 = 3;
+/// Return value of a process killed because it used all the CPU time it was allowed
+pub const CPU_LIMIT_EXCEEDED: u64 = 4;
 
 pub const SIZE_NAME: usize = 20;
 
+pub mod aslr;
+pub mod coredump;
+pub mod credentials;
 pub mod elf;
+pub mod limits;
+pub mod regions;
+pub mod stats;
 
 #[derive(Debug)]
 pub enum ProcessError {
@@ -46,6 +89,8 @@ pub enum ProcessError {
     HeapError,
     InvalidExec,
     ReadError,
+    TooManyProcesses,
+    LimitReached,
 }
 
 #[naked]
@@ -54,10 +99,12 @@ pub enum ProcessError {
 /// Highgly unsafe function!
 ///
 /// Given `Cr3` and `rsp` values, it leaves the context.
+/// The kernel lock is released once on the new stack, so that no other processor resumes the old context too early.
 pub unsafe extern "C" fn leave_context_cr3(_cr3: u64, _rsp: u64) -> ! {
     asm!(
         "mov cr3, rdi",
         "mov rsp, rsi",
+        "call {}",
         "pop r9",
         "pop r8",
         "pop r10",
@@ -76,10 +123,35 @@ pub unsafe extern "C" fn leave_context_cr3(_cr3: u64, _rsp: u64) -> ! {
         "vmovaps ymm0, [rsp]",
         "add rsp, 32",
         "iretq",
+        sym leave_kernel,
         options(noreturn,),
     )
 }
 
+/// Called by `towards_user_give_heap_args` on the stack of the program. Frees the stack pages outside of
+/// its stack region, left by the program it replaced with an exec, then leaves the kernel.
+/// The current process stays on this processor, so the lock can be released before going to the program.
+extern "C" fn release_old_stack() {
+    unsafe {
+        let current = get_current();
+        let level_4 = Cr3::read().0;
+        if let Some(stack) = current.regions.get(RegionKind::Stack) {
+            for (page, _, flags) in memory::mapped_pages(level_4, PageTableFlags::USER_ACCESSIBLE) {
+                if flags.contains(elf::STACK) && !stack.contains(page.as_u64()) {
+                    memory::unmap_pages(level_4, page, 1);
+                }
+            }
+        }
+    }
+    leave_kernel();
+}
+
+/// Called by `leave_context_cr3` on the new stack, the kernel frames of the old context are abandoned
+extern "C" fn leave_kernel() {
+    crate::gdt::reset_page_fault_stack();
+    smp::lock::force_release();
+}
+
 /// # Safety
 /// TODO
 ///
@@ -102,6 +174,22 @@ pub unsafe extern "C" fn towards_user_give_heap_args(
         "mov fs, eax",
         "mov gs, eax",
         "mov rsp, r8",
+        // The old stack is left, it can be freed
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push rcx",
+        "push r8",
+        "push r9",
+        "sub rsp, 8",
+        "call {}",
+        "add rsp, 8",
+        "pop r9",
+        "pop r8",
+        "pop rcx",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
         "add rsp, 8",
         "push 0x42",
         "push rax",  // stack segment
@@ -129,56 +217,13 @@ pub unsafe extern "C" fn towards_user_give_heap_args(
         in("rcx") args_number,
         in("r8") rsp,
         in("r9") rip,
+        sym release_old_stack,
         //options(noreturn,),
     );
     loop {}
 }
 
-/// # Safety
-/// TODO
-///
-/// allocates a given number of additional pages to the process' heap.
-pub unsafe fn allocate_additional_heap_pages(
-    frame_allocator: &mut memory::BootInfoAllocator,
-    start: u64,
-    number: u64,
-    process: &Process,
-) -> u64 {
-    let mut maxi = 0;
-    for i in 0..number {
-        match frame_allocator.add_entry_to_table(
-            PhysFrame::containing_address(process.cr3),
-            VirtAddr::new(start + i * 0x1000),
-            PageTableFlags::USER_ACCESSIBLE
-                | PageTableFlags::PRESENT
-                | PageTableFlags::WRITABLE
-                | elf::HEAP,
-            false,
-        ) {
-            Ok(()) => {
-                maxi = i;
-                match memory::write_into_virtual_memory(
-                    PhysFrame::containing_address(process.cr3),
-                    VirtAddr::new(start + i * 0x1000),
-                    &[0_u8; 0x1000],
-                ) {
-                    Ok(()) => (),
-                    Err(a) => errorln!("{:?} at additional heap-section : {:?}", a, i),
-                };
-            }
-            Err(memory::MemoryError(err)) => {
-                errorln!(
-                    "Could not allocate the {}-th additional part of the heap. Error : {:?}",
-                    i,
-                    err
-                );
-            }
-        }
-    }
-    maxi
-}
-
-/// Converts flags
+/// Converts the bits of a page table entry into flags, writable pages being no-execute
 pub fn page_table_flags_from_u64(flags: u64) -> PageTableFlags {
     let mut res = elf::MODIFY_WITH_EXEC | PageTableFlags::PRESENT;
     if flags.get_bit(0) {
@@ -187,7 +232,9 @@ pub fn page_table_flags_from_u64(flags: u64) -> PageTableFlags {
     if flags.get_bit(1) {
         res |= PageTableFlags::WRITABLE;
     }
-    res |= PageTableFlags::USER_ACCESSIBLE;
+    if flags.get_bit(2) {
+        res |= PageTableFlags::USER_ACCESSIBLE;
+    }
     if flags.get_bit(3) {
         res |= PageTableFlags::WRITE_THROUGH;
     }
@@ -206,7 +253,8 @@ pub fn page_table_flags_from_u64(flags: u64) -> PageTableFlags {
     if flags.get_bit(8) {
         res |= PageTableFlags::GLOBAL;
     }
-    if flags.get_bit(63) {
+    // A writable page is never executable
+    if flags.get_bit(63) || res.contains(PageTableFlags::WRITABLE) {
         res |= PageTableFlags::NO_EXECUTE;
     }
     res
@@ -258,12 +306,8 @@ pub unsafe fn disassemble_and_launch(
     args: &[String],
     new_process: bool,
 ) -> Result<!, ProcessError> {
-    // TODO maybe consider changing this
-    let addr_stack: u64 = if new_process {
-        0x00007ffffffffff8
-    } else {
-        get_current().stack_base
-    };
+    // An exec moves the stack too, the old one is freed by `release_old_stack` once left
+    let addr_stack: u64 = aslr::stack_top(USER_STACK_TOP);
     println!(
         "0x219000 was allocated ? {}",
         memory::check_if_has_flags(
@@ -272,15 +316,8 @@ pub unsafe fn disassemble_and_launch(
             PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE,
         )
     );
-    // We get the `ElfFile` from the raw slice
     println!("Code len : {}", code.len());
-    let elf = ElfFile::new(code).map_err(|_| ProcessError::InvalidELFHeader)?;
-    // We get the main entry point and mmake sure it is
-    // a 64-bit ELF file
-    let prog_entry = match elf.header.pt2 {
-        xmas_elf::header::HeaderPt2::Header64(a) => a.entry_point,
-        _ => panic!("Expected a 64-bit ELF!"),
-    };
+    let (elf, prog_entry) = parse_elf(code)?;
     // This allocates a new level-4 table
     let level_4_table_addr = if new_process {
         match frame_allocator.allocate_level_4_frame() {
@@ -293,97 +330,126 @@ pub unsafe fn disassemble_and_launch(
     };
 
     // TODO Change this
-    ID_TABLE[0].state = State::Runnable;
+    if let Some(first) = ID_TABLE.get_mut(&ID(0)) {
+        first.state = State::Runnable;
+    }
+    let program = load_program(
+        &elf,
+        frame_allocator,
+        level_4_table_addr,
+        addr_stack,
+        stack_size,
+        args,
+    )?;
+    get_current_as_mut().set_name(&program.args_data);
+    get_current_as_mut().args_address = program.args_address;
+    get_current_as_mut().heap_address = program.heap_address;
+    get_current_as_mut().heap_size = program.heap_size;
+    get_current_as_mut().stack_base = addr_stack;
+    reserve_regions(get_current_as_mut(), addr_stack);
+
+    let (_cr3, cr3f) = Cr3::read();
+    Cr3::write(level_4_table_addr, cr3f);
+    //println!("good luck user ;) {:x} {:x}", addr_stack, prog_entry);
+    let prog_entry = program.base + prog_entry;
+    println!("target : {:x}", prog_entry);
+    Ok(towards_user_give_heap_args(
+        program.heap_address,
+        program.heap_size,
+        program.args_address,
+        program.args_number,
+        addr_stack,
+        prog_entry,
+    ))
+}
+
+/// Returns the ELF file in `code`, with its entry point
+fn parse_elf(code: &[u8]) -> Result<(ElfFile, u64), ProcessError> {
+    // We get the `ElfFile` from the raw slice
+    let elf = ElfFile::new(code).map_err(|_| ProcessError::InvalidELFHeader)?;
+    // We get the main entry point and mmake sure it is
+    // a 64-bit ELF file
+    match elf.header.pt2 {
+        xmas_elf::header::HeaderPt2::Header64(a) => {
+            let entry = a.entry_point;
+            Ok((elf, entry))
+        }
+        _ => Err(ProcessError::InvalidELFHeader),
+    }
+}
+
+/// Where `load_program` put the parts of a program
+struct LoadedProgram {
+    /// Added to the addresses of the ELF file, 0 if it is not position independent
+    base: u64,
+    heap_address: u64,
+    heap_size: u64,
+    args_address: u64,
+    args_number: u64,
+    args_data: [u8; 0x1000],
+}
+
+/// Maps the segments of `elf`, a stack of `stack_size` pages ending at `addr_stack`,
+/// a heap and the arguments into the address space of `level_4_table_addr`.
+/// A position independent executable is loaded at a random base, and the heap and the arguments
+/// at random addresses, see `aslr`.
+/// # Safety
+/// `level_4_table_addr` must be a valid level 4 table.
+unsafe fn load_program(
+    elf: &ElfFile,
+    frame_allocator: &mut memory::BootInfoAllocator,
+    level_4_table_addr: PhysFrame,
+    addr_stack: u64,
+    stack_size: u64,
+    args: &[String],
+) -> Result<LoadedProgram, ProcessError> {
+    let base = if is_position_independent(elf) {
+        aslr::PIE_BASE + aslr::offset(aslr::PIE_PAGES)
+    } else {
+        0
+    };
+    // Every page gets the permissions of the segments it belongs to, see `elf::segment_pages`
+    for (page, flags) in elf::segment_pages(elf, base)?.iter() {
+        if let Err(memory::MemoryError(err)) = frame_allocator.add_entry_to_table(
+            level_4_table_addr,
+            VirtAddr::new(*page),
+            *flags,
+            true,
+        ) {
+            errorln!(
+                "Could not allocate the page {:#x} of the code. Error : {:?}",
+                page,
+                err
+            );
+            return Err(ProcessError::AllocatorError);
+        }
+    }
     // This represents the very end of all loaded segments
     let mut maximum_address = 0;
+    // The arguments go under the lowest segment
+    let mut minimum_address = u64::MAX;
     let _args_len = args.len();
-    // Loop over each section
-    for program in elf.program_iter() {
-        // Characteristics of the section
-        let address = program.virtual_addr();
-        let _offset = program.offset();
+    for program in elf
+        .program_iter()
+        .filter(|program| elf::is_loaded(program, base))
+    {
+        let address = base + program.virtual_addr();
         let size = program.mem_size();
-        let file_size = program.file_size();
-
         maximum_address = max(maximum_address, address + size);
-        if program.get_type().is_err() {
-            continue;
+        minimum_address = min(minimum_address, address);
+        let data = match program.get_data(elf) {
+            Ok(SegmentData::Undefined(data)) => data,
+            _ => return Err(ProcessError::InvalidExec),
         };
-        if address == 0 {
-            continue;
-        }
-
-        let mut zeroed_data = Vec::new();
-        let _data = match program.get_type().unwrap() {
-            Type::Load => match program.get_data(&elf).unwrap() {
-                SegmentData::Undefined(a) => a,
-                SegmentData::Note64(_, a) => a,
-                _ => panic!(":("),
-            },
-            Type::Dynamic => match program.get_data(&elf).unwrap() {
-                SegmentData::Undefined(a) => a,
-                SegmentData::Note64(_, a) => a,
-                _ => panic!(":("),
-            },
-            Type::Interp => match program.get_data(&elf).unwrap() {
-                SegmentData::Undefined(a) => a,
-                SegmentData::Note64(_, a) => a,
-                _ => panic!(":("),
-            },
-            Type::Tls => match program.get_data(&elf).unwrap() {
-                SegmentData::Undefined(a) => a,
-                SegmentData::Note64(_, a) => a,
-                _ => panic!(":("),
-            },
-            Type::GnuRelro => match program.get_data(&elf).unwrap() {
-                SegmentData::Undefined(a) => a,
-                SegmentData::Note64(_, a) => a,
-                _ => panic!(":("),
-            },
-            _ => {
-                for _ in 0..size {
-                    zeroed_data.push(0)
-                }
-                &zeroed_data[..]
-            }
-        };
-        let num_blocks = (file_size + 0xFFF) / 0x1000 + 1;
-        let mut flags =
-            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | elf::MODIFY_WITH_EXEC;
-        if program.flags().is_write() {
-            flags |= PageTableFlags::WRITABLE;
-        }
-        if !program.flags().is_execute() {
-            flags |= PageTableFlags::NO_EXECUTE;
-        }
-        for i in 0..num_blocks {
-            // Allocate a frame for each page needed.
-            match frame_allocator.add_entry_to_table(
-                level_4_table_addr,
-                VirtAddr::new(address + (i as u64) * 0x1000),
-                flags,
-                true,
-            ) {
-                Ok(()) => (),
-                Err(memory::MemoryError(err)) => {
-                    errorln!(
-                        "Could not allocate the {}-th part of the code. Error : {:?}",
-                        i,
-                        err
-                    );
-                }
-            }
-        }
-        match memory::write_into_virtual_memory(level_4_table_addr, VirtAddr::new(address), _data) {
-            Ok(()) => (),
-            Err(a) => errorln!("{:?} at section : {:?}", a, 0),
-        };
-        if size != file_size {
+        memory::write_into_virtual_memory(level_4_table_addr, VirtAddr::new(address), data)
+            .map_err(|_| ProcessError::WriteError)?;
+        // The rest of the segment, the `.bss`, is zeroed
+        if size > data.len() as u64 {
             let mut padding = Vec::new();
-            padding.resize(file_size as usize, 0_u8);
+            padding.resize((size - data.len() as u64) as usize, 0_u8);
             memory::write_into_virtual_memory(
                 level_4_table_addr,
-                VirtAddr::new(address + size),
+                VirtAddr::new(address + data.len() as u64),
                 &padding[..],
             )
             .map_err(|_| ProcessError::WriteError)?;
@@ -412,48 +478,24 @@ pub unsafe fn disassemble_and_launch(
             }
         }
     }
-    // Allocate pages for the heap
-    // We define the heap start address as
-    let heap_address = maximum_address + 0x8000_u64;
+    apply_relocations(elf, level_4_table_addr, base)?;
+    // The heap is only reserved, see `reserve_regions`
+    let heap_address = maximum_address + 0x8000_u64 + aslr::offset(aslr::HEAP_PAGES);
     let heap_address_normalized = heap_address - (heap_address % 0x1000);
     let heap_size = DEFAULT_HEAP_SIZE;
 
-    for i in 0..heap_size {
-        match frame_allocator.add_entry_to_table(
-            level_4_table_addr,
-            VirtAddr::new(heap_address_normalized + i * 0x1000),
-            PageTableFlags::USER_ACCESSIBLE
-                | PageTableFlags::PRESENT
-                | PageTableFlags::WRITABLE
-                | elf::HEAP,
-            false,
-        ) {
-            Ok(()) => (),
-            Err(memory::MemoryError(err)) => {
-                errorln!(
-                    "Could not allocate the {}-th part of the heap. Error : {:?}",
-                    i,
-                    err
-                );
-            }
-        }
-        match memory::write_into_virtual_memory(
-            level_4_table_addr,
-            VirtAddr::new(heap_address_normalized + i * 0x1000),
-            &[0_u8; 0x1000],
-        ) {
-            Ok(()) => (),
-            Err(a) => errorln!("{:?} at heap-section : {:?}", a, i),
-        };
-    }
-
     // Allocate a page for the process's arguments.
-    let args_address = 0x1000;
+    let args_pages = min(
+        aslr::ARGS_PAGES,
+        minimum_address.saturating_sub(ARGS_ADDRESS + 0x1000) / 0x1000,
+    );
+    let args_address = ARGS_ADDRESS + aslr::offset(args_pages);
     match frame_allocator.add_entry_to_table(
         level_4_table_addr,
         VirtAddr::new(args_address),
         PageTableFlags::USER_ACCESSIBLE
             | PageTableFlags::PRESENT
+            | PageTableFlags::NO_EXECUTE
             | PageTableFlags::WRITABLE
             | elf::HEAP,
         false,
@@ -466,7 +508,6 @@ pub unsafe fn disassemble_and_launch(
     debug!("Gonna flatten arguments : {:?}", args.len());
     debug!("args : {:?}", args);
     let (args_number, args_data) = flatten_arguments(args);
-    get_current_as_mut().set_name(&args_data);
     // Write the arguments onto the process's memory
     //debug!("Gonna write arguments");
     match memory::write_into_virtual_memory(
@@ -477,25 +518,60 @@ pub unsafe fn disassemble_and_launch(
         Ok(()) => (),
         Err(a) => errorln!("Error when writing arguments : {:?}", a),
     };
-
-    get_current_as_mut().heap_address = heap_address_normalized;
-    get_current_as_mut().heap_size = heap_size;
-    if new_process {
-        get_current_as_mut().stack_base = addr_stack;
+    if let Some(page) = elf::writable_and_executable(level_4_table_addr) {
+        errorln!(
+            "The page {:#x} is both writable and executable",
+            page.as_u64()
+        );
+        return Err(ProcessError::InvalidExec);
     }
 
-    let (_cr3, cr3f) = Cr3::read();
-    Cr3::write(level_4_table_addr, cr3f);
-    //println!("good luck user ;) {:x} {:x}", addr_stack, prog_entry);
-    println!("target : {:x}", prog_entry);
-    Ok(towards_user_give_heap_args(
-        heap_address_normalized,
+    Ok(LoadedProgram {
+        base,
+        heap_address: heap_address_normalized,
         heap_size,
         args_address,
         args_number,
-        addr_stack,
-        prog_entry,
-    ))
+        args_data,
+    })
+}
+
+/// Returns true iff `elf` is a position independent executable, which can be loaded anywhere
+fn is_position_independent(elf: &ElfFile) -> bool {
+    matches!(elf.header.pt2.type_().as_type(), header::Type::SharedObject)
+}
+
+/// Applies the relative relocations of `elf`, loaded at `base` in the address space `level_4_table_addr`.
+/// They are the only ones of a static position independent executable.
+/// # Safety
+/// `level_4_table_addr` must be a valid level 4 table, where `elf` is loaded.
+unsafe fn apply_relocations(
+    elf: &ElfFile,
+    level_4_table_addr: PhysFrame,
+    base: u64,
+) -> Result<(), ProcessError> {
+    if base == 0 {
+        return Ok(());
+    }
+    for section in elf.section_iter() {
+        let relocations = match section.get_data(elf) {
+            Ok(SectionData::Rela64(relocations)) => relocations,
+            _ => continue,
+        };
+        for relocation in relocations {
+            if relocation.get_type() != R_X86_64_RELATIVE {
+                warningln!("Unsupported relocation {}", relocation.get_type());
+                continue;
+            }
+            memory::write_into_virtual_memory(
+                level_4_table_addr,
+                VirtAddr::new(base + relocation.get_offset()),
+                &(base + relocation.get_addend()).to_le_bytes(),
+            )
+            .map_err(|_| ProcessError::WriteError)?;
+        }
+    }
+    Ok(())
 }
 
 /// Main structure of a process.
@@ -505,54 +581,85 @@ pub unsafe fn disassemble_and_launch(
 /// # Fields
 /// * `pid` - the id of the process (unique)
 /// * `ppid` - its parent's (i.e. the process that spawned it) id
-/// * `priority` - the priority, used by the scheduler (not used for now)
-/// * `quantum` - the number of consecutive quanta the process has already been running for
+/// * `priority` - the priority, i.e. the most urgent level the scheduler can give to the process
+/// * `quantum` - the number of ticks the process has already been running for at its current level
+/// * `level` - the level given by the scheduler, between `priority` and `MAX_PRIO - 1`
 /// * `cr3` - pointer to its 1st order VM table. TO DO : replace it with a PhysFrame or PhysAddr
 /// * `cr3f` - cr3 flags ???
 /// * `rip` - current value of the instruction pointer
 /// * `state` - state of the process (e.g. Zombie, Runnable...)
-/// * `owner` - owner ID of the process (can be root or user) usefull for syscalls
+/// * `credentials` - user and group IDs of the process, used for permission checks
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 #[repr(C)]
 pub struct Process {
     pid: ID,
     ppid: ID,
+    /// Process group, used for job control
+    pgid: ID,
+    /// Session, a set of process groups sharing a terminal
+    sid: ID,
     priority: Priority,
-    quantum: u64,
+    pub quantum: u64,
+    pub level: usize,
     pub cr3: PhysAddr,
     pub cr3f: Cr3Flags,
     pub rsp: u64, // every registers are saved on the stack
     pub stack_base: u64,
     pub state: State,
-    owner: u64,
+    pub credentials: Credentials,
+    pub limits: limits::Limits,
     pub heap_address: u64,
     pub heap_size: u64,
+    /// Page holding the arguments of the program
+    pub args_address: u64,
+    /// Lazily backed parts of the address space
+    pub regions: Regions,
     pub open_files: ProcessDescriptorTable,
     pub name: [u8; SIZE_NAME],
+    pub stats: stats::CpuStats,
+    /// True iff the process was stopped and its parent has not been told yet
+    pub stop_pending: bool,
+    /// Kernel threads and the idle task run on a kernel stack, with the page table of the kernel
+    kernel_thread: bool,
+    /// True iff the process was given to the first process after the death of its parent
+    adopted: bool,
     //pub screen: VirtualScreenID,
 }
 
 impl Process {
-    pub fn create_new(parent: ID, priority: Priority, owner: u64) -> Self {
-        let new_pid = ID::new();
+    pub fn create_new(parent: ID, priority: Priority, credentials: Credentials) -> Self {
+        Self::with_pid(ID::new(), parent, priority, credentials)
+    }
+
+    fn with_pid(new_pid: ID, parent: ID, priority: Priority, credentials: Credentials) -> Self {
         unsafe {
             CHILDREN.insert(new_pid, BTreeSet::new());
             Self {
                 pid: new_pid,
                 ppid: parent,
+                pgid: new_pid,
+                sid: new_pid,
                 priority,
                 quantum: 0_u64,
+                level: priority.0,
                 cr3: PhysAddr::zero(),
                 cr3f: Cr3Flags::empty(),
                 rsp: 0,
                 stack_base: 0,
                 state: State::Runnable,
-                owner,
+                credentials,
+                limits: limits::Limits::new(),
                 heap_address: 0,
+                args_address: ARGS_ADDRESS,
                 heap_size: 0,
+                regions: Regions::new(),
                 open_files: ProcessDescriptorTable::init(),
                 name: [b' '; SIZE_NAME],
+                stats: stats::CpuStats::new(wait_queue::get_ticks()),
+                stop_pending: false,
+                kernel_thread: false,
+                adopted: false,
                 //screen: VirtualScreenID::new(),
             }
         }
@@ -561,42 +668,37 @@ impl Process {
     pub fn fork(&self) -> Self {
         let new_pid = ID::new();
         let mut open_files = ProcessDescriptorTable::init();
-        open_files.copy(self.open_files);
+        open_files.copy(&self.open_files);
+        for region in self.regions.iter() {
+            if let RegionKind::File(id) = region.kind {
+                memory::mmap::share(id);
+            }
+        }
         Self {
             pid: new_pid,
             ppid: self.pid,
+            pgid: self.pgid,
+            sid: self.sid,
             priority: self.priority,
             quantum: 0_u64,
+            level: self.priority.0,
             cr3: PhysAddr::zero(),
             cr3f: self.cr3f,
             rsp: self.rsp,
             stack_base: self.stack_base,
             state: self.state,
-            owner: self.owner,
+            credentials: self.credentials,
+            limits: self.limits,
             heap_address: self.heap_address,
+            args_address: self.args_address,
             heap_size: self.heap_size,
+            regions: self.regions.clone(),
             open_files,
             name: self.name,
-        }
-    }
-
-    pub const fn missing() -> Self {
-        Self {
-            pid: ID(0),
-            ppid: ID(0),
-            priority: Priority(0),
-            quantum: 0_u64,
-            cr3: PhysAddr::zero(),
-            cr3f: Cr3Flags::empty(),
-            rsp: 0,
-            stack_base: 0,
-            state: State::SlotAvailable,
-            owner: 0,
-            heap_address: 0,
-            heap_size: 0,
-            open_files: ProcessDescriptorTable::init(),
-            name: [b' '; SIZE_NAME],
-            //screen: VirtualScreenID::null(),
+            stats: stats::CpuStats::new(wait_queue::get_ticks()),
+            stop_pending: false,
+            kernel_thread: false,
+            adopted: false,
         }
     }
 
@@ -605,7 +707,7 @@ impl Process {
     /// `spawn` returns the PID of the child that is newly created.
     pub fn spawn(self, priority: Priority) -> ID {
         // -> &Mutex<Self> {
-        let child = Process::create_new(self.pid, priority, self.owner);
+        let child = Process::create_new(self.pid, priority, self.credentials);
         unsafe {
             CHILDREN.entry(self.pid).and_modify(|set| {
                 set.insert(child.pid);
@@ -618,12 +720,26 @@ impl Process {
     /// TODO
     pub unsafe fn died(&mut self, code: usize) {
         self.state = State::Zombie(code);
-        for process in ID_TABLE.iter_mut() {
-            if process.ppid == self.pid {
-                process.ppid = self.ppid;
+        IDLE.remove(&self.pid);
+        realtime::remove(self.pid);
+        // Orphans are given to the first process, and reaped by the supervisor thread
+        for process in ID_TABLE.values_mut() {
+            if process.ppid == self.pid && process.pid != self.pid {
+                process.ppid = FIRST_PID;
+                process.adopted = true;
             }
         }
-        self.open_files.close()
+        self.open_files.close();
+        wait_queue::forget(self.pid);
+        wait_queue::wake_up(Resource::ChildExit(self.ppid));
+    }
+
+    pub fn get_pid(&self) -> ID {
+        self.pid
+    }
+
+    pub fn get_priority(&self) -> usize {
+        self.priority.0
     }
 
     pub fn get_heap(&self) -> usize {
@@ -634,11 +750,39 @@ impl Process {
         self.ppid.as_usize()
     }
 
+    pub fn get_pgid(&self) -> ID {
+        self.pgid
+    }
+
+    pub fn get_sid(&self) -> ID {
+        self.sid
+    }
+
     pub fn set_name(&mut self, name: &[u8]) {
         self.name[..min(name.len(), SIZE_NAME)]
             .clone_from_slice(&name[..min(name.len(), SIZE_NAME)]);
     }
 
+    /// Returns the ranges of the address space, see `regions::mappings`
+    pub fn mappings(&self) -> Vec<regions::Mapping> {
+        unsafe {
+            regions::mappings(
+                &self.regions,
+                PhysFrame::containing_address(self.cr3),
+                self.args_address,
+            )
+        }
+    }
+
+    /// Returns the number of pages of the process backed by a frame
+    pub fn resident_pages(&self) -> u64 {
+        self.mappings().iter().map(|mapping| mapping.resident).sum()
+    }
+
+    pub fn is_kernel_thread(&self) -> bool {
+        self.kernel_thread
+    }
+
     pub fn get_name(&self) -> Vec<u8> {
         Vec::from(self.name)
     }
@@ -670,7 +814,6 @@ pub enum State {
     SleepInterruptible,
     SleepUninterruptible,
     Stopped,
-    SlotAvailable,
 }
 
 /// A process's ID.
@@ -683,18 +826,10 @@ pub struct ID(pub u64);
 impl ID {
     /// Returns a fresh ID. It uses an atomic operation to make sure no two processes can have the same id.
     ///
-    /// For now, a process's id isn't freed when it exits.
+    /// IDs are never reused, so a stale pid can not designate a newer process.
     pub fn new() -> Self {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
-        for _i in 0..PROCESS_MAX_NUMBER {
-            let new = NEXT_ID.fetch_add(1, Ordering::Relaxed);
-            unsafe {
-                if ID_TABLE[(new % PROCESS_MAX_NUMBER) as usize].state == State::SlotAvailable {
-                    return ID(new % PROCESS_MAX_NUMBER);
-                }
-            }
-        }
-        panic!("no slot available")
+        ID(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
 
     pub fn as_usize(&self) -> usize {
@@ -712,12 +847,28 @@ impl Default for ID {
     }
 }
 
-/// Main array of all processes
-pub static mut ID_TABLE: [Process; PROCESS_MAX_NUMBER as usize] =
-    [Process::missing(); PROCESS_MAX_NUMBER as usize];
+/// Pid of the first process, which the orphans are given to
+pub const FIRST_PID: ID = ID(0);
+
+/// Pid of the idle task of the bootstrap processor, no real process can get it
+pub const IDLE_PID: ID = ID(u64::MAX);
+
+/// Returns the pid of the idle task of the processor `cpu`
+pub fn idle_pid(cpu: usize) -> ID {
+    ID(IDLE_PID.0 - cpu as u64)
+}
+
+/// Returns true iff `pid` is the idle task of a processor
+pub fn is_idle(pid: ID) -> bool {
+    pid.0 > IDLE_PID.0 - MAX_CPUS as u64
+}
+
+/// Main table of all processes, indexed by their pid.
+/// Processes are boxed so that they do not move when the table grows.
+pub static mut ID_TABLE: BTreeMap<ID, Box<Process>> = BTreeMap::new();
 
 pub fn spawn_first_process() {
-    let mut proc = Process::create_new(ID::forge(0), Priority(0), 0);
+    let mut proc = Process::create_new(FIRST_PID, Priority(0), Credentials::root());
     let cr3 = x86_64::registers::control::Cr3::read();
     proc.cr3 = cr3.0.start_address();
     proc.cr3f = cr3.1;
@@ -726,21 +877,457 @@ pub fn spawn_first_process() {
     } else {
         errorln!("could not find mainscreen in first process");
     }*/
+    open_standard_files(&mut proc);
+    unsafe {
+        ID_TABLE.insert(proc.pid, Box::new(proc));
+    }
+}
+
+/// Opens the keyboard, a new virtual screen and the host shell as the standard files of `proc`
+fn open_standard_files(proc: &mut Process) {
+    let max_files = proc.limits.soft(Rlimit::OpenFiles) as usize;
     let screen_file_name = "/hard/kbd";
     proc.open_files
-        .create_file_table(Path::from(&screen_file_name), OpenFlags::ORD);
+        .create_file_table(Path::from(&screen_file_name), OpenFlags::ORD, max_files);
     let screen_file_name = "/hard/screen";
     proc.open_files
-        .create_file_table(Path::from(&screen_file_name), OpenFlags::OWR);
+        .create_file_table(Path::from(&screen_file_name), OpenFlags::OWR, max_files);
     let shell_file_name = "/hard/host";
     proc.open_files
-        .create_file_table(Path::from(&shell_file_name), OpenFlags::OWR);
+        .create_file_table(Path::from(&shell_file_name), OpenFlags::OWR, max_files);
+}
+
+/// Creates a runnable process, child of `parent`, running the program at `path` with the arguments `args`.
+/// It inherits the credentials and the limits of its parent, and gets the same standard files as the first process.
+/// # Safety
+/// Needs to be called with interrupts disabled.
+pub unsafe fn spawn_program(path: &str, args: &[String], parent: ID) -> Result<ID, ProcessError> {
+    let code = filesystem::read_program_from_path(Path::from(path))
+        .map_err(|_| ProcessError::ReadError)?;
+    if code.is_empty() {
+        return Err(ProcessError::ReadError);
+    }
+    let (elf, entry) = parse_elf(&code)?;
+    let parent_process = get_process(parent.as_usize()).ok_or(ProcessError::InvalidExec)?;
+    let frame_allocator = memory::FRAME_ALLOCATOR
+        .as_mut()
+        .ok_or(ProcessError::AllocatorError)?;
+    let level_4_table_addr = frame_allocator
+        .allocate_level_4_frame()
+        .map_err(|_| ProcessError::AllocatorError)?;
+
+    let mut process =
+        Process::create_new(parent, parent_process.priority, parent_process.credentials);
+    process.limits = parent_process.limits;
+    process.cr3 = level_4_table_addr.start_address();
+    process.cr3f = Cr3::read().1;
+    if let Err(error) = prepare_program(&mut process, &elf, entry, frame_allocator, args) {
+        CHILDREN.remove(&process.pid);
+        free_memory(&mut process);
+        return Err(error);
+    }
+    open_standard_files(&mut process);
+
+    let pid = process.pid;
+    ID_TABLE.insert(pid, Box::new(process));
+    if super::enqueue(&ID_TABLE[&pid]) {
+        Ok(pid)
+    } else {
+        if let Some(mut process) = ID_TABLE.remove(&pid) {
+            CHILDREN.remove(&pid);
+            process.open_files.close();
+            free_memory(&mut process);
+        }
+        Err(ProcessError::TooManyProcesses)
+    }
+}
+
+/// Loads `elf` into the address space of `process`, and prepares its stack so that the program is started
+/// by `leave_context_cr3` in the same state as with `towards_user_give_heap_args`.
+unsafe fn prepare_program(
+    process: &mut Process,
+    elf: &ElfFile,
+    entry: u64,
+    frame_allocator: &mut memory::BootInfoAllocator,
+    args: &[String],
+) -> Result<(), ProcessError> {
+    let level_4_table_addr = PhysFrame::containing_address(process.cr3);
+    let stack_top = aslr::stack_top(USER_STACK_TOP);
+    let program = load_program(
+        elf,
+        frame_allocator,
+        level_4_table_addr,
+        stack_top,
+        SPAWN_STACK_SIZE,
+        args,
+    )?;
+    let mut registers = Registers::new();
+    registers.rdi = program.heap_address;
+    registers.rsi = program.heap_size;
+    registers.rdx = program.args_address;
+    registers.rcx = program.args_number;
+    let frame_address = InitialFrame::address(stack_top);
+    let frame = InitialFrame::new(registers, program.base + entry, USER_RFLAGS, stack_top);
+    memory::write_into_virtual_memory(
+        level_4_table_addr,
+        VirtAddr::new(stack_top),
+        &RETURN_ADDRESS.to_le_bytes(),
+    )
+    .map_err(|_| ProcessError::WriteError)?;
+    memory::write_into_virtual_memory(
+        level_4_table_addr,
+        VirtAddr::new(frame_address),
+        frame.as_bytes(),
+    )
+    .map_err(|_| ProcessError::WriteError)?;
+
+    process.rsp = frame_address;
+    process.stack_base = stack_top;
+    process.heap_address = program.heap_address;
+    process.args_address = program.args_address;
+    process.heap_size = program.heap_size;
+    reserve_regions(process, stack_top);
+    process.set_name(&program.args_data);
+    Ok(())
+}
+
+/// Reserves the heap of `process` and its stack ending at `stack_top`, they are backed on first touch.
+/// The stack can grow down to `Rlimit::StackPages` pages.
+fn reserve_regions(process: &mut Process, stack_top: u64) {
+    let mut regions = Regions::new();
+    regions.reserve(
+        process.heap_address,
+        process.heap_size,
+        PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::PRESENT
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::WRITABLE
+            | elf::HEAP,
+        RegionKind::Heap,
+    );
+    let stack_end = (stack_top & !(regions::PAGE_SIZE - 1)) + regions::PAGE_SIZE;
+    let stack_pages = min(process.limits.soft(Rlimit::StackPages), MAX_STACK_PAGES);
+    if !regions.reserve(
+        stack_end - stack_pages * regions::PAGE_SIZE,
+        stack_pages,
+        PageTableFlags::USER_ACCESSIBLE
+            | PageTableFlags::PRESENT
+            | PageTableFlags::NO_EXECUTE
+            | PageTableFlags::WRITABLE
+            | elf::STACK,
+        RegionKind::Stack,
+    ) {
+        crate::warningln!("The stack of process {} can not grow", process.pid.0);
+    }
+    process.regions = regions;
+}
+
+/// Backs the page of `address` if the current process reserved it, see `regions`.
+/// # Safety
+/// Needs to be called from the page fault handler, with the kernel lock held.
+pub unsafe fn handle_page_fault(address: VirtAddr) -> regions::Fault {
+    let current = get_current_as_mut();
+    let fault = match &mut memory::FRAME_ALLOCATOR {
+        Some(frame_allocator) => regions::handle_fault(
+            &current.regions,
+            Cr3::read().0,
+            address.as_u64(),
+            frame_allocator,
+        ),
+        None => regions::Fault::OutOfMemory,
+    };
+    if fault == regions::Fault::Backed {
+        current.stats.page_faults += 1;
+    }
+    fault
+}
+
+/// Returns the start of `pages` free pages in the address space of `process`: `address` if it is not 0,
+/// or an address picked from `SHARED_BASE`. Returns None if `address` is not page aligned or if the pages are not free.
+unsafe fn free_range(process: &Process, address: u64, pages: u64) -> Option<u64> {
+    let level_4 = PhysFrame::containing_address(process.cr3);
+    let start = if address == 0 {
+        process.regions.find_free(SHARED_BASE, pages)
+    } else if address & (regions::PAGE_SIZE - 1) == 0 {
+        address
+    } else {
+        return None;
+    };
+    let end = start.checked_add(pages * regions::PAGE_SIZE)?;
+    if end > USER_STACK_TOP
+        || (start..end)
+            .step_by(regions::PAGE_SIZE as usize)
+            .any(|page| {
+                memory::check_if_has_flags(level_4, VirtAddr::new(page), PageTableFlags::PRESENT)
+            })
+    {
+        return None;
+    }
+    Some(start)
+}
+
+/// Maps the shared-memory segment `id` in the current process at `address`, or at an address
+/// picked from `SHARED_BASE` if it is 0. The mapping is dropped by `exec`.
+/// Returns the address, or None if the segment is empty or does not exist, if the pages are not free
+/// or if there is no frame left for the page tables.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn attach_shared(id: u64, address: u64, writable: bool) -> Option<u64> {
+    let pages = match memory::shm::pages(id) {
+        Some(pages) if pages > 0 => pages,
+        _ => return None,
+    };
+    let current = get_current_as_mut();
+    let level_4 = Cr3::read().0;
+    let start = free_range(current, address, pages)?;
+    let mut flags = PageTableFlags::PRESENT
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE
+        | elf::MODIFY_WITH_EXEC;
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !current
+        .regions
+        .reserve(start, pages, flags, RegionKind::Shared)
+    {
+        return None;
+    }
+    if memory::shm::map(id, level_4, VirtAddr::new(start), flags).is_err() {
+        current.regions.remove_at(start);
+        return None;
+    }
+    Some(start)
+}
+
+/// Unmaps the shared-memory segment mapped at `address` in the current process.
+/// Returns false if no segment starts there.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn detach_shared(address: u64) -> bool {
+    let current = get_current_as_mut();
+    match current.regions.find(address) {
+        Some(region) if region.kind == RegionKind::Shared && region.start == address => (),
+        _ => return false,
+    }
+    if let Some(region) = current.regions.remove_at(address) {
+        memory::shm::unmap(Cr3::read().0, VirtAddr::new(region.start), region.pages());
+    }
+    true
+}
+
+/// Maps `length` bytes of the file open as `fd` from `offset` in the current process at `address`,
+/// or at an address picked from `SHARED_BASE` if it is 0. The pages are read from the file on first touch.
+/// A shared mapping writes back to the file, a private one keeps its writes, see `memory::mmap`.
+/// The mapping is dropped by `exec` and when the process exits.
+/// Returns the address, or None if the file can not be mapped with these rights, if the range goes past
+/// the end of the file or if the pages are not free.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn map_file(
+    fd: usize,
+    length: u64,
+    offset: u64,
+    address: u64,
+    writable: bool,
+    shared: bool,
+) -> Option<u64> {
+    let current = get_current_as_mut();
+    let oft = current
+        .open_files
+        .get_file_table(FileDescriptor::new(fd))
+        .ok()?;
+    let flags = oft.get_flags();
+    if !flags.contains(OpenFlags::ORD) || (writable && shared && !flags.contains(OpenFlags::OWR)) {
+        return None;
+    }
+    let pages = (length + regions::PAGE_SIZE - 1) / regions::PAGE_SIZE;
+    if pages == 0 {
+        return None;
+    }
+    let start = free_range(current, address, pages)?;
+    let id = memory::mmap::create(&oft.get_path().to(), offset, shared)?;
+    // A page past the end of the file could not be read, the tail of the last page reads as zeroes
+    if memory::mmap::pages(id).map_or(true, |file_pages| pages > file_pages) {
+        memory::mmap::release(id);
+        return None;
+    }
+    let mut flags = PageTableFlags::PRESENT
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE
+        | elf::MODIFY_WITH_EXEC;
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !current
+        .regions
+        .reserve(start, pages, flags, RegionKind::File(id))
+    {
+        memory::mmap::release(id);
+        return None;
+    }
+    Some(start)
+}
+
+/// Returns the region of the file mapped at `address` in the current process
+fn file_region(process: &Process, address: u64) -> Option<(u64, regions::Region)> {
+    match process.regions.find(address) {
+        Some(region) if region.start == address => match region.kind {
+            RegionKind::File(id) => Some((id, *region)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Writes back the dirty pages of the file mapped at `address` in the current process.
+/// Returns false if no file is mapped there.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn sync_file(address: u64) -> bool {
+    match file_region(get_current(), address) {
+        Some((id, region)) => {
+            memory::mmap::sync(
+                id,
+                Cr3::read().0,
+                VirtAddr::new(region.start),
+                region.pages(),
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+/// Writes back the dirty pages of the mappings of the file at `path` in the current process, when it is closed
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn sync_file_path(path: &str) {
+    let current = get_current();
+    for region in current.regions.iter() {
+        if let RegionKind::File(id) = region.kind {
+            if memory::mmap::maps(id, path) {
+                memory::mmap::sync(
+                    id,
+                    Cr3::read().0,
+                    VirtAddr::new(region.start),
+                    region.pages(),
+                );
+            }
+        }
+    }
+}
+
+/// Writes back and removes the file mapped at `address` in the current process.
+/// Returns false if no file is mapped there.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+pub unsafe fn unmap_file(address: u64) -> bool {
+    let current = get_current_as_mut();
+    let (id, region) = match file_region(current, address) {
+        Some(file) => file,
+        None => return false,
+    };
+    let level_4 = Cr3::read().0;
+    memory::mmap::sync(id, level_4, VirtAddr::new(region.start), region.pages());
+    memory::unmap_pages(level_4, VirtAddr::new(region.start), region.pages());
+    memory::mmap::release(id);
+    current.regions.remove_at(region.start);
+    true
+}
+
+/// Writes back and forgets the files mapped by `process`, before its pages are given back
+/// by `exec` or when it exits.
+/// # Safety
+/// Needs to be called with the kernel lock held, `process` running on no other processor.
+pub unsafe fn release_files(process: &mut Process) {
+    let level_4 = PhysFrame::containing_address(process.cr3);
+    let files: Vec<(u64, regions::Region)> = process
+        .regions
+        .iter()
+        .filter_map(|region| match region.kind {
+            RegionKind::File(id) => Some((id, *region)),
+            _ => None,
+        })
+        .collect();
+    for (id, region) in files {
+        memory::mmap::sync(id, level_4, VirtAddr::new(region.start), region.pages());
+        memory::mmap::release(id);
+        process.regions.remove_at(region.start);
+    }
+}
+
+/// Creates a process running in the address space of the kernel, `rsp` pointing to its saved registers.
+fn new_kernel_thread(pid: ID, name: &[u8], priority: Priority, rsp: u64) -> Process {
+    let mut thread = Process::with_pid(pid, IDLE_PID, priority, Credentials::root());
+    let cr3 = x86_64::registers::control::Cr3::read();
+    thread.cr3 = cr3.0.start_address();
+    thread.cr3f = cr3.1;
+    thread.rsp = rsp;
+    thread.kernel_thread = true;
+    thread.set_name(name);
+    thread
+}
+
+/// Registers the idle task of the processor `cpu`, `rsp` pointing to its saved registers.
+/// See [`crate::scheduler::idle`]
+pub fn spawn_idle(cpu: usize, rsp: u64) {
+    let pid = idle_pid(cpu);
+    let idle = new_kernel_thread(pid, b"idle", Priority(MAX_PRIO - 1), rsp);
+    unsafe {
+        ID_TABLE.insert(pid, Box::new(idle));
+    }
+}
+
+/// Runs the idle task of the current processor, used once an application processor is initialised.
+/// # Safety
+/// The idle task of the processor must have been registered
+pub unsafe fn enter_idle() -> ! {
+    let pid = idle_pid(smp::cpu_id());
+    set_current(pid.as_usize());
+    let idle = get_current();
+    leave_context_cr3(idle.cr3.as_u64() | idle.cr3f.bits(), idle.rsp)
+}
+
+/// Registers a kernel thread and makes it runnable, `rsp` pointing to its saved registers.
+/// See [`crate::scheduler::kthread`]
+pub fn spawn_kernel_thread(name: &[u8], priority: usize, rsp: u64) -> Result<ID, ProcessError> {
+    let pid = ID::new();
+    let thread = new_kernel_thread(pid, name, Priority(min(priority, MAX_PRIO - 1)), rsp);
+    unsafe {
+        ID_TABLE.insert(pid, Box::new(thread));
+        if !super::enqueue(get_process(pid.as_usize()).unwrap()) {
+            ID_TABLE.remove(&pid);
+            CHILDREN.remove(&pid);
+            return Err(ProcessError::TooManyProcesses);
+        }
+    }
+    Ok(pid)
+}
+
+/// Pid of the process running on each processor
+static mut CURRENT_PROCESS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Returns the pid of the process running on the current processor
+pub fn current_pid() -> usize {
+    unsafe { CURRENT_PROCESS[smp::cpu_id()] }
+}
+
+/// Returns the pid of the process running on the processor `cpu`
+pub fn current_pid_on(cpu: usize) -> ID {
+    unsafe { ID(CURRENT_PROCESS[cpu] as u64) }
+}
+
+fn set_current(pid: usize) {
     unsafe {
-        ID_TABLE[0] = proc;
+        CURRENT_PROCESS[smp::cpu_id()] = pid;
     }
 }
 
-pub static mut CURRENT_PROCESS: usize = 0;
+/// Returns true iff `pid` is running on some processor
+pub(super) fn is_running(pid: ID) -> bool {
+    (0..smp::cpu_count()).any(|cpu| current_pid_on(cpu) == pid)
+}
 
 /// # Safety
 /// Depends of the usage of the data !
@@ -748,88 +1335,164 @@ pub static mut CURRENT_PROCESS: usize = 0;
 /// data structure (as mutable) and the next process to run one's (non mut)
 /// Beware of not doing anything on this data !
 pub unsafe fn gives_switch(_counter: u64) -> (&'static Process, &'static mut Process) {
-    let old_pid = CURRENT_PROCESS;
+    let old_pid = current_pid();
     let new_pid = next_pid_to_run().0 as usize;
-    CURRENT_PROCESS = new_pid;
-    (&ID_TABLE[new_pid], &mut ID_TABLE[old_pid])
+    set_current(new_pid);
+    if new_pid != old_pid {
+        if let Some(old) = get_process_as_mut(old_pid) {
+            old.stats.context_switches += 1;
+        }
+    }
+    (
+        get_process(new_pid).expect("next process is missing"),
+        get_process_as_mut(old_pid).expect("previous process is missing"),
+    )
 }
 
 /// # Safety
 /// Depends of the usage of the data !
 /// From the number of cycles executed and return code, returns a new process
 pub unsafe fn process_died(_counter: u64, return_code: u64) -> &'static Process {
-    let old_pid = CURRENT_PROCESS;
-    if old_pid == 0 {
+    terminate(get_current_as_mut(), return_code as usize);
+
+    let new_pid = next_pid_to_run().0 as usize;
+    set_current(new_pid);
+    get_current()
+}
+
+/// Ends `process` with `return_code`, the system is shut down with the first process.
+/// # Safety
+/// Needs to be called with the kernel lock held.
+unsafe fn terminate(process: &mut Process, return_code: usize) {
+    if process.pid.as_usize() == 0 {
         crate::hardware::power::shutdown();
     }
-    ID_TABLE[old_pid].died(return_code as usize);
+    process.died(return_code);
+}
 
-    let new_pid = next_pid_to_run().0 as usize;
-    CURRENT_PROCESS = new_pid;
+/// Removes a zombie process from the table and frees its memory.
+/// Returns its return value, or None if it is not a zombie or if another processor did not switch it out yet.
+unsafe fn reap(pid: ID) -> Option<usize> {
+    let return_value = match ID_TABLE.get(&pid)?.state {
+        State::Zombie(return_value) => return_value,
+        _ => return None,
+    };
+    if is_running(pid) {
+        return None;
+    }
+    let mut process = ID_TABLE.remove(&pid)?;
+    CHILDREN.remove(&pid);
+    if let Some(children) = CHILDREN.get_mut(&process.ppid) {
+        children.remove(&pid);
+    }
+    free_memory(&mut process);
+    Some(return_value)
+}
 
-    &ID_TABLE[new_pid]
+/// Gives back all the frames used by a process which is not running anymore.
+unsafe fn free_memory(process: &mut Process) {
+    release_files(process);
+    if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
+        frame_allocator.deallocate_level_4_page(
+            process.cr3,
+            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
+            true,
+        );
+        frame_allocator.deallocate_4k_frame(process.cr3);
+    }
 }
 
 pub fn listen(id: usize) -> (usize, usize) {
     unsafe {
-        let ppid = ID::forge(CURRENT_PROCESS as u64);
-        if id == 0 {
-            for (pid, process) in ID_TABLE.iter_mut().enumerate() {
-                if process.ppid == ppid {
-                    if let State::Zombie(return_value) = process.state {
-                        process.state = State::SlotAvailable;
-                        if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
-                            frame_allocator.deallocate_level_4_page(
-                                process.cr3,
-                                PageTableFlags::USER_ACCESSIBLE,
-                                true,
-                            );
-                            frame_allocator.deallocate_4k_frame(process.cr3);
-                        }
-                        return (pid, return_value);
-                    }
-                }
-            }
+        let ppid = ID::forge(current_pid() as u64);
+        let target = if id == 0 {
+            ID_TABLE
+                .values()
+                .find(|process| process.ppid == ppid && matches!(process.state, State::Zombie(_)))
+                .map(|process| process.pid)
         } else {
-            let process = &mut ID_TABLE[id];
-            if process.ppid == ppid {
-                if let State::Zombie(return_value) = process.state {
-                    process.state = State::SlotAvailable;
-                    if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
-                        frame_allocator.deallocate_level_4_page(
-                            process.cr3,
-                            PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
-                            true,
-                        );
-                        frame_allocator.deallocate_4k_frame(process.cr3);
-                    }
-                    return (id, return_value);
-                }
+            ID_TABLE
+                .get(&ID(id as u64))
+                .filter(|process| process.ppid == ppid)
+                .map(|process| process.pid)
+        };
+        if let Some(pid) = target {
+            if let Some(return_value) = reap(pid) {
+                return (pid.as_usize(), return_value);
             }
         }
         (0, 0)
     }
 }
 
+/// Reaps every orphan given to the first process that died.
+/// Returns the number of reaped processes
+/// # Safety
+/// Needs to be called with interrupts disabled.
+pub unsafe fn reap_adopted() -> usize {
+    let orphans: Vec<ID> = ID_TABLE
+        .values()
+        .filter(|process| {
+            process.adopted
+                && process.ppid == FIRST_PID
+                && matches!(process.state, State::Zombie(_))
+        })
+        .map(|process| process.pid)
+        .collect();
+    orphans.into_iter().filter_map(|pid| reap(pid)).count()
+}
+
+/// Returns the number of children of `pid` that have not been reaped yet.
+fn count_children(pid: ID) -> usize {
+    unsafe {
+        ID_TABLE
+            .values()
+            .filter(|process| process.ppid == pid && process.pid != pid)
+            .count()
+    }
+}
+
+/// Returns true iff the process `pid` has a child that has not been reaped yet.
+pub fn has_children(pid: ID) -> bool {
+    unsafe {
+        ID_TABLE
+            .values()
+            .any(|process| process.ppid == pid && process.pid != pid)
+    }
+}
+
 /// Returns the current process data structure as read only
 /// # Safety
 /// TODO
 pub fn get_current() -> &'static Process {
-    unsafe { &ID_TABLE[CURRENT_PROCESS] }
+    unsafe { get_process(current_pid()).expect("current process is missing") }
 }
 
 /// # Safety
 /// Depends on the usage. May cause aliasing
 /// Returns the current process data structure as mutable
 pub unsafe fn get_current_as_mut() -> &'static mut Process {
-    &mut ID_TABLE[CURRENT_PROCESS]
+    get_process_as_mut(current_pid()).expect("current process is missing")
+}
+
+/// Returns the credentials of the current process, or the root ones if no process was launched yet.
+pub fn current_credentials() -> Credentials {
+    unsafe { get_process(current_pid()) }.map_or(Credentials::root(), |process| process.credentials)
 }
 
-/// TODO safeguard the index
+/// Returns the process of the given pid, if it exists.
 /// # Safety
-/// TODO
-pub unsafe fn get_process(pid: usize) -> &'static Process {
-    &ID_TABLE[pid]
+/// The reference is invalidated once the process is reaped.
+pub unsafe fn get_process(pid: usize) -> Option<&'static Process> {
+    ID_TABLE.get(&ID(pid as u64)).map(|process| &**process)
+}
+
+/// # Safety
+/// Same as `get_process`, and may cause aliasing
+pub unsafe fn get_process_as_mut(pid: usize) -> Option<&'static mut Process> {
+    ID_TABLE
+        .get_mut(&ID(pid as u64))
+        .map(|process| &mut **process)
 }
 
 /// # Safety
@@ -837,30 +1500,83 @@ pub unsafe fn get_process(pid: usize) -> &'static Process {
 /// Function to duplicate the current process into two childs
 /// For more info on the usage, see the code of the fork syscall
 /// Returns : child process pid
-pub unsafe fn fork() -> ID {
-    let mut son = ID_TABLE[CURRENT_PROCESS].fork();
-    if let Some(frame_allocator) = &mut memory::FRAME_ALLOCATOR {
-        match frame_allocator.copy_table_entries(ID_TABLE[CURRENT_PROCESS].cr3) {
-            Ok(phys) => son.cr3 = phys,
-            Err(_) => panic!("TODO"),
+pub unsafe fn fork() -> Result<ID, ProcessError> {
+    let current = get_current();
+    if count_children(current.pid) as u64 >= current.limits.soft(Rlimit::Children) {
+        return Err(ProcessError::LimitReached);
+    }
+    let frame_allocator = match &mut memory::FRAME_ALLOCATOR {
+        Some(frame_allocator) => frame_allocator,
+        None => return Err(ProcessError::AllocatorError),
+    };
+    let mut son = current.fork();
+    // The memory is shared copy-on-write, only the page tables are copied
+    match frame_allocator.copy_table_entries(current.cr3) {
+        Ok(phys) => son.cr3 = phys,
+        Err(memory::MemoryError(error)) => {
+            errorln!(
+                "Could not copy the address space of {} : {}",
+                current.pid.0,
+                error
+            );
+            son.open_files.close();
+            return Err(ProcessError::AllocatorError);
         }
-    } else {
-        panic!("uninitialized frame allocator");
     }
     let pid = son.pid;
     son.state = State::Runnable;
-    ID_TABLE[pid.0 as usize] = son;
-    WAITING_QUEUES[son.priority.0]
-        .push(pid)
-        .expect("Could not push son process into the queue");
-    pid
+    ID_TABLE.insert(pid, Box::new(son));
+    if super::enqueue(&ID_TABLE[&pid]) {
+        Ok(pid)
+    } else {
+        // The scheduler can not take it, the child never existed
+        if let Some(mut son) = ID_TABLE.remove(&pid) {
+            son.open_files.close();
+            free_memory(&mut son);
+        }
+        Err(ProcessError::TooManyProcesses)
+    }
 }
 
 pub fn dup2(fd_target: usize, fd_from: usize) -> Result<usize, FileDesciptorError> {
     unsafe {
-        ID_TABLE[CURRENT_PROCESS]
-            .open_files
-            .dup(FileDescriptor::new(fd_target), FileDescriptor::new(fd_from))
+        let current = get_current_as_mut();
+        let max_files = current.limits.soft(Rlimit::OpenFiles) as usize;
+        current.open_files.dup(
+            FileDescriptor::new(fd_target),
+            FileDescriptor::new(fd_from),
+            max_files,
+        )
+    }
+}
+
+/// Returns the limits of the current process for the resource number `resource`.
+/// Returns : (usize::MAX, usize::MAX) or (soft limit, hard limit)
+pub fn getrlimit(resource: u64) -> (u64, u64) {
+    match Rlimit::from_u64(resource) {
+        Some(resource) => {
+            let limit = get_current().limits.get(resource);
+            (limit.soft, limit.hard)
+        }
+        None => (u64::MAX, u64::MAX),
+    }
+}
+
+/// Changes the limits of the current process for the resource number `resource`.
+/// Only root can raise a hard limit.
+/// Returns : usize::MAX or 0 if it succeeds
+pub fn setrlimit(resource: u64, soft: u64, hard: u64) -> usize {
+    let current = unsafe { get_current_as_mut() };
+    let privileged = current.credentials.is_root();
+    match Rlimit::from_u64(resource) {
+        Some(resource)
+            if current
+                .limits
+                .set(resource, limits::Limit::new(soft, hard), privileged) =>
+        {
+            0
+        }
+        _ => usize::MAX,
     }
 }
 
@@ -870,24 +1586,162 @@ pub fn dup2(fd_target: usize, fd_from: usize) -> Result<usize, FileDesciptorErro
 /// the given value. It can be only decreasing
 /// Returns : usize::MAX or the new priority if succeeds
 pub unsafe fn set_priority(prio: usize) -> usize {
-    // TODO : change attribution in WAITING_QUEUES? Or do we wait till the next execution? Is the overhead worth it?
-    if prio > MAX_PRIO {
+    if prio >= MAX_PRIO {
         return usize::MAX;
     }
-    if ID_TABLE[CURRENT_PROCESS].priority.0 <= prio {
-        ID_TABLE[CURRENT_PROCESS].priority.0 = prio;
+    let current = get_current_as_mut();
+    if current.priority.0 <= prio {
+        current.priority.0 = prio;
+        // The new level is taken into account at the next switch
+        current.level = max(current.level, prio);
         prio
     } else {
         usize::MAX
     }
 }
 
+/// Moves the process `pid` (0 for the current one) into the process group `pgid` (0 to use `pid`).
+/// It is only allowed for the current process or one of its children,
+/// if the group is in the same session. Session leaders can not change their group.
+/// Returns : usize::MAX or 0 if it succeeds
+pub fn setpgid(pid: usize, pgid: usize) -> usize {
+    unsafe {
+        let current = get_current();
+        let pid = if pid == 0 {
+            current.pid
+        } else {
+            ID(pid as u64)
+        };
+        let pgid = if pgid == 0 { pid } else { ID(pgid as u64) };
+        let (sid, ppid) = match get_process(pid.as_usize()) {
+            Some(process) => (process.sid, process.ppid),
+            None => return usize::MAX,
+        };
+        if (pid != current.pid && ppid != current.pid) || sid != current.sid || sid == pid {
+            return usize::MAX;
+        }
+        // A new group can only be created with the process as its leader
+        if pgid != pid
+            && !ID_TABLE
+                .values()
+                .any(|process| process.pgid == pgid && process.sid == sid)
+        {
+            return usize::MAX;
+        }
+        if let Some(process) = get_process_as_mut(pid.as_usize()) {
+            process.pgid = pgid;
+        }
+        0
+    }
+}
+
+/// Returns the process group of the process `pid` (0 for the current one), or usize::MAX if it does not exist.
+pub fn getpgid(pid: usize) -> usize {
+    if pid == 0 {
+        return get_current().pgid.as_usize();
+    }
+    match unsafe { get_process(pid) } {
+        Some(process) => process.pgid.as_usize(),
+        None => usize::MAX,
+    }
+}
+
+/// The current process becomes the leader of a new session and of a new process group.
+/// Returns : usize::MAX if it already leads a process group, or the new session id
+pub fn setsid() -> usize {
+    unsafe {
+        let current = get_current_as_mut();
+        let pid = current.pid;
+        if ID_TABLE.values().any(|process| process.pgid == pid) {
+            return usize::MAX;
+        }
+        current.pgid = pid;
+        current.sid = pid;
+        pid.as_usize()
+    }
+}
+
+/// Stops the process `pid`: it will not run until `resume` is called.
+/// A sleeping process forgets what it was waiting for,
+/// restarted syscalls will sleep again if needed once it is resumed.
+/// Returns true iff the process exists and is not a zombie.
+pub fn stop(pid: ID) -> bool {
+    unsafe {
+        let process = match get_process_as_mut(pid.as_usize()) {
+            Some(process) => process,
+            None => return false,
+        };
+        match process.state {
+            State::Zombie(_) => false,
+            State::Stopped => true,
+            _ => {
+                process.state = State::Stopped;
+                process.stop_pending = true;
+                wait_queue::wake_up(Resource::ChildExit(process.ppid));
+                true
+            }
+        }
+    }
+}
+
+/// Resumes a process stopped by `stop`.
+/// Returns true iff the process exists and is not a zombie.
+/// It stays stopped, and false is returned, if the run queues are full.
+pub fn resume(pid: ID) -> bool {
+    unsafe {
+        let process = match get_process_as_mut(pid.as_usize()) {
+            Some(process) => process,
+            None => return false,
+        };
+        match process.state {
+            State::Zombie(_) => false,
+            State::Stopped => {
+                process.state = State::Runnable;
+                process.stop_pending = false;
+                if IDLE.remove(&pid) && enqueue(pid).is_err() {
+                    get_process_as_mut(pid.as_usize()).unwrap().state = State::Stopped;
+                    IDLE.insert(pid);
+                    return false;
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Returns a stopped child of the current process that was not reported yet, `id` being 0 for any child.
+/// It is then considered as reported.
+pub fn listen_stopped(id: usize) -> Option<ID> {
+    unsafe {
+        let ppid = get_current().pid;
+        let child = ID_TABLE.values_mut().find(|process| {
+            process.ppid == ppid
+                && process.stop_pending
+                && (id == 0 || process.pid.as_usize() == id)
+        })?;
+        child.stop_pending = false;
+        Some(child.pid)
+    }
+}
+
+/// Kills the process `target` if the credentials of the current process allow it.
+/// Returns : 1 if it failed, 0 otherwise
 /// # Safety
-/// Need to add more security to prevent killing random processes
+/// TODO
 pub unsafe fn kill(target: usize) -> usize {
-    let target_process = &mut ID_TABLE[target];
+    let target_process = match get_process_as_mut(target) {
+        Some(process) if !process.kernel_thread => process,
+        _ => {
+            crate::warningln!("Kill of {} failed: no such process", target);
+            return 1;
+        }
+    };
     crate::warningln!("Target of Kill: {:?}", target_process.state);
-    if target_process.priority < ID_TABLE[CURRENT_PROCESS].priority {
+    if !get_current()
+        .credentials
+        .can_signal(&target_process.credentials)
+    {
         crate::warningln!("Kill of {} failed", target);
         1
     } else {
@@ -900,7 +1754,7 @@ pub unsafe fn kill(target: usize) -> usize {
 /// # Safety
 /// TODO
 pub unsafe fn write_to_stdout(message: String) {
-    if let Ok(res) = &mut ID_TABLE[CURRENT_PROCESS]
+    if let Ok(res) = &mut get_current_as_mut()
         .open_files
         .get_file_table(FileDescriptor::new(1))
     {
@@ -908,51 +1762,34 @@ pub unsafe fn write_to_stdout(message: String) {
     }
 }
 
-fn next_priority_to_run() -> usize {
-    let mut ticket = random::random_u8();
-    // debug!("Ticket = {:#b}", ticket);
-    // Look for the most significant non null bit in the ticket
-    let mut idx = 7;
-    while idx > 0 && ticket != 0 {
-        ticket <<= 1;
-        idx -= 1;
-    }
-    // debug!("final idx: {}",idx);
-    idx
-}
-
-const MAX_PRIO: usize = 8;
-static mut WAITING_QUEUES: [Queue<ID>; MAX_PRIO] = [
-    Queue::new(),
-    Queue::new(),
-    Queue::new(),
-    Queue::new(),
-    Queue::new(),
-    Queue::new(),
-    Queue::new(),
-    Queue::new(),
-];
-
+/// Processes that are not runnable for now (sleeping or stopped)
 static mut IDLE: BTreeSet<ID> = BTreeSet::new();
 
-/// Adds the given pid to the correct priority queue
-/// It tries to push it in the designated priority, but if it is full,
-/// it will promote the process until it finds room
-/// or there is no place left in priority 0, in which case it crashes.
-/// # Safety
-/// Requires WAITING_QUEUES to be sane
-fn enqueue_prio(pid: ID, prio: usize) {
+/// Adds the given process to the run queues of the least loaded processor.
+/// Returns an error if there is no room left for it, the caller has to keep track of it.
+fn enqueue(pid: ID) -> Result<(), ProcessError> {
     unsafe {
-        let mut effective_prio = prio;
-        while WAITING_QUEUES[effective_prio].is_full() && effective_prio > 0 {
-            effective_prio -= 1
+        match ID_TABLE.get(&pid) {
+            Some(process) if !super::enqueue(process) => Err(ProcessError::TooManyProcesses),
+            _ => Ok(()),
         }
-        if effective_prio == 0 && WAITING_QUEUES[effective_prio].is_full() {
-            panic!("Too many processes want to run at the same priority!")
+    }
+}
+
+/// Puts the process that was running back into the run queues of the current processor.
+/// Real-time processes are not in the run queues.
+/// Returns an error if there is no room left for it, the caller has to keep track of it.
+fn requeue(pid: ID) -> Result<(), ProcessError> {
+    if realtime::is_realtime(pid) {
+        return Ok(());
+    }
+    unsafe {
+        match ID_TABLE.get(&pid) {
+            Some(process) if !super::get_policy().enqueue(process) => {
+                Err(ProcessError::TooManyProcesses)
+            }
+            _ => Ok(()),
         }
-        WAITING_QUEUES[effective_prio]
-            .push(pid)
-            .expect("Scheduler massive fail");
     }
 }
 
@@ -964,46 +1801,97 @@ fn add_idle(pid: ID) {
     }
 }
 
-/// # Safety
-/// Needs sane `WAITING_QUEUES`. Should be safe to use.
-unsafe fn next_pid_to_run() -> ID {
-    let mut prio = next_priority_to_run();
-    // Find the lowest priority at least as urgent as the one indated by the ticket that is not empty
-    while prio < MAX_PRIO && WAITING_QUEUES[prio].is_empty() {
-        prio -= 1; // need to check priority
-    }
-    if prio >= MAX_PRIO {
-        prio = 0;
-        while prio < MAX_PRIO && WAITING_QUEUES[prio].is_empty() {
-            prio += 1;
+/// Accounts a timer tick to the current process, `in_kernel` tells whether the timer interrupted kernel code.
+/// Returns true iff the scheduling policy wants it to give the CPU to another process.
+pub fn tick(in_kernel: bool) -> bool {
+    unsafe {
+        let current = get_current_as_mut();
+        if is_idle(current.pid) {
+            stats::tick(None, in_kernel);
+            // Gives the CPU back as soon as a process was woken up
+            return true;
         }
-        if prio == MAX_PRIO {
-            return ID(CURRENT_PROCESS as u64);
+        stats::tick(Some(&mut current.stats), in_kernel);
+        // Killed or stopped by another processor
+        if matches!(current.state, State::Zombie(_) | State::Stopped) {
+            return true;
         }
+        let used = current.stats.user_ticks + current.stats.kernel_ticks;
+        if used >= current.limits.soft(Rlimit::CpuTicks) {
+            crate::warningln!("Process {} exceeded its CPU time limit", current.pid.0);
+            terminate(current, CPU_LIMIT_EXCEEDED as usize);
+            return true;
+        }
+        if realtime::is_realtime(current.pid) {
+            return realtime::tick(current.pid);
+        }
+        super::get_policy().tick(current) || realtime::has_ready()
     }
-    let old_pid = ID(CURRENT_PROCESS as u64);
-    let old_priority = ID_TABLE[old_pid.0 as usize].priority.0;
-    let old_state = ID_TABLE[old_pid.0 as usize].state;
-    // TODO : book-keeping to potentially empty IDLE
+}
 
-    let new_pid = WAITING_QUEUES[prio].pop().expect("Scheduler massive fail");
-    match old_state {
-        State::Runnable => enqueue_prio(old_pid, old_priority),
-        State::SlotAvailable | State::Zombie(_) => (),
-        State::Running => {
-            ID_TABLE[old_pid.0 as usize].state = State::Runnable;
-            enqueue_prio(old_pid, old_priority);
+/// Wakes up a process that was sleeping on `resource`: it leaves the `IDLE` collection and goes back into the queues.
+/// If it has not been switched out yet, the scheduler will enqueue it by itself.
+/// Returns false if the run queues are full, the process then keeps sleeping.
+pub fn wake(pid: ID, resource: Resource) -> bool {
+    unsafe {
+        let process = match get_process_as_mut(pid.as_usize()) {
+            Some(process) => process,
+            None => return true,
+        };
+        match process.state {
+            State::SleepInterruptible | State::SleepUninterruptible => {
+                let sleeping = process.state;
+                process.state = State::Runnable;
+                super::get_policy().woken_up(process, resource);
+                if IDLE.remove(&pid) && enqueue(pid).is_err() {
+                    get_process_as_mut(pid.as_usize()).unwrap().state = sleeping;
+                    IDLE.insert(pid);
+                    return false;
+                }
+                true
+            }
+            _ => true,
         }
-        State::SleepInterruptible | State::SleepUninterruptible | State::Stopped => {
-            add_idle(old_pid)
+    }
+}
+
+/// # Safety
+/// Needs a sane scheduling policy. Should be safe to use.
+unsafe fn next_pid_to_run() -> ID {
+    let old_pid = ID(current_pid() as u64);
+    if !is_idle(old_pid) {
+        let requeued = match get_current().state {
+            State::Runnable => requeue(old_pid),
+            State::Zombie(_) => Ok(()),
+            State::Running => {
+                get_current_as_mut().state = State::Runnable;
+                requeue(old_pid)
+            }
+            State::SleepInterruptible | State::SleepUninterruptible | State::Stopped => {
+                add_idle(old_pid);
+                Ok(())
+            }
+        };
+        // It can not be put anywhere, so it keeps the CPU
+        if requeued.is_err() {
+            return old_pid;
         }
     }
-    match ID_TABLE[new_pid.as_usize()].state {
-        State::Runnable | State::Running => new_pid,
-        State::SlotAvailable | State::Zombie(_) => next_pid_to_run(),
-        State::SleepInterruptible | State::SleepUninterruptible | State::Stopped => {
-            add_idle(new_pid);
-            next_pid_to_run()
+    loop {
+        let new_pid = match realtime::pick_next()
+            .or_else(|| super::get_policy().pick_next())
+            .or_else(super::steal)
+        {
+            Some(pid) => pid,
+            None => return idle_pid(smp::cpu_id()),
+        };
+        // A process that was reaped while it was still in the queues is simply skipped
+        match get_process(new_pid.as_usize()).map(|process| process.state) {
+            Some(State::Runnable) | Some(State::Running) => return new_pid,
+            None | Some(State::Zombie(_)) => (),
+            Some(State::SleepInterruptible)
+            | Some(State::SleepUninterruptible)
+            | Some(State::Stopped) => add_idle(new_pid),
         }
     }
 }
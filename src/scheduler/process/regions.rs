@@ -0,0 +1,304 @@
+//! Lazily backed regions of the address space of a process.
+//!
+//! The heap and the stack are only reserved when a program is loaded: `page_fault_handler` allocates
+//! and zeroes the frame of one of their pages the first time it is touched.
+//! The stack grows down until `Rlimit::StackPages` pages, with an unmapped guard page underneath,
+//! so that overflowing it is reported instead of silently running into other memory.
+
+use super::elf;
+use crate::memory::{self, cow, BootInfoAllocator};
+use alloc::vec::Vec;
+use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    Stack,
+    /// A shared-memory segment, mapped as a whole when it is attached, see `memory::shm`
+    Shared,
+    /// A file mapped by `mmap`, its pages being read from the file on first touch, see `memory::mmap`
+    File(u64),
+}
+
+/// A range of pages whose frames are allocated on first touch
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    /// First address of the region, page aligned
+    pub start: u64,
+    /// Address right after the last page
+    pub end: u64,
+    /// Flags of the pages once they are backed
+    pub flags: PageTableFlags,
+    pub kind: RegionKind,
+}
+
+impl Region {
+    pub fn contains(&self, address: u64) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    /// Number of pages reserved
+    pub fn pages(&self) -> u64 {
+        (self.end - self.start) / PAGE_SIZE
+    }
+
+    /// Lowest address of the region counting its guard page
+    fn guard_start(&self) -> u64 {
+        match self.kind {
+            RegionKind::Stack => self.start.saturating_sub(PAGE_SIZE),
+            RegionKind::Heap | RegionKind::Shared | RegionKind::File(_) => self.start,
+        }
+    }
+}
+
+/// Outcome of a page fault on a page that is not present
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The page was reserved and is now backed, the access can be retried
+    Backed,
+    /// The access hit the guard page under the stack
+    StackOverflow,
+    /// The page was reserved but no frame is left
+    OutOfMemory,
+    /// The page does not belong to any region
+    Unmapped,
+}
+
+/// Regions of an address space. There are only a few of them, so a vector is enough.
+#[derive(Clone, Debug, Default)]
+pub struct Regions(Vec<Region>);
+
+impl Regions {
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Reserves `pages` pages from `start`, which is rounded down to a page.
+    /// Returns false if it overlaps another region, a guard page included.
+    pub fn reserve(
+        &mut self,
+        start: u64,
+        pages: u64,
+        flags: PageTableFlags,
+        kind: RegionKind,
+    ) -> bool {
+        let start = start & !(PAGE_SIZE - 1);
+        let region = Region {
+            start,
+            end: start + pages * PAGE_SIZE,
+            flags,
+            kind,
+        };
+        if self
+            .0
+            .iter()
+            .any(|other| other.guard_start() < region.end && region.guard_start() < other.end)
+        {
+            return false;
+        }
+        self.0.push(region);
+        true
+    }
+
+    /// Returns the lowest address from `from` where `pages` pages fit between the other regions
+    pub fn find_free(&self, from: u64, pages: u64) -> u64 {
+        let mut start = from & !(PAGE_SIZE - 1);
+        while let Some(other) = self
+            .0
+            .iter()
+            .find(|other| other.guard_start() < start + pages * PAGE_SIZE && start < other.end)
+        {
+            start = other.end;
+        }
+        start
+    }
+
+    /// Forgets the region starting at `start` and returns it
+    pub fn remove_at(&mut self, start: u64) -> Option<Region> {
+        let index = self.0.iter().position(|region| region.start == start)?;
+        Some(self.0.remove(index))
+    }
+
+    /// Forgets the regions of kind `kind`, the pages that were backed stay mapped
+    pub fn remove(&mut self, kind: RegionKind) {
+        self.0.retain(|region| region.kind != kind);
+    }
+
+    pub fn get(&self, kind: RegionKind) -> Option<&Region> {
+        self.0.iter().find(|region| region.kind == kind)
+    }
+
+    /// Adds `pages` pages at the end of the region of kind `kind`.
+    /// Returns false if there is no such region or if it would overlap another one.
+    pub fn extend(&mut self, kind: RegionKind, pages: u64) -> bool {
+        let index = match self.0.iter().position(|region| region.kind == kind) {
+            Some(index) => index,
+            None => return false,
+        };
+        let end = self.0[index].end + pages * PAGE_SIZE;
+        let overlaps = self.0.iter().enumerate().any(|(other, region)| {
+            other != index && region.guard_start() < end && self.0[index].start < region.end
+        });
+        if overlaps {
+            return false;
+        }
+        self.0[index].end = end;
+        true
+    }
+
+    /// Returns the region containing `address`
+    pub fn find(&self, address: u64) -> Option<&Region> {
+        self.0.iter().find(|region| region.contains(address))
+    }
+
+    /// Returns true iff `address` is in the guard page under the stack
+    pub fn is_guard_page(&self, address: u64) -> bool {
+        self.get(RegionKind::Stack).map_or(false, |stack| {
+            stack.guard_start() <= address && address < stack.start
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.0.iter()
+    }
+}
+
+/// What a range of the address space is used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MappingKind {
+    Elf,
+    Heap,
+    Stack,
+    Args,
+    Shared,
+    File,
+}
+
+/// A range of the address space, as listed in `/proc/<pid>/maps`
+#[derive(Clone, Copy, Debug)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    pub flags: PageTableFlags,
+    pub kind: MappingKind,
+    /// Number of pages backed by a frame
+    pub resident: u64,
+}
+
+/// Flags that tell two ranges apart, the others being set by the processor
+fn mapping_flags(flags: PageTableFlags) -> PageTableFlags {
+    flags
+        & (PageTableFlags::WRITABLE
+            | PageTableFlags::NO_EXECUTE
+            | cow::COPY_ON_WRITE
+            | elf::MODIFY_WITH_EXEC
+            | elf::STACK
+            | elf::HEAP)
+}
+
+/// Returns the ranges of the address space `level_4` whose regions are `regions`, sorted by address.
+/// A region is a single range, even when only some of its pages are backed.
+/// The other pages are grouped when they are contiguous and have the same flags.
+/// # Safety
+/// `level_4` must be a valid level 4 table.
+pub unsafe fn mappings(regions: &Regions, level_4: PhysFrame, args_address: u64) -> Vec<Mapping> {
+    let pages = memory::mapped_pages(
+        level_4,
+        PageTableFlags::USER_ACCESSIBLE | PageTableFlags::PRESENT,
+    );
+    let mut mappings: Vec<Mapping> = regions
+        .iter()
+        .map(|region| Mapping {
+            start: region.start,
+            end: region.end,
+            flags: mapping_flags(region.flags),
+            kind: match region.kind {
+                RegionKind::Heap => MappingKind::Heap,
+                RegionKind::Stack => MappingKind::Stack,
+                RegionKind::Shared => MappingKind::Shared,
+                RegionKind::File(_) => MappingKind::File,
+            },
+            resident: pages
+                .iter()
+                .filter(|(page, _, _)| region.contains(page.as_u64()))
+                .count() as u64,
+        })
+        .collect();
+    let mut others: Vec<Mapping> = Vec::new();
+    for (page, _, flags) in pages {
+        let page = page.as_u64();
+        if regions.find(page).is_some() {
+            continue;
+        }
+        let flags = mapping_flags(flags);
+        let kind = if page == args_address {
+            MappingKind::Args
+        } else if flags.contains(elf::STACK) {
+            MappingKind::Stack
+        } else if flags.contains(elf::HEAP) {
+            MappingKind::Heap
+        } else {
+            MappingKind::Elf
+        };
+        match others.last_mut() {
+            Some(last) if last.end == page && last.flags == flags && last.kind == kind => {
+                last.end += PAGE_SIZE;
+                last.resident += 1;
+            }
+            _ => others.push(Mapping {
+                start: page,
+                end: page + PAGE_SIZE,
+                flags,
+                kind,
+                resident: 1,
+            }),
+        }
+    }
+    mappings.append(&mut others);
+    mappings.sort_by_key(|mapping| mapping.start);
+    mappings
+}
+
+/// Handles an access to the page of `address` that is not present in the address space `level_4`,
+/// whose regions are `regions`: a zeroed frame is mapped if it was reserved, or the page of the file it maps.
+/// # Safety
+/// `level_4` must be a valid level 4 table, running on no other processor.
+pub unsafe fn handle_fault(
+    regions: &Regions,
+    level_4: PhysFrame,
+    address: u64,
+    frame_allocator: &mut BootInfoAllocator,
+) -> Fault {
+    let region = match regions.find(address) {
+        // Its pages are all mapped when it is attached
+        Some(region) if region.kind == RegionKind::Shared => return Fault::Unmapped,
+        Some(region) => region,
+        None if regions.is_guard_page(address) => return Fault::StackOverflow,
+        None => return Fault::Unmapped,
+    };
+    let page = VirtAddr::new(address & !(PAGE_SIZE - 1));
+    if let RegionKind::File(id) = region.kind {
+        return match memory::mmap::pages(id) {
+            Some(pages) if (page.as_u64() - region.start) / PAGE_SIZE >= pages => Fault::Unmapped,
+            _ if memory::mmap::handle_fault(id, level_4, region.start, page, region.flags) => {
+                Fault::Backed
+            }
+            _ => Fault::OutOfMemory,
+        };
+    }
+    // Some pages are written to the swap first if the frames run low
+    memory::swap::balance();
+    if frame_allocator
+        .add_entry_to_table(level_4, page, region.flags, false)
+        .is_err()
+    {
+        return Fault::OutOfMemory;
+    }
+    match memory::write_into_virtual_memory(level_4, page, &[0_u8; PAGE_SIZE as usize]) {
+        Ok(()) => Fault::Backed,
+        Err(_) => Fault::OutOfMemory,
+    }
+}
@@ -0,0 +1,107 @@
+//! CPU usage accounting of the processes and of the whole system.
+//!
+//! Syscalls run with interrupts disabled, so the timer never fires while a process is in the kernel.
+//! Instead, the cycles spent in syscalls are measured with the time-stamp counter
+//! and converted into kernel ticks using the length of the last tick.
+
+use crate::smp::{self, MAX_CPUS};
+use core::arch::x86_64::_rdtsc;
+
+/// CPU usage of a single process
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuStats {
+    /// Ticks spent running the program itself
+    pub user_ticks: u64,
+    /// Ticks spent in syscalls or in the kernel on behalf of the process
+    pub kernel_ticks: u64,
+    /// Number of times the process was switched out
+    pub context_switches: u64,
+    pub page_faults: u64,
+    /// Tick at which the process was created
+    pub start_time: u64,
+    /// Cycles spent in syscalls that are not yet accounted as kernel ticks
+    syscall_cycles: u64,
+}
+
+impl CpuStats {
+    pub fn new(start_time: u64) -> Self {
+        Self {
+            start_time,
+            ..Self::default()
+        }
+    }
+
+    /// Accounts the cycles spent in a syscall.
+    pub fn add_syscall_cycles(&mut self, cycles: u64) {
+        self.syscall_cycles = self.syscall_cycles.saturating_add(cycles);
+    }
+
+    /// Accounts a tick during which the process was running.
+    /// `in_kernel` tells whether the timer interrupted kernel code.
+    fn tick(&mut self, in_kernel: bool, cycles_per_tick: u64) {
+        if in_kernel {
+            self.kernel_ticks += 1;
+        } else if cycles_per_tick != 0 && self.syscall_cycles >= cycles_per_tick {
+            self.syscall_cycles -= cycles_per_tick;
+            self.kernel_ticks += 1;
+        } else {
+            self.user_ticks += 1;
+        }
+    }
+}
+
+/// CPU usage of the whole system, in ticks
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemStats {
+    pub user_ticks: u64,
+    pub kernel_ticks: u64,
+    /// Ticks during which no process could run
+    pub idle_ticks: u64,
+}
+
+static mut SYSTEM_STATS: SystemStats = SystemStats {
+    user_ticks: 0,
+    kernel_ticks: 0,
+    idle_ticks: 0,
+};
+
+/// Time-stamp counter at the last timer interrupt of each processor
+static mut LAST_TICK_TSC: [u64; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Number of cycles between the two last timer interrupts of each processor
+static mut CYCLES_PER_TICK: [u64; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Returns the CPU usage of the whole system since boot
+pub fn get_system_stats() -> SystemStats {
+    unsafe { SYSTEM_STATS }
+}
+
+/// Reads the time-stamp counter
+pub fn cycles() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Accounts a timer tick. `current` is None if no process could run during this tick.
+/// Needs to be called at each timer interrupt.
+pub fn tick(current: Option<&mut CpuStats>, in_kernel: bool) {
+    unsafe {
+        let cpu = smp::cpu_id();
+        let now = cycles();
+        if LAST_TICK_TSC[cpu] != 0 {
+            CYCLES_PER_TICK[cpu] = now.wrapping_sub(LAST_TICK_TSC[cpu]);
+        }
+        LAST_TICK_TSC[cpu] = now;
+        match current {
+            None => SYSTEM_STATS.idle_ticks += 1,
+            Some(stats) => {
+                let kernel_ticks = stats.kernel_ticks;
+                stats.tick(in_kernel, CYCLES_PER_TICK[cpu]);
+                if stats.kernel_ticks != kernel_ticks {
+                    SYSTEM_STATS.kernel_ticks += 1;
+                } else {
+                    SYSTEM_STATS.user_ticks += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Real-time scheduling class, above the run queues of the scheduling policies.
+//!
+//! A real-time process reserves a `budget` of ticks in every `period` of ticks.
+//! The ready ones are scheduled by earliest deadline first, the deadline being the end of their current period,
+//! and always before the processes of the policies.
+//! A process that used up its budget is switched out until its next period, so a misbehaving one cannot
+//! lock up the machine. Admission control keeps the sum of the reservations under `MAX_UTILISATION`.
+
+use super::process::{self, State, ID};
+use super::wait_queue;
+use alloc::collections::BTreeMap;
+
+/// Share of a single processor, in parts per million, that the real-time processes can reserve.
+/// The rest is kept for the other processes.
+pub const MAX_UTILISATION: u64 = 800_000;
+
+/// Shortest period that can be reserved, in ticks
+pub const MIN_PERIOD: u64 = 2;
+
+#[derive(Debug)]
+pub enum RealtimeError {
+    /// The budget is null or longer than the period, or the period is too short
+    InvalidParameters,
+    /// Accepting the reservation would overload the processor
+    Overloaded,
+}
+
+/// Reservation of a real-time process
+#[derive(Clone, Copy, Debug)]
+pub struct Reservation {
+    /// Length of a period, in ticks
+    pub period: u64,
+    /// Ticks the process may run in each period
+    pub budget: u64,
+    /// Ticks left in the current period
+    pub remaining: u64,
+    /// Tick at which the current period ends
+    pub deadline: u64,
+}
+
+impl Reservation {
+    /// Share of a processor reserved, in parts per million, rounded up
+    fn utilisation(&self) -> u64 {
+        (self.budget * 1_000_000 + self.period - 1) / self.period
+    }
+
+    /// Starts a new period if the current one is over
+    fn replenish(&mut self, now: u64) {
+        if now >= self.deadline {
+            let periods = (now - self.deadline) / self.period + 1;
+            self.deadline += periods * self.period;
+            self.remaining = self.budget;
+        }
+    }
+}
+
+/// Reservations of the real-time processes
+static mut RESERVATIONS: BTreeMap<ID, Reservation> = BTreeMap::new();
+
+/// Puts `pid` into the real-time class, or updates its reservation.
+/// A null `period` puts it back into the normal class.
+pub fn set(pid: ID, period: u64, budget: u64) -> Result<(), RealtimeError> {
+    if period == 0 {
+        remove(pid);
+        return Ok(());
+    }
+    if period < MIN_PERIOD || budget == 0 || budget > period {
+        return Err(RealtimeError::InvalidParameters);
+    }
+    let now = wait_queue::get_ticks();
+    let reservation = Reservation {
+        period,
+        budget,
+        remaining: budget,
+        deadline: now + period,
+    };
+    unsafe {
+        let reserved: u64 = RESERVATIONS
+            .iter()
+            .filter(|(other, _)| **other != pid)
+            .map(|(_, reservation)| reservation.utilisation())
+            .sum();
+        if reserved + reservation.utilisation() > MAX_UTILISATION {
+            return Err(RealtimeError::Overloaded);
+        }
+        RESERVATIONS.insert(pid, reservation);
+    }
+    Ok(())
+}
+
+/// Removes `pid` from the real-time class, used when it leaves it or dies
+pub fn remove(pid: ID) {
+    unsafe {
+        RESERVATIONS.remove(&pid);
+    }
+}
+
+/// Returns the reservation of `pid`, or None if it is in the normal class
+pub fn get(pid: ID) -> Option<Reservation> {
+    unsafe { RESERVATIONS.get(&pid).copied() }
+}
+
+pub fn is_realtime(pid: ID) -> bool {
+    unsafe { RESERVATIONS.contains_key(&pid) }
+}
+
+/// Returns the ready real-time process with the earliest deadline, and its deadline.
+/// A process is ready if it is runnable, has some budget left and is not running on a processor.
+fn earliest_ready() -> Option<(ID, u64)> {
+    let now = wait_queue::get_ticks();
+    unsafe {
+        RESERVATIONS
+            .iter_mut()
+            .filter_map(|(pid, reservation)| {
+                reservation.replenish(now);
+                let runnable = matches!(
+                    process::get_process(pid.as_usize()).map(|process| process.state),
+                    Some(State::Runnable) | Some(State::Running)
+                );
+                if runnable && reservation.remaining > 0 && !process::is_running(*pid) {
+                    Some((*pid, reservation.deadline))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|(_, deadline)| *deadline)
+    }
+}
+
+/// Returns the real-time process that has to run next, if any.
+/// It stays in the class, there is no queue to remove it from.
+pub fn pick_next() -> Option<ID> {
+    earliest_ready().map(|(pid, _)| pid)
+}
+
+/// Returns true iff a real-time process is waiting for a processor
+pub fn has_ready() -> bool {
+    earliest_ready().is_some()
+}
+
+/// Charges a timer tick to the real-time process `pid`.
+/// Returns true iff it has to give the CPU back: its budget is used up,
+/// or another real-time process has an earlier deadline.
+pub fn tick(pid: ID) -> bool {
+    let now = wait_queue::get_ticks();
+    let deadline = unsafe {
+        match RESERVATIONS.get_mut(&pid) {
+            Some(reservation) => {
+                reservation.replenish(now);
+                reservation.remaining = reservation.remaining.saturating_sub(1);
+                if reservation.remaining == 0 {
+                    return true;
+                }
+                reservation.deadline
+            }
+            None => return false,
+        }
+    };
+    matches!(earliest_ready(), Some((_, other)) if other < deadline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ids no process uses
+    const FIRST: u64 = 1 << 40;
+    const SECOND: u64 = FIRST + 1;
+
+    #[test_case]
+    fn admission_keeps_the_utilisation_under_the_limit() {
+        let (first, second) = (ID::forge(FIRST), ID::forge(SECOND));
+        assert!(set(first, 10, 5).is_ok());
+        assert!(matches!(set(second, 10, 4), Err(RealtimeError::Overloaded)));
+        assert!(!is_realtime(second));
+        assert!(set(second, 10, 3).is_ok());
+        // A reservation replaces the previous one of the same process
+        assert!(set(first, 10, 5).is_ok());
+        assert!(matches!(set(first, 10, 6), Err(RealtimeError::Overloaded)));
+        assert_eq!(get(first).map(|reservation| reservation.budget), Some(5));
+        // Leaving the class frees its share
+        assert!(set(second, 0, 0).is_ok());
+        assert!(set(first, 10, 8).is_ok());
+        remove(first);
+    }
+
+    #[test_case]
+    fn invalid_reservations_are_refused() {
+        let pid = ID::forge(FIRST);
+        assert!(matches!(
+            set(pid, 1, 1),
+            Err(RealtimeError::InvalidParameters)
+        ));
+        assert!(matches!(
+            set(pid, 10, 0),
+            Err(RealtimeError::InvalidParameters)
+        ));
+        assert!(matches!(
+            set(pid, 10, 11),
+            Err(RealtimeError::InvalidParameters)
+        ));
+        assert!(!is_realtime(pid));
+    }
+}
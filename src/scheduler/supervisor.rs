@@ -0,0 +1,138 @@
+//! The supervisor, a kernel thread doing the work of an init.
+//!
+//! There is no init program on disk: the first process is the program at `FIRST_PROGRAM_PATH`,
+//! which only gets the orphans as their parent. The supervisor reaps them as soon as they die.
+//! It also launches the services listed in `INITTAB_PATH`, and launches them again when they exit.
+//! Each line of the table is `<action>:<program> [arguments]`, where `action` is `once` or `respawn`.
+//! Empty lines and lines starting with `#` are ignored.
+
+use super::kthread;
+use super::process::{self, ID};
+use crate::data_storage::path::Path;
+use crate::filesystem;
+use crate::smp::lock::with_kernel_lock;
+use crate::{errorln, warningln};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Program of the first process, the clock at the top of the screen, which runs until the shutdown.
+/// The shell is a service of `INITTAB_PATH`, so that it comes back when it exits.
+pub const FIRST_PROGRAM_PATH: &str = "/usr/clock";
+
+/// Services launched by the supervisor
+pub const INITTAB_PATH: &str = "/usr/etc/inittab";
+
+pub const SUPERVISOR_PRIORITY: usize = 1;
+
+/// Ticks between two rounds of the supervisor
+const SUPERVISOR_PERIOD: u64 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    /// Launched at boot only
+    Once,
+    /// Launched again each time it exits
+    Respawn,
+}
+
+#[derive(Debug)]
+struct Service {
+    action: Action,
+    /// The program, followed by its arguments
+    args: Vec<String>,
+    /// Pid of the running instance, if any
+    pid: Option<ID>,
+    /// True iff it was launched at least once
+    started: bool,
+}
+
+/// Returns the code of the first process: the program at `FIRST_PROGRAM_PATH`, or the embedded launcher if there is none.
+pub fn first_program() -> Vec<u8> {
+    match filesystem::read_program_from_path(Path::from(FIRST_PROGRAM_PATH)) {
+        Ok(code) if !code.is_empty() => code,
+        _ => {
+            warningln!("No {}, using the embedded launcher", FIRST_PROGRAM_PATH);
+            Vec::from(crate::FIRST_PROGRAM)
+        }
+    }
+}
+
+fn parse_inittab(table: &str) -> Vec<Service> {
+    let mut services = Vec::new();
+    for line in table.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (action, command) = match line.find(':') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => {
+                errorln!("Invalid line in {} : {}", INITTAB_PATH, line);
+                continue;
+            }
+        };
+        let action = match action.trim() {
+            "once" => Action::Once,
+            "respawn" => Action::Respawn,
+            _ => {
+                errorln!("Unknown action in {} : {}", INITTAB_PATH, action);
+                continue;
+            }
+        };
+        let args: Vec<String> = command.split_whitespace().map(String::from).collect();
+        if args.is_empty() {
+            errorln!("No program in {} : {}", INITTAB_PATH, line);
+            continue;
+        }
+        services.push(Service {
+            action,
+            args,
+            pid: None,
+            started: false,
+        });
+    }
+    services
+}
+
+fn read_inittab() -> Vec<Service> {
+    match filesystem::read_file_from_path(Path::from(INITTAB_PATH)) {
+        Ok(table) => parse_inittab(&String::from_utf8_lossy(&table)),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Reaps the services that exited and launches the ones that need to run.
+/// # Safety
+/// Needs to be called with interrupts disabled and the kernel lock held, from the supervisor.
+unsafe fn supervise(services: &mut [Service]) {
+    let supervisor = process::get_current().get_pid();
+    for service in services.iter_mut() {
+        if let Some(pid) = service.pid {
+            let (reaped, return_value) = process::listen(pid.as_usize());
+            if reaped == 0 {
+                continue;
+            }
+            warningln!("Service {} exited with {}", service.args[0], return_value);
+            service.pid = None;
+        }
+        if service.started && service.action == Action::Once {
+            continue;
+        }
+        service.started = true;
+        match process::spawn_program(&service.args[0], &service.args, supervisor) {
+            Ok(pid) => service.pid = Some(pid),
+            Err(error) => errorln!("Could not launch {} : {:?}", service.args[0], error),
+        }
+    }
+}
+
+/// Kernel thread reaping the orphans and supervising the services
+pub fn supervisor() -> ! {
+    let mut services = with_kernel_lock(read_inittab);
+    loop {
+        with_kernel_lock(|| unsafe {
+            process::reap_adopted();
+            supervise(&mut services);
+        });
+        kthread::sleep(SUPERVISOR_PERIOD);
+    }
+}
@@ -0,0 +1,118 @@
+//! Wait-queues on which processes sleep until the resource they need becomes available.
+//!
+//! A driver that cannot serve a request registers the current process on the queue
+//! of the matching `Resource` and returns `IoError::Sleep`. Whoever produces data for
+//! this resource (an interrupt handler, the writer of a fifo, ...) then calls `wake_up`.
+//!
+//! Syscalls that sleep are restarted when the process is resumed, so a spurious
+//! wake-up only means the process goes back to sleep.
+
+use super::process::{self, State, ID};
+use alloc::collections::{BTreeMap, VecDeque};
+
+/// Every resource a process can sleep on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Resource {
+    /// A new scancode was received from the keyboard
+    Keyboard,
+    /// Data was written into (or the last writer closed) the fifo of the given id
+    Fifo(usize),
+    /// A child of the given process exited
+    ChildExit(ID),
+    /// The timer reached the given tick
+    Timer(u64),
+}
+
+/// Sleeping processes, sorted by the resource they wait for
+static mut WAIT_QUEUES: BTreeMap<Resource, VecDeque<ID>> = BTreeMap::new();
+
+/// Number of timer interrupts since boot
+static mut TICKS: u64 = 0;
+
+/// Returns the number of timer interrupts since boot
+pub fn get_ticks() -> u64 {
+    unsafe { TICKS }
+}
+
+/// Puts the current process to sleep on `resource`.
+/// # Safety
+/// The caller must give the CPU to another process right after,
+/// see `syscall_0_read` for an example.
+pub unsafe fn sleep_on(resource: Resource, interruptible: bool) {
+    let current = process::get_current_as_mut();
+    current.state = if interruptible {
+        State::SleepInterruptible
+    } else {
+        State::SleepUninterruptible
+    };
+    let pid = current.get_pid();
+    WAIT_QUEUES
+        .entry(resource)
+        .or_insert_with(VecDeque::new)
+        .push_back(pid);
+}
+
+/// Puts the current process to sleep until the timer reaches `ticks` more interrupts.
+/// # Safety
+/// Same as `sleep_on`
+pub unsafe fn sleep_for(ticks: u64) {
+    sleep_on(Resource::Timer(TICKS.saturating_add(ticks)), true)
+}
+
+/// Wakes up every process sleeping on `resource`.
+/// The processes that the run queues can not take stay on the queue, the ones sleeping on the timer
+/// until the next tick. Returns the number of processes woken up.
+pub fn wake_up(resource: Resource) -> usize {
+    unsafe {
+        match WAIT_QUEUES.remove(&resource) {
+            None => 0,
+            Some(mut queue) => {
+                let number = queue.len();
+                queue.retain(|pid| !process::wake(*pid, resource));
+                let woken = number - queue.len();
+                if !queue.is_empty() {
+                    let retry = match resource {
+                        Resource::Timer(_) => Resource::Timer(TICKS + 1),
+                        _ => resource,
+                    };
+                    WAIT_QUEUES
+                        .entry(retry)
+                        .or_insert_with(VecDeque::new)
+                        .append(&mut queue);
+                }
+                woken
+            }
+        }
+    }
+}
+
+/// Updates the timer and wakes up every process whose deadline is over.
+/// Needs to be called at each timer interrupt.
+pub fn tick() {
+    unsafe {
+        TICKS += 1;
+        // Nothing is allocated in the interrupt, the expired queues are taken one by one
+        while let Some((&resource, _)) = WAIT_QUEUES
+            .range(Resource::Timer(0)..=Resource::Timer(TICKS))
+            .next()
+        {
+            wake_up(resource);
+        }
+    }
+}
+
+/// Removes `pid` from every queue, for a process that died.
+/// # Safety
+/// Needs to be called with interrupts disabled.
+pub unsafe fn forget(pid: ID) {
+    for queue in WAIT_QUEUES.values_mut() {
+        queue.retain(|sleeper| *sleeper != pid);
+    }
+    while let Some(resource) = WAIT_QUEUES
+        .iter()
+        .find(|(_, queue)| queue.is_empty())
+        .map(|(resource, _)| *resource)
+    {
+        WAIT_QUEUES.remove(&resource);
+    }
+}
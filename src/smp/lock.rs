@@ -0,0 +1,94 @@
+//! Big kernel lock.
+//!
+//! The kernel was written for a single processor: its shared structures (`ID_TABLE`, the `VFS`,
+//! the `GLOBAL_FILE_TABLE`, the `FRAME_ALLOCATOR`, the run queues...) are `static mut`s,
+//! only protected by running with interrupts disabled. With several processors, every entry into the kernel
+//! (interrupts, exceptions and syscalls) also takes this lock, so only one processor runs kernel code at a time.
+//!
+//! The lock is recursive, so that a fault raised while holding it does not deadlock.
+//! Leaving the kernel towards another context (see `leave_context_cr3`) releases it whatever its depth.
+
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Value of `OWNER` when the lock is free
+const NO_OWNER: usize = usize::MAX;
+
+/// Processor holding the lock
+static OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+
+/// Number of times the owner took the lock, only accessed by the owner
+static mut DEPTH: usize = 0;
+
+/// Releases the lock once dropped
+pub struct KernelLockGuard(());
+
+impl Drop for KernelLockGuard {
+    fn drop(&mut self) {
+        release();
+    }
+}
+
+/// Takes the lock, spinning until the other processors release it.
+/// It has to be called with interrupts disabled.
+pub fn acquire() -> KernelLockGuard {
+    let cpu = super::cpu_id();
+    if OWNER.load(Ordering::Acquire) != cpu {
+        while OWNER
+            .compare_exchange_weak(NO_OWNER, cpu, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+    }
+    unsafe {
+        DEPTH += 1;
+    }
+    KernelLockGuard(())
+}
+
+/// Releases the lock once. Should only be used through `KernelLockGuard`.
+fn release() {
+    unsafe {
+        if OWNER.load(Ordering::Relaxed) != super::cpu_id() {
+            return;
+        }
+        DEPTH -= 1;
+        if DEPTH == 0 {
+            OWNER.store(NO_OWNER, Ordering::Release);
+        }
+    }
+}
+
+/// Releases the lock if the current processor holds it, whatever the depth.
+/// Used when leaving the kernel without going back through the callers that took it.
+pub extern "C" fn force_release() {
+    unsafe {
+        if OWNER.load(Ordering::Relaxed) == super::cpu_id() {
+            DEPTH = 0;
+            OWNER.store(NO_OWNER, Ordering::Release);
+        }
+    }
+}
+
+/// Entry point used by `saveRegisters!`, the matching `release` is done by `exit_kernel`
+pub extern "C" fn enter_kernel() {
+    core::mem::forget(acquire());
+}
+
+/// Exit point used by `saveRegisters!`
+pub extern "C" fn exit_kernel() {
+    release();
+}
+
+/// Runs `f` with interrupts disabled and the lock held.
+/// Kernel threads use it to touch the shared structures.
+pub fn with_kernel_lock<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let _guard = acquire();
+        f()
+    })
+}
@@ -0,0 +1,256 @@
+//! Support of several processors.
+//!
+//! The processors are found in the MADT of the ACPI tables, and the application processors (APs)
+//! are started by the bootstrap processor with the INIT-SIPI-SIPI sequence.
+//! Each processor has its own GDT and TSS, idle task, current process and run queues.
+//! The kernel itself is protected by a big lock, see `lock`.
+
+use crate::gdt;
+use crate::hardware::{acpi, apic, timer};
+use crate::interrupts;
+use crate::memory::{BootInfoAllocator, PHYSICAL_OFFSET};
+use crate::scheduler::{idle, kthread, process};
+use crate::warningln;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::{Cr0, Cr3, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+pub mod lock;
+mod trampoline;
+
+/// Maximal number of processors used
+pub const MAX_CPUS: usize = 8;
+
+const EFER_MSR: u32 = 0xC000_0080;
+/// Long mode active, set by the processor itself
+const EFER_LMA: u64 = 1 << 10;
+
+/// Delays of the startup sequence, in cycles of the PIT
+const INIT_DELAY: u16 = 11_932; // 10 ms
+const STARTUP_DELAY: u16 = 239; // 200 µs
+/// Number of `STARTUP_DELAY` to wait for a processor before giving up on it
+const STARTUP_TIMEOUT: usize = 500;
+
+/// Local APIC id of each processor, indexed by the number the kernel gives them
+static mut APIC_IDS: [u8; MAX_CPUS] = [0; MAX_CPUS];
+
+/// Number of processors having an entry in `APIC_IDS`
+static mut CPU_COUNT: usize = 1;
+
+/// Number of processors running the scheduler
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/// State of the bootstrap processor copied by the application processors
+static mut KERNEL_CR3: u64 = 0;
+static mut BSP_CR0: u64 = 0;
+static mut BSP_CR4: u64 = 0;
+static mut BSP_XCR0: u64 = 0;
+
+/// Returns the number of the current processor, 0 being the bootstrap one
+pub fn cpu_id() -> usize {
+    let apic_id = apic::id();
+    unsafe {
+        APIC_IDS[..CPU_COUNT]
+            .iter()
+            .position(|id| *id == apic_id)
+            .unwrap_or(0)
+    }
+}
+
+/// Returns the number of processors running the scheduler
+pub fn cpu_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Asks the processor `cpu` to pick a new process, used when it is idle and some work was given to it.
+pub fn reschedule(cpu: usize) {
+    if cpu != cpu_id() && cpu < cpu_count() {
+        unsafe {
+            apic::send_ipi(APIC_IDS[cpu], interrupts::RESCHEDULE_VECTOR);
+        }
+    }
+}
+
+/// Creates a copy of the kernel's page table where the trampoline is identity mapped.
+/// Returns None if the lower half of the kernel's table is used around the trampoline.
+unsafe fn trampoline_table(frame_allocator: &mut BootInfoAllocator) -> Option<PhysFrame> {
+    let table = frame_allocator.allocate_level_4_frame().ok()?;
+    let level_4: &mut PageTable =
+        &mut *((table.start_address().as_u64() + PHYSICAL_OFFSET) as *mut PageTable);
+    if !level_4[0].is_unused() {
+        frame_allocator.deallocate_4k_frame(table.start_address());
+        return None;
+    }
+    let mut mapper = OffsetPageTable::new(level_4, VirtAddr::new(PHYSICAL_OFFSET));
+    let frame =
+        PhysFrame::<Size4KiB>::containing_address(PhysAddr::new(trampoline::TRAMPOLINE_ADDRESS));
+    match mapper.identity_map(
+        frame,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        frame_allocator,
+    ) {
+        Ok(flush) => flush.ignore(),
+        Err(_) => {
+            free_trampoline_table(table, frame_allocator);
+            return None;
+        }
+    }
+    Some(table)
+}
+
+/// Frees the page table created by `trampoline_table`, with the tables of its identity mapping.
+unsafe fn free_trampoline_table(table: PhysFrame, frame_allocator: &mut BootInfoAllocator) {
+    let mut frame = table.start_address();
+    let mut frames = [frame; 4];
+    let mut depth = 1;
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(trampoline::TRAMPOLINE_ADDRESS));
+    let indexes = [page.p4_index(), page.p3_index(), page.p2_index()];
+    for index in indexes.iter() {
+        let level: &PageTable = &*((frame.as_u64() + PHYSICAL_OFFSET) as *const PageTable);
+        if level[*index].is_unused() {
+            break;
+        }
+        frame = level[*index].addr();
+        frames[depth] = frame;
+        depth += 1;
+    }
+    for frame in frames[..depth].iter() {
+        frame_allocator.deallocate_4k_frame(*frame);
+    }
+}
+
+/// Starts the application processor of local APIC `apic_id`, it becomes the processor number `cpu`.
+/// Returns true iff it came online.
+unsafe fn start(apic_id: u8, cpu: usize) -> bool {
+    let stack = match kthread::allocate_stack() {
+        Some(top) => top,
+        None => return false,
+    };
+    APIC_IDS[cpu] = apic_id;
+    CPU_COUNT = cpu + 1;
+    trampoline::prepare(stack, cpu);
+
+    apic::send_init(apic_id);
+    timer::wait_cycles(INIT_DELAY);
+    for _ in 0..2 {
+        if cpu_count() > cpu {
+            break;
+        }
+        apic::send_startup(apic_id, (trampoline::TRAMPOLINE_ADDRESS >> 12) as u8);
+        timer::wait_cycles(STARTUP_DELAY);
+    }
+    for _ in 0..STARTUP_TIMEOUT {
+        if cpu_count() > cpu {
+            return true;
+        }
+        timer::wait_cycles(STARTUP_DELAY);
+    }
+    CPU_COUNT = cpu;
+    false
+}
+
+/// Starts every application processor listed in the ACPI tables.
+/// Returns the number of processors running.
+/// # Safety
+/// Needs to be called once with interrupts disabled, by the bootstrap processor after the initialisation
+/// of the local APIC, of the scheduler and of its idle task.
+pub unsafe fn init(frame_allocator: &mut BootInfoAllocator) -> usize {
+    if !apic::is_enabled() {
+        return 1;
+    }
+    APIC_IDS[0] = apic::id();
+    let processors = match acpi::processors() {
+        Some(processors) => processors,
+        None => {
+            warningln!("No ACPI tables, running on a single processor");
+            return 1;
+        }
+    };
+    if processors.len() <= 1 {
+        return 1;
+    }
+    let table = match trampoline_table(frame_allocator) {
+        Some(table) => table,
+        None => {
+            warningln!("Could not map the trampoline, running on a single processor");
+            return 1;
+        }
+    };
+
+    let (cr3, cr3_flags) = Cr3::read();
+    KERNEL_CR3 = cr3.start_address().as_u64() | cr3_flags.bits();
+    BSP_CR0 = Cr0::read_raw();
+    BSP_CR4 = Cr4::read_raw();
+    if Cr4::read().contains(Cr4Flags::OSXSAVE) {
+        BSP_XCR0 = xgetbv();
+    }
+    trampoline::install(
+        table.start_address().as_u64(),
+        Msr::new(EFER_MSR).read() & !EFER_LMA,
+        ap_entry,
+    );
+
+    for processor in processors.iter() {
+        if processor.apic_id == APIC_IDS[0] {
+            continue;
+        }
+        let cpu = cpu_count();
+        if cpu == MAX_CPUS {
+            warningln!("Only {} processors are used", MAX_CPUS);
+            break;
+        }
+        if !start(processor.apic_id, cpu) {
+            warningln!("Processor {} did not start", processor.apic_id);
+        }
+    }
+    // The processors already started may use the frame allocator
+    let _lock = lock::acquire();
+    free_trampoline_table(table, frame_allocator);
+    cpu_count()
+}
+
+unsafe fn xgetbv() -> u64 {
+    let low: u32;
+    let high: u32;
+    asm!("xgetbv", in("ecx") 0, out("eax") low, out("edx") high);
+    (high as u64) << 32 | low as u64
+}
+
+unsafe fn xsetbv(value: u64) {
+    asm!("xsetbv", in("ecx") 0, in("eax") value as u32, in("edx") (value >> 32) as u32);
+}
+
+/// Entry point of the application processors, called by the trampoline in long mode.
+extern "C" fn ap_entry(cpu: u64) -> ! {
+    unsafe {
+        // Enables the caches, and the write protection needed by the copy-on-write
+        asm!("mov cr0, {}", in(reg) BSP_CR0);
+        asm!("mov cr4, {}", in(reg) BSP_CR4);
+        if BSP_XCR0 != 0 {
+            xsetbv(BSP_XCR0);
+        }
+        asm!("mov cr3, {}", in(reg) KERNEL_CR3);
+        gdt::init_ap(cpu as usize);
+        interrupts::init_ap();
+        apic::init_ap();
+
+        core::mem::forget(lock::acquire());
+        if !idle::spawn_ap(cpu as usize) {
+            lock::force_release();
+            // Stays offline, `start` gives up on it
+            crate::halt_loop();
+        }
+        ONLINE.fetch_add(1, Ordering::Release);
+        // Releases the lock
+        process::enter_idle()
+    }
+}
+
+/// Returns true iff the frame can be given by the frame allocator: the trampoline is kept for the processors.
+pub fn is_reserved_frame(address: u64) -> bool {
+    address == trampoline::TRAMPOLINE_ADDRESS
+}
@@ -0,0 +1,117 @@
+//! Code run by the application processors when they wake up, in real mode.
+//!
+//! It is copied at `TRAMPOLINE_ADDRESS`, which is identity mapped by the page table given to it.
+//! It goes directly from real mode to long mode, then calls `entry(cpu)` on the stack given by the bootstrap processor.
+
+use crate::memory::PHYSICAL_OFFSET;
+
+/// Physical address of the trampoline. The startup IPI takes its page number.
+/// It has to match the address hard-coded in the assembly below.
+pub const TRAMPOLINE_ADDRESS: u64 = 0x8000;
+
+global_asm!(
+    ".section .text.ap_trampoline, \"ax\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "lgdt [ap_trampoline_gdtr - ap_trampoline_start]",
+    // PAE and PGE, the rest of CR4 is set in long mode
+    "mov eax, 0xA0",
+    "mov cr4, eax",
+    "mov eax, dword ptr [ap_trampoline_cr3 - ap_trampoline_start]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080",
+    "mov eax, dword ptr [ap_trampoline_efer - ap_trampoline_start]",
+    "xor edx, edx",
+    "wrmsr",
+    // Paging and protection at once
+    "mov eax, cr0",
+    "or eax, 0x80000001",
+    "mov cr0, eax",
+    // Far jump to the 64 bits code segment
+    ".byte 0x66, 0xEA",
+    ".long 0x8000 + ap_trampoline_long_mode - ap_trampoline_start",
+    ".word 0x08",
+    ".code64",
+    "ap_trampoline_long_mode:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "xor ax, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov rsp, qword ptr [0x8000 + ap_trampoline_stack - ap_trampoline_start]",
+    "mov rdi, qword ptr [0x8000 + ap_trampoline_cpu - ap_trampoline_start]",
+    "mov rax, qword ptr [0x8000 + ap_trampoline_entry - ap_trampoline_start]",
+    "call rax",
+    "2:",
+    "hlt",
+    "jmp 2b",
+    ".align 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",
+    ".quad 0x00AF9A000000FFFF",
+    ".quad 0x00CF92000000FFFF",
+    "ap_trampoline_gdtr:",
+    ".word 23",
+    ".long 0x8000 + ap_trampoline_gdt - ap_trampoline_start",
+    ".align 8",
+    "ap_trampoline_cr3:",
+    ".quad 0",
+    "ap_trampoline_efer:",
+    ".quad 0",
+    "ap_trampoline_stack:",
+    ".quad 0",
+    "ap_trampoline_entry:",
+    ".quad 0",
+    "ap_trampoline_cpu:",
+    ".quad 0",
+    "ap_trampoline_end:",
+    ".text",
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_efer: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_cpu: u8;
+}
+
+/// Returns the physical address where the field `symbol` of the trampoline is copied
+unsafe fn field(symbol: &u8) -> *mut u64 {
+    let offset = symbol as *const u8 as u64 - &ap_trampoline_start as *const u8 as u64;
+    (TRAMPOLINE_ADDRESS + offset + PHYSICAL_OFFSET) as *mut u64
+}
+
+/// Copies the trampoline at `TRAMPOLINE_ADDRESS`, with the page table and the EFER it has to load.
+/// # Safety
+/// The frame at `TRAMPOLINE_ADDRESS` must not be used by anything else
+pub unsafe fn install(cr3: u64, efer: u64, entry: extern "C" fn(u64) -> !) {
+    let start = &ap_trampoline_start as *const u8;
+    let length = &ap_trampoline_end as *const u8 as usize - start as usize;
+    core::ptr::copy_nonoverlapping(
+        start,
+        (TRAMPOLINE_ADDRESS + PHYSICAL_OFFSET) as *mut u8,
+        length,
+    );
+    field(&ap_trampoline_cr3).write_volatile(cr3);
+    field(&ap_trampoline_efer).write_volatile(efer);
+    field(&ap_trampoline_entry).write_volatile(entry as u64);
+}
+
+/// Sets the stack and the index of the next processor to start
+/// # Safety
+/// The trampoline must have been installed, and no processor must be running it
+pub unsafe fn prepare(stack_top: u64, cpu: usize) {
+    field(&ap_trampoline_stack).write_volatile(stack_top);
+    field(&ap_trampoline_cpu).write_volatile(cpu as u64);
+}
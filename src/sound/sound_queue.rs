@@ -10,20 +10,10 @@ use crate::println;
 /// Max number of sounds in a session.
 const MAX_SOUND: u64 = 8192;
 
-/// Current sound tick. Incremented at each clock tick.
-/// TODO add guard to avoid overflowing this variable!
-static mut TICK: u64 = 0;
-
-/// Get current tick.
+/// Get current tick, the one of the timer, so that the sounds keep their length
+/// even if the queue is not handled at each tick.
 fn get_tick() -> u64 {
-    unsafe { TICK }
-}
-
-/// Never call this function without a very good reason
-fn incr_tick() {
-    unsafe {
-        TICK += 1;
-    }
+    crate::scheduler::wait_queue::get_ticks()
 }
 
 /// A basic ID structure
@@ -105,7 +95,6 @@ impl SoundQueue {
     }
     /// Updates the sound
     pub fn handle(&mut self) {
-        incr_tick();
         if let Some(sound) = self.1 {
             if sound.begin + sound.length > get_tick() {
                 // Meaning the sound is still playing